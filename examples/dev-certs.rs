@@ -0,0 +1,35 @@
+//! This example demonstrates serving development certificates signed by a
+//! local certificate authority, which is created on the first run and re-used
+//! afterwards.
+//!
+//! After trusting the printed certificate authority, you can visit
+//! <https://localhost:3000> without certificate errors, even after restarting
+//! the example. <http://localhost:3000> is redirected to HTTPS.
+
+#![allow(clippy::print_stdout)]
+
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use axum::{routing, Router};
+use axum_server_dual_protocol::{DevCerts, ServerExt};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+	let app = Router::new().route("/", routing::get(|| async { "Hello, world!" }));
+
+	let address = SocketAddr::from(([127, 0, 0, 1], 3000));
+	println!("Listening on {address}.");
+
+	let config = DevCerts::new(["localhost", "127.0.0.1", "::1"])
+		.directory("target/dev-certs")
+		.rustls_config()
+		.await?;
+
+	axum_server_dual_protocol::bind_dual_protocol(address, config)
+		.set_upgrade(true)
+		.serve(app.into_make_service())
+		.await?;
+
+	Ok(())
+}
@@ -0,0 +1,1210 @@
+//! ACME certificate management.
+//!
+//! See [`Acme`] and [`AcmeConfig`].
+
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aws_lc_rs::digest::{self, SHA256};
+use aws_lc_rs::rand::SystemRandom;
+use aws_lc_rs::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use axum_server::tls_rustls::RustlsConfig;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use bytes::Bytes;
+use http::header::{ACCEPT, CONTENT_TYPE, LOCATION, RETRY_AFTER};
+use http::{HeaderMap, HeaderValue, Request, Response, StatusCode};
+use http_body_util::Full;
+use rcgen::{CertificateParams, CustomExtension, KeyPair as CertificateKeyPair};
+use reqwest::Client;
+use rustls::crypto::aws_lc_rs::default_provider;
+use rustls::crypto::aws_lc_rs::sign::any_supported_type;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use serde_json::{json, Value};
+use tokio::{fs, time};
+
+use crate::dual_protocol::ACME_TLS_ALPN;
+use crate::file;
+
+/// Path prefix of HTTP-01 challenge requests, see
+/// [RFC 8555 Section 8.3](https://www.rfc-editor.org/rfc/rfc8555#section-8.3).
+const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+/// How long to wait between polling the status of an authorization or order,
+/// unless the ACME server sends a `Retry-After` header.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often to poll the status of an authorization or order before giving up.
+const POLL_ATTEMPTS: usize = 60;
+
+/// Configuration for [`Acme`].
+///
+/// # Example
+///
+/// ```
+/// # use axum_server_dual_protocol::AcmeConfig;
+/// let config = AcmeConfig::new(["example.com", "www.example.com"])
+/// 	.directory(AcmeConfig::LETS_ENCRYPT_STAGING)
+/// 	.contact("mailto:admin@example.com")
+/// 	.terms_of_service_agreed(true)
+/// 	.cache("/var/cache/acme");
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "acme")))]
+#[derive(Clone, Debug)]
+pub struct AcmeConfig {
+	/// URL of the ACME directory.
+	directory: String,
+	/// Domains the certificate is issued for.
+	domains: Vec<String>,
+	/// Contact URLs of the account.
+	contact: Vec<String>,
+	/// If the terms of service of the ACME server are agreed to.
+	terms_of_service_agreed: bool,
+	/// Directory to cache the account key and certificate in.
+	cache: PathBuf,
+	/// Additional root certificates to trust when connecting to the ACME
+	/// server.
+	root_certificates: Vec<CertificateDer<'static>>,
+	/// How long before it expires the certificate is renewed.
+	renew_before: Duration,
+	/// Challenge type to prove control over the domains.
+	challenge: AcmeChallenge,
+}
+
+impl AcmeConfig {
+	/// Directory URL of the Let's Encrypt production environment.
+	pub const LETS_ENCRYPT_PRODUCTION: &'static str =
+		"https://acme-v02.api.letsencrypt.org/directory";
+	/// Directory URL of the Let's Encrypt staging environment.
+	pub const LETS_ENCRYPT_STAGING: &'static str =
+		"https://acme-staging-v02.api.letsencrypt.org/directory";
+
+	/// Create a new [`AcmeConfig`] issuing a single certificate for all given
+	/// domains.
+	///
+	/// Defaults to the [Let's Encrypt production
+	/// environment](Self::LETS_ENCRYPT_PRODUCTION), caching in `acme` relative
+	/// to the working directory and renewing certificates 30 days before they
+	/// expire.
+	#[must_use]
+	pub fn new<Domains, Domain>(domains: Domains) -> Self
+	where
+		Domains: IntoIterator<Item = Domain>,
+		Domain: Into<String>,
+	{
+		Self {
+			directory: String::from(Self::LETS_ENCRYPT_PRODUCTION),
+			domains: domains.into_iter().map(Into::into).collect(),
+			contact: Vec::new(),
+			terms_of_service_agreed: false,
+			cache: PathBuf::from("acme"),
+			root_certificates: Vec::new(),
+			renew_before: Duration::from_secs(30 * 24 * 60 * 60),
+			challenge: AcmeChallenge::Http01,
+		}
+	}
+
+	/// Set the URL of the ACME directory.
+	#[must_use]
+	pub fn directory<Url: Into<String>>(mut self, url: Url) -> Self {
+		self.directory = url.into();
+		self
+	}
+
+	/// Add a contact URL to the account, e.g. `mailto:admin@example.com`.
+	#[must_use]
+	pub fn contact<Url: Into<String>>(mut self, url: Url) -> Self {
+		self.contact.push(url.into());
+		self
+	}
+
+	/// Set if the terms of service of the ACME server are agreed to. Most ACME
+	/// servers require this. Defaults to `false`.
+	#[must_use]
+	pub const fn terms_of_service_agreed(mut self, agreed: bool) -> Self {
+		self.terms_of_service_agreed = agreed;
+		self
+	}
+
+	/// Set the directory the account key and certificate are cached in. It is
+	/// created if it doesn't exist.
+	///
+	/// Certificates are cached separately for each [directory
+	/// URL](Self::directory()) and set of domains, so changing either obtains a
+	/// new certificate.
+	#[must_use]
+	pub fn cache<Path: Into<PathBuf>>(mut self, path: Path) -> Self {
+		self.cache = path.into();
+		self
+	}
+
+	/// Trust an additional root certificate when connecting to the ACME
+	/// server, e.g. the one of a local test server like
+	/// [Pebble](https://github.com/letsencrypt/pebble).
+	#[must_use]
+	pub fn root_certificate(mut self, certificate: CertificateDer<'static>) -> Self {
+		self.root_certificates.push(certificate);
+		self
+	}
+
+	/// Set how long before it expires the certificate is renewed. Defaults to
+	/// 30 days.
+	#[must_use]
+	pub const fn renew_before(mut self, duration: Duration) -> Self {
+		self.renew_before = duration;
+		self
+	}
+
+	/// Set the challenge type to prove control over the domains. Defaults to
+	/// [`AcmeChallenge::Http01`].
+	#[must_use]
+	pub const fn challenge(mut self, challenge: AcmeChallenge) -> Self {
+		self.challenge = challenge;
+		self
+	}
+}
+
+/// Challenge type [`Acme`] uses to prove control over the domains.
+///
+/// See [`AcmeConfig::challenge()`].
+#[cfg_attr(docsrs, doc(cfg(feature = "acme")))]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum AcmeChallenge {
+	/// HTTP-01, answered on the plain path when set with
+	/// [`ServerExt::set_acme()`](crate::ServerExt::set_acme()). The server has
+	/// to be reachable over HTTP on port 80.
+	#[default]
+	Http01,
+	/// TLS-ALPN-01, answered during the handshake by the [`RustlsConfig`] from
+	/// [`Acme::rustls_config()`]. The server has to be reachable over HTTPS on
+	/// port 443, plain HTTP isn't required.
+	TlsAlpn01,
+}
+
+impl AcmeChallenge {
+	/// Returns the challenge type as named by the ACME server.
+	const fn kind(self) -> &'static str {
+		match self {
+			Self::Http01 => "http-01",
+			Self::TlsAlpn01 => "tls-alpn-01",
+		}
+	}
+}
+
+/// Obtains and renews certificates from an ACME server, e.g.
+/// [Let's Encrypt](https://letsencrypt.org).
+///
+/// HTTP-01 challenges are answered on the plain path before
+/// [`UpgradeHttp`](crate::UpgradeHttp) when set with
+/// [`ServerExt::set_acme()`](crate::ServerExt::set_acme()), so the server has
+/// to be reachable over HTTP on port 80. Alternatively TLS-ALPN-01 challenges
+/// are answered during the handshake, see [`AcmeChallenge`]. Certificates are
+/// cached on disk, see [`AcmeConfig::cache()`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::net::SocketAddr;
+/// # use std::time::Duration;
+/// # use axum::{routing, Router};
+/// use axum_server_dual_protocol::{Acme, AcmeConfig, ServerExt};
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let app = Router::new();
+/// let acme = Acme::new(
+/// 	AcmeConfig::new(["example.com"])
+/// 		.contact("mailto:admin@example.com")
+/// 		.terms_of_service_agreed(true),
+/// )?;
+/// let config = acme.rustls_config().await?;
+///
+/// // Binding starts listening, so challenges can be answered from here on.
+/// let server = axum_server_dual_protocol::bind_split_protocol(
+/// 	SocketAddr::from(([0, 0, 0, 0], 80)),
+/// 	SocketAddr::from(([0, 0, 0, 0], 443)),
+/// 	config.clone(),
+/// )?
+/// .set_upgrade(true)
+/// .set_acme(acme.clone());
+///
+/// tokio::spawn(async move {
+/// 	loop {
+/// 		match acme.run(&config).await {
+/// 			Ok(never) => match never {},
+/// 			Err(error) => eprintln!("failed to renew certificate: {error}"),
+/// 		}
+/// 		tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+/// 	}
+/// });
+///
+/// server.serve(app.into_make_service()).await?;
+/// #
+/// # Ok(())
+/// # }
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "acme")))]
+#[derive(Clone, Debug)]
+pub struct Acme(Arc<AcmeInner>);
+
+/// Shared state of [`Acme`].
+#[derive(Debug)]
+struct AcmeInner {
+	/// Configuration.
+	config: AcmeConfig,
+	/// Client to connect to the ACME server.
+	client: Client,
+	/// Pending HTTP-01 challenges, mapping tokens to key authorizations.
+	http_challenges: Mutex<HashMap<String, String>>,
+	/// Pending TLS-ALPN-01 challenges, mapping domains to challenge
+	/// certificates.
+	tls_challenges: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl Acme {
+	/// Create a new [`Acme`].
+	///
+	/// # Errors
+	///
+	/// If an [additional root
+	/// certificate](AcmeConfig::root_certificate()) is invalid.
+	pub fn new(config: AcmeConfig) -> Result<Self, AcmeError> {
+		let mut roots = RootCertStore::empty();
+		roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+		for certificate in &config.root_certificates {
+			roots.add(certificate.clone()).map_err(crypto)?;
+		}
+
+		let tls = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+			.with_safe_default_protocol_versions()
+			.map_err(crypto)?
+			.with_root_certificates(roots)
+			.with_no_client_auth();
+
+		let client = Client::builder()
+			.use_preconfigured_tls(tls)
+			.user_agent(concat!(
+				env!("CARGO_PKG_NAME"),
+				"/",
+				env!("CARGO_PKG_VERSION")
+			))
+			.build()
+			.map_err(|error| AcmeError::Http(error.into()))?;
+
+		Ok(Self(Arc::new(AcmeInner {
+			config,
+			client,
+			http_challenges: Mutex::default(),
+			tls_challenges: Mutex::default(),
+		})))
+	}
+
+	/// Returns the [`AcmeConfig`].
+	#[must_use]
+	pub fn config(&self) -> &AcmeConfig {
+		&self.0.config
+	}
+
+	/// Create a [`RustlsConfig`] from the cached certificate, which also
+	/// answers TLS-ALPN-01 challenges.
+	///
+	/// If no certificate was cached yet, a self-signed placeholder is used
+	/// until [`renew()`](Self::renew()) replaces it.
+	///
+	/// # Errors
+	///
+	/// If the cache can't be read or the cached certificate is invalid.
+	pub async fn rustls_config(&self) -> Result<RustlsConfig, AcmeError> {
+		let certificate = if let Some(cached) = self.cached().await? {
+			cached.certified_key
+		} else {
+			let placeholder = rcgen::generate_simple_self_signed(self.0.config.domains.clone())
+				.map_err(crypto)?;
+
+			certified_key(
+				vec![placeholder.cert.der().clone()],
+				&PrivateKeyDer::Pkcs8(placeholder.key_pair.serialize_der().into()),
+			)?
+		};
+
+		Ok(RustlsConfig::from_config(self.server_config(certificate)?))
+	}
+
+	/// Obtains a new certificate and swaps it into `config` if none was cached
+	/// yet or the cached one expires within [`AcmeConfig::renew_before()`].
+	///
+	/// Returns `true` if a new certificate was obtained.
+	///
+	/// # Errors
+	///
+	/// If obtaining the certificate or accessing the cache fails.
+	pub async fn renew(&self, config: &RustlsConfig) -> Result<bool, AcmeError> {
+		if !self.renew_in().await?.is_zero() {
+			return Ok(false);
+		}
+
+		let (certificate, private_key) = self.issue().await?;
+		let certified_key = pem_certified_key(certificate.as_bytes(), private_key.as_bytes())?;
+
+		let (certificate_path, private_key_path) = self.certificate_paths();
+		fs::create_dir_all(&self.0.config.cache).await?;
+		// Each file is replaced atomically, a mismatched pair left by an interruption
+		// in between is detected when loading.
+		file::write_private_key(&private_key_path, private_key.as_bytes()).await?;
+		file::write(&certificate_path, certificate.as_bytes()).await?;
+
+		config.reload_from_config(self.server_config(certified_key)?);
+
+		Ok(true)
+	}
+
+	/// Calls [`renew()`](Self::renew()) whenever the certificate is due.
+	///
+	/// # Errors
+	///
+	/// Returns the first error, it is up to the caller to log it and call
+	/// [`run()`](Self::run()) again.
+	pub async fn run(&self, config: &RustlsConfig) -> Result<Infallible, AcmeError> {
+		loop {
+			let _ = self.renew(config).await?;
+			time::sleep(self.renew_in().await?).await;
+		}
+	}
+
+	/// Returns how long until the cached certificate is due for renewal.
+	async fn renew_in(&self) -> Result<Duration, AcmeError> {
+		let Some(cached) = self.cached().await? else {
+			return Ok(Duration::ZERO);
+		};
+		let renew_at = cached
+			.not_after
+			.checked_sub(self.0.config.renew_before)
+			.unwrap_or(UNIX_EPOCH);
+
+		Ok(renew_at
+			.duration_since(SystemTime::now())
+			.unwrap_or_default())
+	}
+
+	/// Loads the cached certificate. Returns [`None`] if none was cached yet
+	/// or it doesn't match its private key, e.g. because writing them was
+	/// interrupted.
+	async fn cached(&self) -> Result<Option<Cached>, AcmeError> {
+		let (certificate_path, private_key_path) = self.certificate_paths();
+
+		let (Some(certificate), Some(private_key)) = (
+			read(&certificate_path).await?,
+			read(&private_key_path).await?,
+		) else {
+			return Ok(None);
+		};
+		let certified_key = pem_certified_key(&certificate, &private_key)?;
+
+		if certified_key.keys_match().is_err() {
+			return Ok(None);
+		}
+
+		let leaf = certified_key
+			.cert
+			.first()
+			.ok_or_else(|| crypto("no certificate found"))?;
+		let (_, leaf) = x509_parser::parse_x509_certificate(leaf).map_err(crypto)?;
+		let not_after = u64::try_from(leaf.validity().not_after.timestamp()).map_err(crypto)?;
+
+		Ok(Some(Cached {
+			not_after: UNIX_EPOCH + Duration::from_secs(not_after),
+			certified_key,
+		}))
+	}
+
+	/// Creates a [`ServerConfig`] serving `certificate`, unless a TLS-ALPN-01
+	/// challenge is requested.
+	fn server_config(
+		&self,
+		certificate: Arc<CertifiedKey>,
+	) -> Result<Arc<ServerConfig>, AcmeError> {
+		let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+			.with_safe_default_protocol_versions()
+			.map_err(crypto)?
+			.with_no_client_auth()
+			.with_cert_resolver(Arc::new(AcmeResolver {
+				acme: self.clone(),
+				certificate,
+			}));
+		config.alpn_protocols = vec![
+			b"h2".to_vec(),
+			b"http/1.1".to_vec(),
+			b"http/1.0".to_vec(),
+			ACME_TLS_ALPN.to_vec(),
+		];
+
+		Ok(Arc::new(config))
+	}
+
+	/// Returns the paths of the cached certificate and private key, which are
+	/// unique for the directory URL and set of domains.
+	fn certificate_paths(&self) -> (PathBuf, PathBuf) {
+		let config = &self.0.config;
+		let mut domains: Vec<_> = config
+			.domains
+			.iter()
+			.map(|domain| domain.to_ascii_lowercase())
+			.collect();
+		domains.sort_unstable();
+		domains.dedup();
+
+		let key = format!("{}\n{}", config.directory, domains.join("\n"));
+		let hash = URL_SAFE_NO_PAD.encode(digest::digest(&SHA256, key.as_bytes()));
+		let name = config.domains.first().map_or("certificate", String::as_str);
+		let name = format!("{name}-{hash}");
+
+		(
+			config.cache.join(format!("{name}.crt.pem")),
+			config.cache.join(format!("{name}.key.pem")),
+		)
+	}
+
+	/// Loads the cached account key or creates a new one.
+	async fn account_key(&self) -> Result<AccountKey, AcmeError> {
+		let path = self.0.config.cache.join("account.der");
+
+		let pkcs8 = match fs::read(&path).await {
+			Ok(pkcs8) => pkcs8,
+			Err(error) if error.kind() == ErrorKind::NotFound => {
+				let pkcs8 = EcdsaKeyPair::generate_pkcs8(
+					&ECDSA_P256_SHA256_FIXED_SIGNING,
+					&SystemRandom::new(),
+				)
+				.map_err(crypto)?;
+
+				fs::create_dir_all(&self.0.config.cache).await?;
+				file::write_private_key(&path, pkcs8.as_ref()).await?;
+
+				pkcs8.as_ref().to_vec()
+			}
+			Err(error) => return Err(error.into()),
+		};
+
+		AccountKey::new(&pkcs8)
+	}
+
+	/// Orders a new certificate, returning the PEM encoded certificate chain
+	/// and private key.
+	async fn issue(&self) -> Result<(String, String), AcmeError> {
+		let config = &self.0.config;
+		let mut session = Session::new(self, self.account_key().await?).await?;
+
+		session.account().await?;
+
+		let identifiers: Vec<_> = config
+			.domains
+			.iter()
+			.map(|domain| json!({ "type": "dns", "value": domain }))
+			.collect();
+		let response = session
+			.post(
+				&session.directory.order.clone(),
+				Some(&json!({ "identifiers": identifiers })),
+			)
+			.await?;
+		let order_url = response.location()?;
+		let order = response.json()?;
+
+		let mut challenges = Challenges {
+			acme: self,
+			tokens: Vec::new(),
+			domains: Vec::new(),
+		};
+
+		for authorization in strings(&order, "authorizations")? {
+			session.authorize(&authorization, &mut challenges).await?;
+		}
+
+		let order = session.poll(&order_url, &["pending"]).await?;
+		expect_order_status(&order, "ready")?;
+		drop(challenges);
+
+		let private_key = CertificateKeyPair::generate().map_err(crypto)?;
+		let request = CertificateParams::new(config.domains.clone())
+			.and_then(|params| params.serialize_request(&private_key))
+			.map_err(crypto)?;
+		let _ = session
+			.post(
+				string(&order, "finalize")?,
+				Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(request.der()) })),
+			)
+			.await?;
+
+		let order = session.poll(&order_url, &["ready", "processing"]).await?;
+		expect_order_status(&order, "valid")?;
+
+		let certificate = session.post(string(&order, "certificate")?, None).await?;
+		let certificate = String::from_utf8(certificate.body.to_vec())
+			.map_err(|_| AcmeError::InvalidResponse("certificate is not PEM encoded"))?;
+
+		Ok((certificate, private_key.serialize_pem()))
+	}
+
+	/// Answers HTTP-01 challenge requests, returning [`None`] for all other
+	/// requests.
+	pub(crate) fn respond<Body>(&self, request: &Request<Body>) -> Option<Response<Full<Bytes>>> {
+		let token = request.uri().path().strip_prefix(CHALLENGE_PATH)?;
+		let key_authorization = self
+			.0
+			.http_challenges
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.get(token)
+			.cloned();
+
+		let mut response = Response::new(Full::default());
+
+		if let Some(key_authorization) = key_authorization {
+			*response.body_mut() = Full::from(key_authorization);
+			let _ = response.headers_mut().insert(
+				CONTENT_TYPE,
+				HeaderValue::from_static("application/octet-stream"),
+			);
+		} else {
+			*response.status_mut() = StatusCode::NOT_FOUND;
+		}
+
+		Some(response)
+	}
+}
+
+/// Certificate loaded from the cache.
+struct Cached {
+	/// Certificate chain and private key.
+	certified_key: Arc<CertifiedKey>,
+	/// Expiry of the certificate.
+	not_after: SystemTime,
+}
+
+/// Reads a file, returning [`None`] if it doesn't exist.
+async fn read(path: &Path) -> io::Result<Option<Vec<u8>>> {
+	match fs::read(path).await {
+		Ok(contents) => Ok(Some(contents)),
+		Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+		Err(error) => Err(error),
+	}
+}
+
+/// Removes pending challenges when dropped.
+struct Challenges<'acme> {
+	/// [`Acme`] the challenges are registered with.
+	acme: &'acme Acme,
+	/// Tokens of registered HTTP-01 challenges.
+	tokens: Vec<String>,
+	/// Domains of registered TLS-ALPN-01 challenges.
+	domains: Vec<String>,
+}
+
+impl Challenges<'_> {
+	/// Registers a HTTP-01 challenge token with its key authorization.
+	fn insert_http(&mut self, token: String, key_authorization: String) {
+		let _ = self
+			.acme
+			.0
+			.http_challenges
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(token.clone(), key_authorization);
+		self.tokens.push(token);
+	}
+
+	/// Registers a TLS-ALPN-01 challenge certificate for a domain.
+	fn insert_tls(&mut self, domain: String, certificate: Arc<CertifiedKey>) {
+		let _ = self
+			.acme
+			.0
+			.tls_challenges
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.insert(domain.clone(), certificate);
+		self.domains.push(domain);
+	}
+}
+
+impl Drop for Challenges<'_> {
+	fn drop(&mut self) {
+		let mut http = self
+			.acme
+			.0
+			.http_challenges
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner);
+
+		for token in &self.tokens {
+			let _ = http.remove(token);
+		}
+
+		drop(http);
+
+		let mut tls = self
+			.acme
+			.0
+			.tls_challenges
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner);
+
+		for domain in &self.domains {
+			let _ = tls.remove(domain);
+		}
+	}
+}
+
+/// Resolves the certificate of [`Acme`] or, if requested with the
+/// `acme-tls/1` ALPN, a TLS-ALPN-01 challenge certificate.
+#[derive(Debug)]
+struct AcmeResolver {
+	/// [`Acme`] holding pending challenges.
+	acme: Acme,
+	/// Certificate to serve otherwise.
+	certificate: Arc<CertifiedKey>,
+}
+
+impl ResolvesServerCert for AcmeResolver {
+	fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+		let challenge = client_hello
+			.alpn()
+			.into_iter()
+			.flatten()
+			.any(|protocol| protocol == ACME_TLS_ALPN);
+
+		if challenge {
+			let domain = client_hello.server_name()?;
+
+			self.acme
+				.0
+				.tls_challenges
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner)
+				.get(domain)
+				.cloned()
+		} else {
+			Some(Arc::clone(&self.certificate))
+		}
+	}
+}
+
+/// Creates a TLS-ALPN-01 challenge certificate, see
+/// [RFC 8737 Section 3](https://www.rfc-editor.org/rfc/rfc8737#section-3).
+fn tls_alpn_certificate(
+	domain: &str,
+	key_authorization: &str,
+) -> Result<Arc<CertifiedKey>, AcmeError> {
+	let digest = digest::digest(&SHA256, key_authorization.as_bytes());
+	let mut params = CertificateParams::new(vec![domain.to_owned()]).map_err(crypto)?;
+	params
+		.custom_extensions
+		.push(CustomExtension::new_acme_identifier(digest.as_ref()));
+
+	let private_key = CertificateKeyPair::generate().map_err(crypto)?;
+	let certificate = params.self_signed(&private_key).map_err(crypto)?;
+
+	certified_key(
+		vec![certificate.der().clone()],
+		&PrivateKeyDer::Pkcs8(private_key.serialize_der().into()),
+	)
+}
+
+/// Creates a [`CertifiedKey`] from a PEM encoded certificate chain and private
+/// key.
+fn pem_certified_key(
+	certificate: &[u8],
+	private_key: &[u8],
+) -> Result<Arc<CertifiedKey>, AcmeError> {
+	let chain = CertificateDer::pem_slice_iter(certificate)
+		.collect::<Result<_, _>>()
+		.map_err(crypto)?;
+	let private_key = PrivateKeyDer::from_pem_slice(private_key).map_err(crypto)?;
+
+	certified_key(chain, &private_key)
+}
+
+/// Creates a [`CertifiedKey`].
+fn certified_key(
+	chain: Vec<CertificateDer<'static>>,
+	private_key: &PrivateKeyDer<'_>,
+) -> Result<Arc<CertifiedKey>, AcmeError> {
+	let private_key = any_supported_type(private_key).map_err(crypto)?;
+
+	Ok(Arc::new(CertifiedKey::new(chain, private_key)))
+}
+
+/// ES256 account key, signing requests as JSON Web Signatures, see
+/// [RFC 8555 Section 6.2](https://www.rfc-editor.org/rfc/rfc8555#section-6.2).
+struct AccountKey {
+	/// Key pair.
+	key_pair: EcdsaKeyPair,
+	/// Public key as JSON Web Key.
+	jwk: Value,
+	/// JSON Web Key thumbprint, see
+	/// [RFC 7638](https://www.rfc-editor.org/rfc/rfc7638).
+	thumbprint: String,
+}
+
+impl AccountKey {
+	/// Create a new [`AccountKey`] from a PKCS#8 encoded key.
+	fn new(pkcs8: &[u8]) -> Result<Self, AcmeError> {
+		let key_pair =
+			EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8).map_err(crypto)?;
+
+		// Uncompressed point: `0x04`, followed by the x and y coordinates.
+		let [4, point @ ..] = key_pair.public_key().as_ref() else {
+			return Err(AcmeError::Crypto("unexpected public key format".into()));
+		};
+		let (x, y) = point.split_at(point.len() / 2);
+		let x = URL_SAFE_NO_PAD.encode(x);
+		let y = URL_SAFE_NO_PAD.encode(y);
+
+		// Members have to be in lexicographic order without whitespace.
+		let thumbprint = format!(r#"{{"crv":"P-256","kty":"EC","x":"{x}","y":"{y}"}}"#);
+		let thumbprint = URL_SAFE_NO_PAD.encode(digest::digest(&SHA256, thumbprint.as_bytes()));
+
+		Ok(Self {
+			key_pair,
+			jwk: json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y }),
+			thumbprint,
+		})
+	}
+
+	/// Signs a request body. Without an `account` the public key is embedded.
+	fn sign(
+		&self,
+		url: &str,
+		nonce: &str,
+		account: Option<&str>,
+		payload: Option<&Value>,
+	) -> Result<Vec<u8>, AcmeError> {
+		let protected = if let Some(account) = account {
+			json!({ "alg": "ES256", "kid": account, "nonce": nonce, "url": url })
+		} else {
+			json!({ "alg": "ES256", "jwk": self.jwk, "nonce": nonce, "url": url })
+		};
+
+		let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+		// POST-as-GET requests have an empty payload.
+		let payload = payload.map_or_else(String::new, |payload| {
+			URL_SAFE_NO_PAD.encode(payload.to_string())
+		});
+		let signature = self
+			.key_pair
+			.sign(
+				&SystemRandom::new(),
+				format!("{protected}.{payload}").as_bytes(),
+			)
+			.map_err(crypto)?;
+
+		Ok(json!({
+			"protected": protected,
+			"payload": payload,
+			"signature": URL_SAFE_NO_PAD.encode(signature),
+		})
+		.to_string()
+		.into_bytes())
+	}
+}
+
+/// Resources of the ACME server.
+struct Directory {
+	/// URL to get a new nonce.
+	nonce: String,
+	/// URL to create or look up an account.
+	account: String,
+	/// URL to create a new order.
+	order: String,
+}
+
+/// Response from the ACME server.
+struct AcmeResponse {
+	/// Headers.
+	headers: HeaderMap,
+	/// Body.
+	body: Bytes,
+}
+
+impl AcmeResponse {
+	/// Returns the `Location` header.
+	fn location(&self) -> Result<String, AcmeError> {
+		self.headers
+			.get(LOCATION)
+			.and_then(|location| location.to_str().ok())
+			.map(String::from)
+			.ok_or(AcmeError::InvalidResponse("missing `Location` header"))
+	}
+
+	/// Returns the `Retry-After` header, if it is given in seconds.
+	fn retry_after(&self) -> Option<Duration> {
+		self.headers
+			.get(RETRY_AFTER)?
+			.to_str()
+			.ok()?
+			.parse()
+			.ok()
+			.map(Duration::from_secs)
+	}
+
+	/// Parses the body as JSON.
+	fn json(&self) -> Result<Value, AcmeError> {
+		serde_json::from_slice(&self.body)
+			.map_err(|_| AcmeError::InvalidResponse("body is not valid JSON"))
+	}
+}
+
+/// Authenticated connection to the ACME server.
+struct Session<'acme> {
+	/// [`Acme`] this session belongs to.
+	acme: &'acme Acme,
+	/// Account key.
+	key: AccountKey,
+	/// Resources of the ACME server.
+	directory: Directory,
+	/// Nonce for the next request.
+	nonce: Option<String>,
+	/// Account URL, once known.
+	account: Option<String>,
+}
+
+impl<'acme> Session<'acme> {
+	/// Fetches the directory and creates a new [`Session`].
+	async fn new(acme: &'acme Acme, key: AccountKey) -> Result<Session<'acme>, AcmeError> {
+		let response = acme
+			.0
+			.client
+			.get(&acme.0.config.directory)
+			.send()
+			.await?
+			.error_for_status()?;
+		let directory: Value = serde_json::from_slice(&response.bytes().await?)
+			.map_err(|_| AcmeError::InvalidResponse("directory is not valid JSON"))?;
+
+		Ok(Session {
+			acme,
+			key,
+			directory: Directory {
+				nonce: string(&directory, "newNonce")?.to_owned(),
+				account: string(&directory, "newAccount")?.to_owned(),
+				order: string(&directory, "newOrder")?.to_owned(),
+			},
+			nonce: None,
+			account: None,
+		})
+	}
+
+	/// Creates the account or looks up the existing one for the account key.
+	async fn account(&mut self) -> Result<(), AcmeError> {
+		let config = &self.acme.0.config;
+		let payload = json!({
+			"termsOfServiceAgreed": config.terms_of_service_agreed,
+			"contact": config.contact,
+		});
+		let response = self
+			.post(&self.directory.account.clone(), Some(&payload))
+			.await?;
+		self.account = Some(response.location()?);
+
+		Ok(())
+	}
+
+	/// Completes the configured challenge of an authorization.
+	async fn authorize(
+		&mut self,
+		url: &str,
+		challenges: &mut Challenges<'_>,
+	) -> Result<(), AcmeError> {
+		let authorization = self.post(url, None).await?.json()?;
+
+		if status(&authorization)? == "valid" {
+			return Ok(());
+		}
+
+		let domain = authorization
+			.get("identifier")
+			.and_then(|identifier| identifier.get("value"))
+			.and_then(Value::as_str)
+			.unwrap_or_default()
+			.to_owned();
+		let kind = self.acme.0.config.challenge;
+		let challenge = authorization
+			.get("challenges")
+			.and_then(Value::as_array)
+			.and_then(|list| {
+				list.iter().find(|challenge| {
+					challenge.get("type").and_then(Value::as_str) == Some(kind.kind())
+				})
+			})
+			.ok_or_else(|| AcmeError::Authorization {
+				domain: domain.clone(),
+				detail: format!("no `{}` challenge offered", kind.kind()),
+			})?;
+
+		let token = string(challenge, "token")?;
+		let key_authorization = format!("{token}.{}", self.key.thumbprint);
+
+		match kind {
+			AcmeChallenge::Http01 => challenges.insert_http(token.to_owned(), key_authorization),
+			AcmeChallenge::TlsAlpn01 => challenges.insert_tls(
+				domain.clone(),
+				tls_alpn_certificate(&domain, &key_authorization)?,
+			),
+		}
+		let _ = self
+			.post(string(challenge, "url")?, Some(&json!({})))
+			.await?;
+
+		let authorization = self.poll(url, &["pending"]).await?;
+
+		if status(&authorization)? == "valid" {
+			Ok(())
+		} else {
+			let detail = authorization
+				.get("challenges")
+				.and_then(Value::as_array)
+				.into_iter()
+				.flatten()
+				.find_map(|challenge| challenge.get("error")?.get("detail")?.as_str())
+				.map_or_else(|| String::from("challenge failed"), String::from);
+
+			Err(AcmeError::Authorization { domain, detail })
+		}
+	}
+
+	/// Polls a resource until its status isn't one of `pending` anymore,
+	/// honoring `Retry-After`.
+	async fn poll(&mut self, url: &str, pending: &[&str]) -> Result<Value, AcmeError> {
+		for _ in 0..POLL_ATTEMPTS {
+			let response = self.post(url, None).await?;
+			let resource = response.json()?;
+
+			if !pending.contains(&status(&resource)?) {
+				return Ok(resource);
+			}
+
+			time::sleep(response.retry_after().unwrap_or(POLL_INTERVAL)).await;
+		}
+
+		Err(AcmeError::Timeout)
+	}
+
+	/// Sends a signed request, retrying once if the nonce was rejected. Without
+	/// a `payload` a POST-as-GET request is sent.
+	async fn post(
+		&mut self,
+		url: &str,
+		payload: Option<&Value>,
+	) -> Result<AcmeResponse, AcmeError> {
+		let client = &self.acme.0.client;
+		let mut retried = false;
+
+		loop {
+			let nonce = if let Some(nonce) = self.nonce.take() {
+				nonce
+			} else {
+				self.new_nonce().await?
+			};
+			let body = self
+				.key
+				.sign(url, &nonce, self.account.as_deref(), payload)?;
+
+			let response = client
+				.post(url)
+				.header(CONTENT_TYPE, "application/jose+json")
+				.header(
+					ACCEPT,
+					"application/json, application/pem-certificate-chain",
+				)
+				.body(body)
+				.send()
+				.await?;
+
+			let status = response.status();
+			let headers = response.headers().clone();
+			let body = response.bytes().await?;
+			self.nonce = replay_nonce(&headers);
+
+			if status.is_success() {
+				return Ok(AcmeResponse { headers, body });
+			}
+
+			let problem: Value = serde_json::from_slice(&body).unwrap_or_default();
+			let kind = problem
+				.get("type")
+				.and_then(Value::as_str)
+				.unwrap_or_default();
+
+			if kind == "urn:ietf:params:acme:error:badNonce" && !retried {
+				retried = true;
+				continue;
+			}
+
+			return Err(AcmeError::Problem {
+				status,
+				kind: kind.to_owned(),
+				detail: problem
+					.get("detail")
+					.and_then(Value::as_str)
+					.unwrap_or_default()
+					.to_owned(),
+			});
+		}
+	}
+
+	/// Requests a new nonce.
+	async fn new_nonce(&self) -> Result<String, AcmeError> {
+		let response = self
+			.acme
+			.0
+			.client
+			.head(&self.directory.nonce)
+			.send()
+			.await?
+			.error_for_status()?;
+
+		replay_nonce(response.headers())
+			.ok_or(AcmeError::InvalidResponse("missing `Replay-Nonce` header"))
+	}
+}
+
+/// Returns the `Replay-Nonce` header.
+fn replay_nonce(headers: &HeaderMap) -> Option<String> {
+	headers
+		.get("replay-nonce")
+		.and_then(|nonce| nonce.to_str().ok())
+		.map(String::from)
+}
+
+/// Returns a string field of a JSON object.
+fn string<'value>(value: &'value Value, field: &'static str) -> Result<&'value str, AcmeError> {
+	value
+		.get(field)
+		.and_then(Value::as_str)
+		.ok_or(AcmeError::InvalidResponse(field))
+}
+
+/// Returns a string array field of a JSON object.
+fn strings(value: &Value, field: &'static str) -> Result<Vec<String>, AcmeError> {
+	value
+		.get(field)
+		.and_then(Value::as_array)
+		.and_then(|list| {
+			list.iter()
+				.map(|item| item.as_str().map(String::from))
+				.collect()
+		})
+		.ok_or(AcmeError::InvalidResponse(field))
+}
+
+/// Creates an [`AcmeError::Crypto`].
+fn crypto<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> AcmeError {
+	AcmeError::Crypto(error.into())
+}
+
+/// Returns the `status` field of a JSON object.
+fn status(value: &Value) -> Result<&str, AcmeError> {
+	string(value, "status")
+}
+
+/// Makes sure an order has the expected status.
+fn expect_order_status(order: &Value, expected: &str) -> Result<(), AcmeError> {
+	if status(order)? == expected {
+		Ok(())
+	} else {
+		Err(AcmeError::InvalidResponse("order has an unexpected status"))
+	}
+}
+
+/// Reason why [`Acme`] failed to obtain a certificate.
+#[cfg_attr(docsrs, doc(cfg(feature = "acme")))]
+#[derive(Debug)]
+pub enum AcmeError {
+	/// Communicating with the ACME server failed.
+	Http(Box<dyn Error + Send + Sync>),
+	/// The ACME server returned an error, see
+	/// [RFC 8555 Section 6.7](https://www.rfc-editor.org/rfc/rfc8555#section-6.7).
+	Problem {
+		/// The HTTP status code.
+		status: StatusCode,
+		/// The error type, e.g. `urn:ietf:params:acme:error:rateLimited`.
+		kind: String,
+		/// Human-readable description.
+		detail: String,
+	},
+	/// The ACME server returned an unexpected response.
+	InvalidResponse(&'static str),
+	/// A domain couldn't be authorized.
+	Authorization {
+		/// The domain.
+		domain: String,
+		/// Human-readable description.
+		detail: String,
+	},
+	/// An order or authorization didn't complete in time.
+	Timeout,
+	/// Generating or loading keys or certificates failed.
+	Crypto(Box<dyn Error + Send + Sync>),
+	/// Accessing the cache or loading the certificate failed.
+	Io(io::Error),
+}
+
+impl Display for AcmeError {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Http(error) => write!(formatter, "request to the ACME server failed: {error}"),
+			Self::Problem {
+				status,
+				kind,
+				detail,
+			} => write!(
+				formatter,
+				"ACME server responded with {status} {kind}: {detail}"
+			),
+			Self::InvalidResponse(reason) => {
+				write!(formatter, "invalid response from the ACME server: {reason}")
+			}
+			Self::Authorization { domain, detail } => {
+				write!(formatter, "failed to authorize `{domain}`: {detail}")
+			}
+			Self::Timeout => formatter.write_str("ACME server didn't complete the order in time"),
+			Self::Crypto(error) => write!(formatter, "cryptographic operation failed: {error}"),
+			Self::Io(error) => write!(formatter, "failed to access the certificate cache: {error}"),
+		}
+	}
+}
+
+impl Error for AcmeError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::Http(error) | Self::Crypto(error) => Some(error.as_ref()),
+			Self::Io(error) => Some(error),
+			Self::Problem { .. }
+			| Self::InvalidResponse(_)
+			| Self::Authorization { .. }
+			| Self::Timeout => None,
+		}
+	}
+}
+
+impl From<io::Error> for AcmeError {
+	fn from(error: io::Error) -> Self {
+		Self::Io(error)
+	}
+}
+
+impl From<reqwest::Error> for AcmeError {
+	fn from(error: reqwest::Error) -> Self {
+		Self::Http(error.into())
+	}
+}
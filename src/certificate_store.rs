@@ -0,0 +1,417 @@
+//! Multi-domain certificate store.
+//!
+//! See [`CertificateStore`].
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::Poll;
+use std::time::Duration;
+
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::crypto::aws_lc_rs::default_provider;
+use rustls::crypto::aws_lc_rs::sign::any_supported_type;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use tokio::fs;
+use x509_parser::extensions::GeneralName;
+
+use crate::watch::{self, Callback};
+
+/// File name suffix of PEM encoded certificate chains.
+const CERTIFICATE_SUFFIX: &str = ".crt.pem";
+
+/// File name suffix of PEM encoded private keys.
+const PRIVATE_KEY_SUFFIX: &str = ".key.pem";
+
+/// Serves certificates from a directory, selected by the server name the client
+/// requested (SNI).
+///
+/// Every `<name>.crt.pem` in the directory is loaded together with the private
+/// key in `<name>.key.pem`, other files are ignored. Certificates are indexed
+/// by the DNS names of their subject alternative names, including wildcards
+/// like `*.example.com`. Exact names take precedence over wildcards, otherwise
+/// the first certificate in file name order wins. If no certificate matches,
+/// the one for the [`default()`](Self::default()) name is served.
+///
+/// The directory is checked for changes every
+/// [`interval()`](Self::interval()) by [`run()`](Self::run()). All
+/// certificates are validated before they are swapped in, otherwise the
+/// current ones are kept. The outcome of every reload is reported to the
+/// callback set with [`on_reload()`](Self::on_reload()).
+///
+/// All clones share the loaded certificates.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::net::SocketAddr;
+/// # use std::time::Duration;
+/// # use axum::{routing, Router};
+/// use axum_server_dual_protocol::CertificateStore;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let app = Router::new().route("/", routing::get(|| async { "Hello, world!" }));
+/// # let address = SocketAddr::from(([127, 0, 0, 1], 0));
+/// let store = CertificateStore::new("/etc/certificates")
+/// 	.default("example.com")
+/// 	.on_reload(|result| {
+/// 		if let Err(error) = result {
+/// 			eprintln!("{error}");
+/// 		}
+/// 	});
+/// let config = store.rustls_config().await?;
+///
+/// tokio::spawn(async move { store.run().await });
+///
+/// axum_server_dual_protocol::bind_dual_protocol(address, config)
+/// 	.serve(app.into_make_service())
+/// 	.await?;
+/// #
+/// # Ok(())
+/// # }
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "certificate-store")))]
+#[derive(Clone)]
+pub struct CertificateStore {
+	/// Directory the certificates are loaded from.
+	directory: PathBuf,
+	/// Name whose certificate is served if no other matches.
+	default: Option<String>,
+	/// How often to check the directory for changes.
+	interval: Duration,
+	/// Receives the outcome of every reload.
+	callback: Option<Callback<CertificateStoreError>>,
+	/// Currently loaded certificates.
+	certificates: Arc<Mutex<Arc<Certificates>>>,
+}
+
+/// Contents of the certificate and private key files, by file name.
+type Files = BTreeMap<String, Vec<u8>>;
+
+impl Debug for CertificateStore {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter
+			.debug_struct("CertificateStore")
+			.field("directory", &self.directory)
+			.field("default", &self.default)
+			.field("interval", &self.interval)
+			.field("certificates", &self.certificates)
+			.finish_non_exhaustive()
+	}
+}
+
+impl CertificateStore {
+	/// Creates a new [`CertificateStore`] for the given directory.
+	///
+	/// Nothing is loaded until [`reload()`](Self::reload()),
+	/// [`rustls_config()`](Self::rustls_config()) or [`run()`](Self::run()) is
+	/// called. The directory is checked for changes every 5 seconds by
+	/// default.
+	pub fn new<Directory: Into<PathBuf>>(directory: Directory) -> Self {
+		Self {
+			directory: directory.into(),
+			default: None,
+			interval: Duration::from_secs(5),
+			callback: None,
+			certificates: Arc::default(),
+		}
+	}
+
+	/// Sets the name whose certificate is served if the client didn't request
+	/// a server name or no certificate matches it. Loading fails if no
+	/// certificate matches this name.
+	///
+	/// By default the handshake fails in that case.
+	#[must_use]
+	pub fn default<Name: Into<String>>(mut self, name: Name) -> Self {
+		self.default = Some(name.into());
+		self
+	}
+
+	/// Sets how often the directory is checked for changes. The contents of
+	/// all certificate and private key files are compared.
+	///
+	/// # Panics
+	///
+	/// If `interval` is zero.
+	#[must_use]
+	pub fn interval(mut self, interval: Duration) -> Self {
+		assert!(!interval.is_zero(), "`interval` must be non-zero");
+		self.interval = interval;
+		self
+	}
+
+	/// Sets the callback receiving the outcome of every reload by
+	/// [`run()`](Self::run()).
+	#[must_use]
+	pub fn on_reload<Callback>(mut self, callback: Callback) -> Self
+	where
+		Callback: 'static + Fn(Result<(), CertificateStoreError>) + Send + Sync,
+	{
+		self.callback = Some(Arc::new(callback));
+		self
+	}
+
+	/// Loads the certificates and creates a [`RustlsConfig`] serving them.
+	///
+	/// # Errors
+	///
+	/// See [`reload()`](Self::reload()).
+	pub async fn rustls_config(&self) -> Result<RustlsConfig, CertificateStoreError> {
+		self.reload().await?;
+
+		let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+			.with_safe_default_protocol_versions()
+			.map_err(|error| CertificateStoreError::Crypto(error.into()))?
+			.with_no_client_auth()
+			.with_cert_resolver(Arc::new(self.clone()));
+		config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+
+		Ok(RustlsConfig::from_config(Arc::new(config)))
+	}
+
+	/// Loads all certificates from the directory, replacing the current ones.
+	///
+	/// # Errors
+	///
+	/// If the directory can't be read, any certificate or private key is
+	/// invalid or missing or no certificate matches the
+	/// [`default()`](Self::default()) name. The current certificates are kept
+	/// in that case.
+	pub async fn reload(&self) -> Result<(), CertificateStoreError> {
+		let files = self.read().await?;
+
+		self.load(&files)
+	}
+
+	/// Load the certificates and reload them whenever the directory changes,
+	/// reporting the outcome to the callback.
+	pub async fn run(&self) -> Infallible {
+		watch::watch(
+			self.interval,
+			|| self.read(),
+			|files| self.load(files),
+			|_| Poll::Pending,
+			self.callback.as_ref(),
+		)
+		.await
+	}
+
+	/// Reads all certificate and private key files in the directory.
+	async fn read(&self) -> io::Result<Files> {
+		let mut files = Files::new();
+		let mut entries = fs::read_dir(&self.directory).await?;
+
+		while let Some(entry) = entries.next_entry().await? {
+			let Ok(name) = entry.file_name().into_string() else {
+				continue;
+			};
+
+			if name.ends_with(CERTIFICATE_SUFFIX) || name.ends_with(PRIVATE_KEY_SUFFIX) {
+				let _ = files.insert(name, fs::read(entry.path()).await?);
+			}
+		}
+
+		Ok(files)
+	}
+
+	/// Indexes the certificates in `files` and swaps them in.
+	fn load(&self, files: &Files) -> Result<(), CertificateStoreError> {
+		let mut certificates = Certificates::default();
+
+		for (file, certificate) in files {
+			let Some(name) = file.strip_suffix(CERTIFICATE_SUFFIX) else {
+				continue;
+			};
+
+			let private_key = format!("{name}{PRIVATE_KEY_SUFFIX}");
+			let certified_key = files
+				.get(&private_key)
+				.ok_or_else(|| Box::from(format!("missing private key `{private_key}`")))
+				.and_then(|private_key| certified_key(certificate, private_key))
+				.map_err(|error| CertificateStoreError::Invalid {
+					path: self.directory.join(file),
+					error,
+				})?;
+
+			certificates.insert(certified_key);
+		}
+
+		if let Some(default) = &self.default {
+			let certificate = certificates
+				.get(default)
+				.ok_or_else(|| CertificateStoreError::MissingDefault(default.clone()))?;
+			certificates.default = Some(Arc::clone(certificate));
+		}
+
+		*self
+			.certificates
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = Arc::new(certificates);
+
+		Ok(())
+	}
+}
+
+impl ResolvesServerCert for CertificateStore {
+	fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+		let certificates = Arc::clone(
+			&self
+				.certificates
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner),
+		);
+
+		client_hello
+			.server_name()
+			.and_then(|name| certificates.get(name))
+			.or(certificates.default.as_ref())
+			.cloned()
+	}
+}
+
+/// Certificates indexed by their DNS names.
+#[derive(Default)]
+struct Certificates {
+	/// Certificates by exact DNS name.
+	names: HashMap<String, Arc<CertifiedKey>>,
+	/// Certificates by the parent domain of their wildcard DNS name, e.g.
+	/// `example.com` for `*.example.com`.
+	wildcards: HashMap<String, Arc<CertifiedKey>>,
+	/// Certificate served if no other matches.
+	default: Option<Arc<CertifiedKey>>,
+}
+
+impl Debug for Certificates {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter
+			.debug_struct("Certificates")
+			.field("names", &self.names.keys())
+			.field("wildcards", &self.wildcards.keys())
+			.field("default", &self.default.is_some())
+			.finish()
+	}
+}
+
+impl Certificates {
+	/// Indexes `certificate` under all its DNS names not taken yet.
+	fn insert(&mut self, (certificate, names): (Arc<CertifiedKey>, Vec<String>)) {
+		for name in names {
+			let (index, name) = match name.strip_prefix("*.") {
+				Some(parent) => (&mut self.wildcards, parent.to_owned()),
+				None => (&mut self.names, name),
+			};
+
+			let _ = index
+				.entry(name)
+				.or_insert_with(|| Arc::clone(&certificate));
+		}
+	}
+
+	/// Returns the certificate matching `name`, preferring exact matches over
+	/// wildcards.
+	fn get(&self, name: &str) -> Option<&Arc<CertifiedKey>> {
+		let name = name.to_ascii_lowercase();
+
+		self.names.get(&name).or_else(|| {
+			let (_, parent) = name.split_once('.')?;
+			self.wildcards.get(parent)
+		})
+	}
+}
+
+/// Creates a [`CertifiedKey`] from a PEM encoded certificate chain and private
+/// key, returning it together with the DNS names of the certificate.
+fn certified_key(
+	certificate: &[u8],
+	private_key: &[u8],
+) -> Result<(Arc<CertifiedKey>, Vec<String>), Box<dyn Error + Send + Sync>> {
+	let chain = CertificateDer::pem_slice_iter(certificate).collect::<Result<Vec<_>, _>>()?;
+	let leaf = chain.first().ok_or("no certificate found")?;
+
+	let (_, leaf) = x509_parser::parse_x509_certificate(leaf)?;
+	let names: Vec<_> = leaf
+		.subject_alternative_name()?
+		.into_iter()
+		.flat_map(|extension| &extension.value.general_names)
+		.filter_map(|name| match name {
+			GeneralName::DNSName(name) => Some(name.to_ascii_lowercase()),
+			_ => None,
+		})
+		.collect();
+
+	if names.is_empty() {
+		return Err("certificate has no DNS names".into());
+	}
+
+	let private_key = PrivateKeyDer::from_pem_slice(private_key)?;
+	let certified_key = CertifiedKey::new(chain, any_supported_type(&private_key)?);
+	certified_key.keys_match()?;
+
+	Ok((Arc::new(certified_key), names))
+}
+
+/// Reason why [`CertificateStore`] failed to load the certificates.
+#[cfg_attr(docsrs, doc(cfg(feature = "certificate-store")))]
+#[derive(Debug)]
+pub enum CertificateStoreError {
+	/// Reading the directory failed.
+	Io(io::Error),
+	/// A certificate or private key is invalid, they don't match or the private
+	/// key is missing.
+	Invalid {
+		/// The certificate file.
+		path: PathBuf,
+		/// The reason.
+		error: Box<dyn Error + Send + Sync>,
+	},
+	/// No certificate matches the [default
+	/// name](CertificateStore::default()).
+	MissingDefault(String),
+	/// Creating the [`ServerConfig`] failed.
+	Crypto(Box<dyn Error + Send + Sync>),
+}
+
+impl Display for CertificateStoreError {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Io(error) => write!(formatter, "failed to read the certificates: {error}"),
+			Self::Invalid { path, error } => {
+				write!(
+					formatter,
+					"invalid certificate `{}`: {error}",
+					path.display()
+				)
+			}
+			Self::MissingDefault(name) => {
+				write!(formatter, "no certificate for the default name `{name}`")
+			}
+			Self::Crypto(error) => write!(formatter, "cryptographic operation failed: {error}"),
+		}
+	}
+}
+
+impl Error for CertificateStoreError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::Io(error) => Some(error),
+			Self::Invalid { error, .. } | Self::Crypto(error) => Some(error.as_ref()),
+			Self::MissingDefault(_) => None,
+		}
+	}
+}
+
+impl From<io::Error> for CertificateStoreError {
+	fn from(error: io::Error) -> Self {
+		Self::Io(error)
+	}
+}
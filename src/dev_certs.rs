@@ -0,0 +1,354 @@
+//! Development certificates.
+//!
+//! See [`DevCerts`].
+
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum_server::tls_rustls::RustlsConfig;
+use rcgen::{
+	BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+	ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose,
+};
+use rustls::client::{verify_server_cert_signed_by_trust_anchor, verify_server_name};
+use rustls::crypto::aws_lc_rs::default_provider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::server::ParsedCertificate;
+use rustls::RootCertStore;
+use time::OffsetDateTime;
+use tokio::fs;
+
+use crate::file;
+
+/// Common name of the certificate authority.
+const CA_NAME: &str = "axum-server-dual-protocol Development CA";
+
+/// How long the certificate authority is valid for.
+const CA_VALIDITY: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+
+/// How long certificates are valid for.
+const VALIDITY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// How long before expiring certificates are replaced.
+const RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Locally trusted certificates for development.
+///
+/// Creates a certificate authority and a certificate for the given hostnames,
+/// signed by it, and stores them in a directory. Both are re-used by later
+/// runs, so the certificate authority only has to be trusted once, e.g. by
+/// adding [`DevCerts::ca_path()`] to the trust store of the browser or system.
+/// The certificate is replaced if the hostnames change or it is about to
+/// expire.
+///
+/// Anybody with access to the private key of the certificate authority can
+/// impersonate any website to a system trusting it. It must never be shared or
+/// used in production.
+///
+/// # Example
+///
+/// ```
+/// # use std::net::SocketAddr;
+/// # use axum::{routing, Router};
+/// use axum_server_dual_protocol::DevCerts;
+///
+/// # async fn test() -> Result<(), Box<dyn std::error::Error>> {
+/// # let app = Router::new().route("/", routing::get(|| async { "Hello, world!" }));
+/// # let address = SocketAddr::from(([127, 0, 0, 1], 0));
+/// let config = DevCerts::new(["localhost", "127.0.0.1", "::1"])
+/// 	.directory("target/dev-certs")
+/// 	.rustls_config()
+/// 	.await?;
+///
+/// axum_server_dual_protocol::bind_dual_protocol(address, config)
+/// 	.serve(app.into_make_service())
+/// 	.await?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "dev-certs")))]
+#[derive(Clone, Debug)]
+pub struct DevCerts {
+	/// Hostnames or IP addresses the certificate is issued for.
+	hostnames: Vec<String>,
+	/// Directory to store the certificate authority and certificate in.
+	directory: PathBuf,
+}
+
+impl DevCerts {
+	/// Creates a new [`DevCerts`] for the given hostnames or IP addresses.
+	///
+	/// The directory defaults to `dev-certs`, relative to the current
+	/// directory.
+	pub fn new<Hostnames, Hostname>(hostnames: Hostnames) -> Self
+	where
+		Hostnames: IntoIterator<Item = Hostname>,
+		Hostname: Into<String>,
+	{
+		Self {
+			hostnames: hostnames.into_iter().map(Into::into).collect(),
+			directory: PathBuf::from("dev-certs"),
+		}
+	}
+
+	/// Sets the directory to store the certificate authority and certificate
+	/// in. It is created if it doesn't exist.
+	#[must_use]
+	pub fn directory<Directory: Into<PathBuf>>(mut self, directory: Directory) -> Self {
+		self.directory = directory.into();
+		self
+	}
+
+	/// Returns the path to the PEM encoded certificate of the certificate
+	/// authority, which has to be trusted to avoid certificate errors.
+	#[must_use]
+	pub fn ca_path(&self) -> PathBuf {
+		self.directory.join("ca.crt.pem")
+	}
+
+	/// Create a [`RustlsConfig`] from the stored certificate, creating the
+	/// certificate authority and certificate first if necessary. Prints
+	/// [`DevCerts::ca_path()`] to `stderr`.
+	///
+	/// # Errors
+	///
+	/// - If a hostname is neither a valid DNS name nor an IP address.
+	/// - If the directory can't be accessed.
+	/// - If the stored certificate authority is invalid.
+	#[allow(clippy::print_stderr)]
+	pub async fn rustls_config(&self) -> Result<RustlsConfig, DevCertsError> {
+		let hostnames = self
+			.hostnames
+			.iter()
+			.map(|hostname| {
+				ServerName::try_from(hostname.as_str())
+					.map_err(|_| DevCertsError::InvalidHostname(hostname.clone()))
+			})
+			.collect::<Result<Vec<_>, _>>()?;
+
+		fs::create_dir_all(&self.directory).await?;
+
+		let (ca, ca_key) = self.ca().await?;
+		let (certificate_path, private_key_path) = self.certificate_paths();
+
+		let certificate = if fs::try_exists(&certificate_path).await? {
+			Some(fs::read(&certificate_path).await?)
+		} else {
+			None
+		};
+
+		let certificate = match certificate {
+			Some(certificate) if is_valid(ca.der(), &certificate, &hostnames)? => certificate,
+			_ => {
+				let private_key = KeyPair::generate().map_err(crypto)?;
+				let certificate = self
+					.certificate_params()?
+					.signed_by(&private_key, &ca, &ca_key)
+					.map_err(crypto)?;
+
+				// The certificate is written last, it determines if both have to be replaced.
+				file::write_private_key(&private_key_path, private_key.serialize_pem().as_bytes())
+					.await?;
+				file::write(&certificate_path, certificate.pem().as_bytes()).await?;
+
+				certificate.pem().into_bytes()
+			}
+		};
+
+		let private_key = fs::read(private_key_path).await?;
+
+		eprintln!(
+			"Serving development certificates, trust the certificate authority at `{}` to avoid \
+			 certificate errors.",
+			self.ca_path().display()
+		);
+
+		Ok(RustlsConfig::from_pem(certificate, private_key).await?)
+	}
+
+	/// Loads the stored certificate authority or creates a new one.
+	async fn ca(&self) -> Result<(Certificate, KeyPair), DevCertsError> {
+		let certificate_path = self.ca_path();
+		let private_key_path = self.directory.join("ca.key.pem");
+
+		// The certificate is written last, so the private key exists if it does.
+		if fs::try_exists(&certificate_path).await? {
+			let private_key =
+				KeyPair::from_pem(&fs::read_to_string(&private_key_path).await?).map_err(crypto)?;
+			let stored = CertificateDer::from_pem_file(&certificate_path).map_err(crypto)?;
+
+			if !contains_public_key(&stored, &private_key) {
+				return Err(crypto(
+					"certificate authority doesn't match its private key",
+				));
+			}
+
+			// Only the distinguished name and the key are used to sign certificates, so
+			// this is equivalent to the stored certificate.
+			let certificate = ca_params()?.self_signed(&private_key).map_err(crypto)?;
+
+			Ok((certificate, private_key))
+		} else {
+			let private_key = KeyPair::generate().map_err(crypto)?;
+			let mut params = ca_params()?;
+			(params.not_before, params.not_after) = validity(CA_VALIDITY)?;
+			let certificate = params.self_signed(&private_key).map_err(crypto)?;
+
+			file::write_private_key(&private_key_path, private_key.serialize_pem().as_bytes())
+				.await?;
+			file::write(&certificate_path, certificate.pem().as_bytes()).await?;
+
+			Ok((certificate, private_key))
+		}
+	}
+
+	/// Returns the paths to the certificate and private key.
+	fn certificate_paths(&self) -> (PathBuf, PathBuf) {
+		let name = self
+			.hostnames
+			.first()
+			.map_or("certificate", String::as_str)
+			.replace(':', "_");
+
+		(
+			self.directory.join(format!("{name}.crt.pem")),
+			self.directory.join(format!("{name}.key.pem")),
+		)
+	}
+
+	/// Returns the [`CertificateParams`] of the certificate.
+	fn certificate_params(&self) -> Result<CertificateParams, DevCertsError> {
+		let mut params = CertificateParams::new(self.hostnames.clone()).map_err(crypto)?;
+		params.distinguished_name = DistinguishedName::new();
+
+		if let Some(hostname) = self.hostnames.first() {
+			params.distinguished_name.push(DnType::CommonName, hostname);
+		}
+
+		params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+		params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+		params.use_authority_key_identifier_extension = true;
+		(params.not_before, params.not_after) = validity(VALIDITY)?;
+
+		Ok(params)
+	}
+}
+
+/// Returns the [`CertificateParams`] of the certificate authority, without
+/// validity.
+fn ca_params() -> Result<CertificateParams, DevCertsError> {
+	let mut params = CertificateParams::new([]).map_err(crypto)?;
+	params.distinguished_name = DistinguishedName::new();
+	params.distinguished_name.push(DnType::CommonName, CA_NAME);
+	params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+	params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+
+	Ok(params)
+}
+
+/// Returns a validity period of the given length, starting now.
+fn validity(length: Duration) -> Result<(OffsetDateTime, OffsetDateTime), DevCertsError> {
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_err(crypto)?;
+	let timestamp = |time: Duration| {
+		OffsetDateTime::from_unix_timestamp(i64::try_from(time.as_secs()).map_err(crypto)?)
+			.map_err(crypto)
+	};
+
+	Ok((timestamp(now)?, timestamp(now + length)?))
+}
+
+/// Returns `true` if the PEM encoded certificate is signed by the certificate
+/// authority, valid for all hostnames and doesn't expire soon.
+fn is_valid(
+	ca: &CertificateDer<'_>,
+	certificate: &[u8],
+	hostnames: &[ServerName<'_>],
+) -> Result<bool, DevCertsError> {
+	let Ok(certificate) = CertificateDer::from_pem_slice(certificate) else {
+		return Ok(false);
+	};
+	let Ok(certificate) = ParsedCertificate::try_from(&certificate) else {
+		return Ok(false);
+	};
+
+	let mut roots = RootCertStore::empty();
+	roots.add(ca.clone().into_owned()).map_err(crypto)?;
+
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map_err(crypto)?;
+	let signed = verify_server_cert_signed_by_trust_anchor(
+		&certificate,
+		&roots,
+		&[],
+		UnixTime::since_unix_epoch(now + RENEW_BEFORE),
+		default_provider().signature_verification_algorithms.all,
+	);
+
+	Ok(signed.is_ok()
+		&& hostnames
+			.iter()
+			.all(|hostname| verify_server_name(&certificate, hostname).is_ok()))
+}
+
+/// Returns `true` if the certificate contains the public key of
+/// `private_key`.
+fn contains_public_key(certificate: &CertificateDer<'_>, private_key: &KeyPair) -> bool {
+	let public_key = private_key.public_key_raw();
+
+	certificate
+		.windows(public_key.len())
+		.any(|window| window == public_key)
+}
+
+/// Reason why [`DevCerts`] failed to create a [`RustlsConfig`].
+#[cfg_attr(docsrs, doc(cfg(feature = "dev-certs")))]
+#[derive(Debug)]
+pub enum DevCertsError {
+	/// A hostname is neither a valid DNS name nor an IP address.
+	InvalidHostname(String),
+	/// Generating or loading keys or certificates failed.
+	Crypto(Box<dyn Error + Send + Sync>),
+	/// Accessing the directory failed.
+	Io(io::Error),
+}
+
+impl Display for DevCertsError {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::InvalidHostname(hostname) => write!(formatter, "invalid hostname `{hostname}`"),
+			Self::Crypto(error) => write!(formatter, "cryptographic operation failed: {error}"),
+			Self::Io(error) => write!(
+				formatter,
+				"failed to access the development certificates: {error}"
+			),
+		}
+	}
+}
+
+impl Error for DevCertsError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::InvalidHostname(_) => None,
+			Self::Crypto(error) => Some(error.as_ref()),
+			Self::Io(error) => Some(error),
+		}
+	}
+}
+
+impl From<io::Error> for DevCertsError {
+	fn from(error: io::Error) -> Self {
+		Self::Io(error)
+	}
+}
+
+/// Creates a [`DevCertsError::Crypto`].
+fn crypto<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> DevCertsError {
+	DevCertsError::Crypto(error.into())
+}
@@ -2,28 +2,45 @@
 //!
 //! See [`bind_dual_protocol()`] and [`DualProtocolAcceptor`].
 
+use std::collections::BTreeMap;
+use std::convert::Infallible;
 use std::fmt::{self, Debug, Formatter};
 use std::future::Future;
 use std::io::ErrorKind;
 use std::net::{SocketAddr, TcpListener};
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
 use std::{io, slice};
 
 use axum_server::accept::Accept;
+#[cfg(feature = "openssl")]
+use axum_server::tls_openssl::{OpenSSLAcceptor, OpenSSLConfig};
 use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
 use axum_server::Server;
 use bytes::Bytes;
 use http::{Request, Response};
-use http_body_util::{Either as BodyEither, Empty};
+use http_body_util::{Either as BodyEither, Full};
 use pin_project::pin_project;
 use tokio::io::ReadBuf;
 use tokio::net::TcpStream;
+#[cfg(feature = "openssl")]
+use tokio_openssl::SslStream;
 use tokio_rustls::server::TlsStream;
 use tokio_util::either::Either as TokioEither;
+use tower_layer::Layer;
 use tower_service::Service as TowerService;
 
-use crate::UpgradeHttp;
+#[cfg(feature = "acme")]
+use crate::Acme;
+use crate::{
+	BadRequest, ForwardedProtocolLayer, StrictTransportSecurityLayer, UpgradeHttp,
+	UpgradeHttpFuture, UpgradeHttpLayer,
+};
+
+/// ALPN protocol ID of ACME TLS-ALPN-01 challenges, see
+/// [RFC 8737 Section 6.2](https://www.rfc-editor.org/rfc/rfc8737#section-6.2).
+pub(crate) const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
 
 /// Create a [`Server`] that will bind to the provided address, accepting both
 /// HTTP and HTTPS on the same port.
@@ -49,7 +66,37 @@ pub fn from_tcp_dual_protocol(
 	Server::from_tcp(listener).acceptor(acceptor)
 }
 
-/// Supplies configuration methods for [`Server`] with [`DualProtocolAcceptor`].
+/// Create a [`Server`] that will bind to the provided address, accepting both
+/// HTTP and HTTPS on the same port, using OpenSSL for HTTPS.
+#[cfg(feature = "openssl")]
+#[cfg_attr(docsrs, doc(cfg(feature = "openssl")))]
+#[must_use]
+pub fn bind_dual_protocol_openssl(
+	address: SocketAddr,
+	config: OpenSSLConfig,
+) -> Server<DualProtocolAcceptor<OpenSSLAcceptor>> {
+	let acceptor = DualProtocolAcceptor::with_tls(OpenSSLAcceptor::new(config));
+
+	Server::bind(address).acceptor(acceptor)
+}
+
+/// Create a [`Server`] from an existing [`TcpListener`], accepting both
+/// HTTP and HTTPS on the same port, using OpenSSL for HTTPS.
+#[cfg(feature = "openssl")]
+#[cfg_attr(docsrs, doc(cfg(feature = "openssl")))]
+#[must_use]
+pub fn from_tcp_dual_protocol_openssl(
+	listener: TcpListener,
+	config: OpenSSLConfig,
+) -> Server<DualProtocolAcceptor<OpenSSLAcceptor>> {
+	let acceptor = DualProtocolAcceptor::with_tls(OpenSSLAcceptor::new(config));
+
+	Server::from_tcp(listener).acceptor(acceptor)
+}
+
+/// Supplies configuration methods for [`Server`] with [`DualProtocolAcceptor`],
+/// [`SplitProtocolServer`](crate::SplitProtocolServer) and
+/// [`MultiAddressServer`](crate::MultiAddressServer).
 ///
 /// See [`bind_dual_protocol()`] for easy creation.
 pub trait ServerExt {
@@ -58,13 +105,103 @@ pub trait ServerExt {
 	/// See [`UpgradeHttp`] for more details.
 	#[must_use]
 	fn set_upgrade(self, upgrade: bool) -> Self;
+
+	/// Automatically upgrade HTTP connections to HTTPS with the given
+	/// configuration, including its
+	/// [fallback](UpgradeHttpLayer::fallback()).
+	///
+	/// See [`UpgradeHttp`] for more details.
+	#[must_use]
+	fn set_upgrade_layer<Fallback: Clone>(
+		self,
+		layer: UpgradeHttpLayer<Fallback>,
+	) -> Self::WithFallback<Fallback>;
+
+	/// Set the [`Protocol`] of requests from trusted proxies according to their
+	/// forwarding headers. This is applied before [`UpgradeHttp`].
+	///
+	/// See [`ForwardedProtocol`](crate::ForwardedProtocol) for more details.
+	#[must_use]
+	fn set_forwarded_protocol(self, layer: ForwardedProtocolLayer) -> Self;
+
+	/// Set the `Strict-Transport-Security` header on HTTPS responses.
+	///
+	/// See [`StrictTransportSecurity`](crate::StrictTransportSecurity) for more
+	/// details.
+	#[must_use]
+	fn set_strict_transport_security(self, layer: StrictTransportSecurityLayer) -> Self;
+
+	/// Answer ACME HTTP-01 challenges on the plain path. This is applied before
+	/// [`UpgradeHttp`].
+	///
+	/// See [`Acme`](crate::Acme) for more details.
+	#[cfg(feature = "acme")]
+	#[cfg_attr(docsrs, doc(cfg(feature = "acme")))]
+	#[must_use]
+	fn set_acme(self, acme: Acme) -> Self;
+
+	/// Hand TLS connections negotiating the given ALPN protocol to `handler`
+	/// instead of the app.
+	///
+	/// See [`DualProtocolAcceptor::set_alpn_handler()`] for more details.
+	#[must_use]
+	fn set_alpn_handler<Id, Handler, Fut>(self, protocol: Id, handler: Handler) -> Self
+	where
+		Id: Into<Vec<u8>>,
+		Handler: 'static + Fn(Self::TlsStream, PeerAddr) -> Fut + Send + Sync,
+		Fut: 'static + Future<Output = ()> + Send;
+
+	/// Stream of the TLS [`Accept`]or, which
+	/// [ALPN handlers](Self::set_alpn_handler()) receive.
+	type TlsStream;
+
+	/// This server with a different [fallback](UpgradeHttpLayer::fallback())
+	/// for [`UpgradeHttp`], returned by
+	/// [`set_upgrade_layer()`](Self::set_upgrade_layer()).
+	type WithFallback<Fallback>: ServerExt<TlsStream = Self::TlsStream>;
 }
 
-impl ServerExt for Server<DualProtocolAcceptor> {
+impl<Tls: TlsAccept, Fallback> ServerExt for Server<DualProtocolAcceptor<Tls, Fallback>> {
+	type TlsStream = Tls::Stream;
+	type WithFallback<NewFallback> = Server<DualProtocolAcceptor<Tls, NewFallback>>;
+
 	fn set_upgrade(mut self, upgrade: bool) -> Self {
 		self.get_mut().set_upgrade(upgrade);
 		self
 	}
+
+	fn set_upgrade_layer<NewFallback: Clone>(
+		self,
+		layer: UpgradeHttpLayer<NewFallback>,
+	) -> Self::WithFallback<NewFallback> {
+		self.map(|acceptor| acceptor.set_upgrade_layer(layer))
+	}
+
+	fn set_forwarded_protocol(mut self, layer: ForwardedProtocolLayer) -> Self {
+		self.get_mut().set_forwarded_protocol(layer);
+		self
+	}
+
+	fn set_strict_transport_security(mut self, layer: StrictTransportSecurityLayer) -> Self {
+		self.get_mut().set_strict_transport_security(layer);
+		self
+	}
+
+	#[cfg(feature = "acme")]
+	fn set_acme(mut self, acme: Acme) -> Self {
+		self.get_mut().set_acme(acme);
+		self
+	}
+
+	fn set_alpn_handler<Id, Handler, Fut>(mut self, protocol: Id, handler: Handler) -> Self
+	where
+		Id: Into<Vec<u8>>,
+		Handler: 'static + Fn(Tls::Stream, PeerAddr) -> Fut + Send + Sync,
+		Fut: 'static + Future<Output = ()> + Send,
+	{
+		self.get_mut().set_alpn_handler(protocol, handler);
+		self
+	}
 }
 
 /// The protocol used by this connection. See
@@ -77,115 +214,493 @@ pub enum Protocol {
 	Plain,
 }
 
+/// The address of the peer of this connection. See
+/// [`Request::extensions()`](Request::extensions()).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// The local address this connection was accepted on. See
+/// [`Request::extensions()`](Request::extensions()).
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct LocalAddr(pub SocketAddr);
+
+/// TLS [`Accept`]or usable with [`DualProtocolAcceptor`].
+///
+/// Names the stream of the TLS [`Accept`]or independently of the
+/// [`Service`](TowerService), so [ALPN
+/// handlers](DualProtocolAcceptor::set_alpn_handler()) are checked against it
+/// at compile time.
+pub trait TlsAccept {
+	/// The TLS stream, which has to be the same as the
+	/// [`Accept::Stream`](Accept::Stream) of the TLS [`Accept`]or.
+	type Stream: 'static + AlpnStream + Send;
+}
+
+impl TlsAccept for RustlsAcceptor {
+	type Stream = TlsStream<TcpStream>;
+}
+
+#[cfg(feature = "openssl")]
+#[cfg_attr(docsrs, doc(cfg(feature = "openssl")))]
+impl TlsAccept for OpenSSLAcceptor {
+	type Stream = SslStream<TcpStream>;
+}
+
+/// TLS stream that reports the protocol negotiated with ALPN.
+///
+/// Required from the streams of TLS [`Accept`]ors used with
+/// [`DualProtocolAcceptor`]. Connections that negotiated `acme-tls/1` only
+/// answer ACME TLS-ALPN-01 challenges and are closed after the handshake. See
+/// [`DualProtocolAcceptor::set_alpn_handler()`] for handling other protocols.
+pub trait AlpnStream {
+	/// Returns the protocol negotiated with ALPN, if any.
+	fn alpn_protocol(&self) -> Option<&[u8]>;
+}
+
+impl<Io> AlpnStream for TlsStream<Io> {
+	fn alpn_protocol(&self) -> Option<&[u8]> {
+		self.get_ref().1.alpn_protocol()
+	}
+}
+
+#[cfg(feature = "openssl")]
+#[cfg_attr(docsrs, doc(cfg(feature = "openssl")))]
+impl<Io> AlpnStream for SslStream<Io> {
+	fn alpn_protocol(&self) -> Option<&[u8]> {
+		self.ssl().selected_alpn_protocol()
+	}
+}
+
 /// Simultaneous HTTP and HTTPS [`Accept`]or.
+///
+/// HTTPS connections are handed to the TLS [`Accept`]or, which defaults to
+/// [`RustlsAcceptor`]. See [`DualProtocolAcceptor::with_tls()`] to use a
+/// different one, e.g. [`LazyRustlsAcceptor`](crate::LazyRustlsAcceptor) to
+/// resolve the [`ServerConfig`](rustls::ServerConfig) asynchronously.
 #[derive(Debug, Clone)]
-pub struct DualProtocolAcceptor {
-	/// [`RustlsAcceptor`] re-used to handle HTTPS requests.
-	rustls: RustlsAcceptor,
+pub struct DualProtocolAcceptor<Tls: TlsAccept = RustlsAcceptor, Fallback = BadRequest> {
+	/// TLS [`Accept`]or re-used to handle HTTPS requests.
+	tls: Tls,
 	/// Stores if HTTP connections should be automatically upgraded to HTTPS.
+	upgrade: bool,
+	/// Stores how HTTP connections are upgraded to HTTPS, if enabled.
 	///
 	/// See [`UpgradeHttp`] for more details.
-	upgrade: bool,
+	upgrade_layer: UpgradeHttpLayer<Fallback>,
+	/// Stores how to determine the [`Protocol`] of requests from trusted
+	/// proxies.
+	///
+	/// See [`ForwardedProtocol`](crate::ForwardedProtocol) for more details.
+	forwarded: Option<ForwardedProtocolLayer>,
+	/// Stores the `Strict-Transport-Security` header configuration.
+	///
+	/// See [`StrictTransportSecurity`](crate::StrictTransportSecurity) for more
+	/// details.
+	hsts: Option<StrictTransportSecurityLayer>,
+	/// Stores the [`Protocol`] all connections are assumed to use, if any.
+	protocol: Option<Protocol>,
+	/// Stores the [`Acme`] answering challenges on the plain path, if any.
+	#[cfg(feature = "acme")]
+	acme: Option<Acme>,
+	/// Stores the handlers for TLS connections by their ALPN protocol.
+	alpn: AlpnHandlers<Tls>,
 }
 
 impl DualProtocolAcceptor {
 	/// Create a new [`DualProtocolAcceptor`].
 	#[must_use]
 	pub fn new(config: RustlsConfig) -> Self {
+		Self::with_tls(RustlsAcceptor::new(config))
+	}
+}
+
+impl<Tls: TlsAccept> DualProtocolAcceptor<Tls> {
+	/// Create a new [`DualProtocolAcceptor`] handling HTTPS connections with
+	/// the given TLS [`Accept`]or.
+	///
+	/// The TLS [`Accept`]or has to pass through the
+	/// [`Service`](TowerService) it is given, like [`RustlsAcceptor`] does,
+	/// and implement [`TlsAccept`].
+	#[must_use]
+	pub fn with_tls(tls: Tls) -> Self {
 		Self {
-			rustls: RustlsAcceptor::new(config),
+			tls,
 			upgrade: false,
+			upgrade_layer: UpgradeHttpLayer::new(),
+			forwarded: None,
+			hsts: None,
+			protocol: None,
+			#[cfg(feature = "acme")]
+			acme: None,
+			alpn: AlpnHandlers::new(),
 		}
 	}
+}
 
-	/// Set if HTTP connections should be automatically upgraded to HTTPS.
+impl<Tls: TlsAccept, Fallback> DualProtocolAcceptor<Tls, Fallback> {
+	/// Returns a reference to the TLS [`Accept`]or.
+	pub const fn tls(&self) -> &Tls {
+		&self.tls
+	}
+
+	/// Set if HTTP connections should be automatically upgraded to HTTPS,
+	/// with the configuration from
+	/// [`set_upgrade_layer()`](Self::set_upgrade_layer()) if any.
 	///
 	/// See [`UpgradeHttp`] for more details.
 	pub fn set_upgrade(&mut self, upgrade: bool) {
 		self.upgrade = upgrade;
 	}
+
+	/// Automatically upgrade HTTP connections to HTTPS with the given
+	/// configuration, including its
+	/// [fallback](UpgradeHttpLayer::fallback()).
+	///
+	/// See [`UpgradeHttp`] for more details.
+	#[must_use]
+	pub fn set_upgrade_layer<NewFallback>(
+		self,
+		layer: UpgradeHttpLayer<NewFallback>,
+	) -> DualProtocolAcceptor<Tls, NewFallback> {
+		DualProtocolAcceptor {
+			tls: self.tls,
+			upgrade: true,
+			upgrade_layer: layer,
+			forwarded: self.forwarded,
+			hsts: self.hsts,
+			protocol: self.protocol,
+			#[cfg(feature = "acme")]
+			acme: self.acme,
+			alpn: self.alpn,
+		}
+	}
+
+	/// Set the [`Protocol`] of requests from trusted proxies according to their
+	/// forwarding headers. This is applied before [`UpgradeHttp`].
+	///
+	/// See [`ForwardedProtocol`](crate::ForwardedProtocol) for more details.
+	pub fn set_forwarded_protocol(&mut self, layer: ForwardedProtocolLayer) {
+		self.forwarded = Some(layer);
+	}
+
+	/// Set the `Strict-Transport-Security` header on HTTPS responses.
+	///
+	/// See [`StrictTransportSecurity`](crate::StrictTransportSecurity) for more
+	/// details.
+	pub fn set_strict_transport_security(&mut self, layer: StrictTransportSecurityLayer) {
+		self.hsts = Some(layer);
+	}
+
+	/// Set the [`Protocol`] all connections are assumed to use instead of
+	/// detecting it, e.g. if HTTP and HTTPS are served on separate ports.
+	/// Defaults to [`None`].
+	///
+	/// See [`bind_split_protocol()`](crate::bind_split_protocol()) for more
+	/// details.
+	pub fn set_protocol(&mut self, protocol: Option<Protocol>) {
+		self.protocol = protocol;
+	}
+
+	/// Answer ACME HTTP-01 challenges on the plain path. This is applied before
+	/// [`UpgradeHttp`].
+	///
+	/// See [`Acme`](crate::Acme) for more details.
+	#[cfg(feature = "acme")]
+	#[cfg_attr(docsrs, doc(cfg(feature = "acme")))]
+	pub fn set_acme(&mut self, acme: Acme) {
+		self.acme = Some(acme);
+	}
+
+	/// Hand TLS connections negotiating the given ALPN protocol to `handler`
+	/// instead of the app. Registering a handler for the same protocol again
+	/// replaces it.
+	///
+	/// The handler receives the established TLS stream and the [`PeerAddr`]
+	/// and the connection is closed when the returned [`Future`] resolves.
+	/// Connections negotiating any other or no protocol are served by the app
+	/// as usual.
+	///
+	/// Handlers run in their own task, so they don't hold up shutting down the
+	/// server through its [`Handle`](axum_server::Handle). They aren't
+	/// notified of it either, pass them a signal of your own instead, e.g. a
+	/// [`CancellationToken`](tokio_util::sync::CancellationToken):
+	///
+	/// ```
+	/// # use axum_server::tls_rustls::RustlsConfig;
+	/// # use axum_server_dual_protocol::{DualProtocolAcceptor, PeerAddr};
+	/// # use tokio::io::AsyncWriteExt;
+	/// # use tokio_util::sync::CancellationToken;
+	/// # fn test(config: RustlsConfig) {
+	/// let shutdown = CancellationToken::new();
+	/// let mut acceptor = DualProtocolAcceptor::new(config);
+	/// acceptor.set_alpn_handler("custom/1", move |mut stream, PeerAddr(peer)| {
+	/// 	let shutdown = shutdown.clone();
+	///
+	/// 	async move {
+	/// 		let _ = stream.write_all(format!("Hello, {peer}!").as_bytes()).await;
+	/// 		shutdown.cancelled().await;
+	/// 	}
+	/// });
+	/// # }
+	/// ```
+	///
+	/// The handler receives the stream of the TLS [`Accept`]or, see
+	/// [`TlsAccept`]. The TLS configuration has to offer the protocol as well,
+	/// e.g. with
+	/// [`ServerConfig::alpn_protocols`](rustls::ServerConfig::alpn_protocols).
+	pub fn set_alpn_handler<Id, Handler, Fut>(&mut self, protocol: Id, handler: Handler)
+	where
+		Id: Into<Vec<u8>>,
+		Handler: 'static + Fn(Tls::Stream, PeerAddr) -> Fut + Send + Sync,
+		Fut: 'static + Future<Output = ()> + Send,
+	{
+		self.alpn.insert(protocol.into(), handler);
+	}
+
+	/// Set the port HTTP connections are upgraded to, unless already
+	/// configured.
+	///
+	/// See [`UpgradeHttpLayer::redirect_port()`] for more details.
+	pub(crate) fn set_default_redirect_port(&mut self, port: u16) {
+		self.upgrade_layer.set_default_redirect_port(port);
+	}
 }
 
-impl<Service: Clone> Accept<TcpStream, Service> for DualProtocolAcceptor {
-	type Stream = TokioEither<TlsStream<TcpStream>, TcpStream>;
-	type Service = DualProtocolService<Service>;
-	type Future = DualProtocolAcceptorFuture<Service>;
+impl<Service, Tls, Fallback> Accept<TcpStream, Service> for DualProtocolAcceptor<Tls, Fallback>
+where
+	Service: Clone,
+	Fallback: Clone,
+	Tls: Accept<
+			TcpStream,
+			DualProtocolService<Service, Fallback>,
+			Stream = <Tls as TlsAccept>::Stream,
+			Service = DualProtocolService<Service, Fallback>,
+		> + TlsAccept
+		+ Clone,
+{
+	type Stream = TokioEither<<Tls as TlsAccept>::Stream, TcpStream>;
+	type Service = DualProtocolService<Service, Fallback>;
+	type Future = DualProtocolAcceptorFuture<Service, Tls, Fallback>;
 
 	fn accept(&self, stream: TcpStream, service: Service) -> Self::Future {
+		let forwarded = self.forwarded.clone();
+		let hsts = self.hsts.clone();
+
 		let service = if self.upgrade {
-			DualProtocolServiceBuilder::new_upgrade(service)
+			DualProtocolServiceBuilder::new_upgrade(
+				self.upgrade_layer.layer(service),
+				forwarded,
+				hsts,
+			)
 		} else {
-			DualProtocolServiceBuilder::new_service(service)
+			DualProtocolServiceBuilder::new_service(service, forwarded, hsts)
 		};
 
-		DualProtocolAcceptorFuture::new(stream, service, self.rustls.clone())
+		#[cfg(feature = "acme")]
+		let service = service.with_acme(self.acme.clone());
+
+		DualProtocolAcceptorFuture::new(
+			stream,
+			service,
+			self.tls.clone(),
+			self.protocol,
+			self.alpn.clone(),
+		)
+	}
+}
+
+/// Type-erased [`Future`] returned by ALPN handlers.
+type AlpnFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Type-erased ALPN handler for the TLS streams of `Tls`.
+type AlpnHandler<Tls> = dyn Fn(<Tls as TlsAccept>::Stream, PeerAddr) -> AlpnFuture + Send + Sync;
+
+/// Handlers for TLS connections by the protocol they negotiated with ALPN.
+struct AlpnHandlers<Tls: TlsAccept>(BTreeMap<Vec<u8>, Arc<AlpnHandler<Tls>>>);
+
+// `Tls` doesn't have to implement `Clone`.
+impl<Tls: TlsAccept> Clone for AlpnHandlers<Tls> {
+	fn clone(&self) -> Self {
+		Self(self.0.clone())
+	}
+}
+
+impl<Tls: TlsAccept> Debug for AlpnHandlers<Tls> {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter
+			.debug_set()
+			.entries(
+				self.0
+					.keys()
+					.map(|protocol| String::from_utf8_lossy(protocol)),
+			)
+			.finish()
+	}
+}
+
+impl<Tls: TlsAccept> AlpnHandlers<Tls> {
+	/// Create an empty [`AlpnHandlers`].
+	const fn new() -> Self {
+		Self(BTreeMap::new())
+	}
+
+	/// Register `handler` for the given protocol.
+	fn insert<Handler, Fut>(&mut self, protocol: Vec<u8>, handler: Handler)
+	where
+		Handler: 'static + Fn(Tls::Stream, PeerAddr) -> Fut + Send + Sync,
+		Fut: 'static + Future<Output = ()> + Send,
+	{
+		let handler: Arc<AlpnHandler<Tls>> =
+			Arc::new(move |stream, peer| Box::pin(handler(stream, peer)));
+		let _ = self.0.insert(protocol, handler);
+	}
+
+	/// Returns the handler for the given protocol, if any.
+	fn get(&self, protocol: &[u8]) -> Option<&AlpnHandler<Tls>> {
+		self.0.get(protocol).map(AsRef::as_ref)
 	}
 }
 
 /// [`Future`](Accept::Future) type for [`DualProtocolAcceptor`].
-#[derive(Debug)]
 #[pin_project(project = DualProtocolAcceptorFutureProj)]
-pub struct DualProtocolAcceptorFuture<Service: Clone>(
+pub struct DualProtocolAcceptorFuture<Service, Tls = RustlsAcceptor, Fallback = BadRequest>(
 	/// State. `enum` variants can't be private, so this solution was used to
 	/// hide implementation details.
 	#[pin]
-	FutureState<Service>,
-);
+	FutureState<Service, Tls, Fallback>,
+)
+where
+	Service: Clone,
+	Tls: Accept<TcpStream, DualProtocolService<Service, Fallback>> + TlsAccept;
+
+// Rust can't figure out the correct bounds.
+impl<Service, Tls, Fallback> Debug for DualProtocolAcceptorFuture<Service, Tls, Fallback>
+where
+	Service: Clone,
+	Tls: Accept<TcpStream, DualProtocolService<Service, Fallback>> + TlsAccept,
+	FutureState<Service, Tls, Fallback>: Debug,
+{
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter
+			.debug_tuple("DualProtocolAcceptorFuture")
+			.field(&self.0)
+			.finish()
+	}
+}
 
 /// State of accepting a new request for [`DualProtocolAcceptorFuture`].
-#[derive(Debug)]
 #[pin_project(project = FutuereStateProj)]
-enum FutureState<Service: Clone> {
+enum FutureState<Service, Tls, Fallback>
+where
+	Service: Clone,
+	Tls: Accept<TcpStream, DualProtocolService<Service, Fallback>> + TlsAccept,
+{
 	/// Peeking state, still trying to determine if the incoming request is HTTP
 	/// or HTTPS.
-	Peek(Option<PeekState<Service>>),
+	Peek(Option<PeekState<Service, Tls, Fallback>>),
 	/// HTTPS state, it was determined that the incoming request is HTTPS, now
-	/// the [`RustlsAcceptor`] has to be polled to completion.
-	Https(#[pin] <RustlsAcceptor as Accept<TcpStream, DualProtocolService<Service>>>::Future),
+	/// the TLS [`Accept`]or has to be polled to completion.
+	Https {
+		/// The TLS [`Accept`]or [`Future`].
+		#[pin]
+		future: Tls::Future,
+		/// The address of the peer of this connection.
+		peer: PeerAddr,
+		/// Handlers for non-HTTP protocols negotiated with ALPN.
+		alpn: AlpnHandlers<Tls>,
+	},
+}
+
+// Rust can't figure out the correct bounds.
+impl<Service, Tls, Fallback> Debug for FutureState<Service, Tls, Fallback>
+where
+	Service: Clone + Debug,
+	Fallback: Debug,
+	Tls: Accept<TcpStream, DualProtocolService<Service, Fallback>> + TlsAccept + Debug,
+	<Tls as Accept<TcpStream, DualProtocolService<Service, Fallback>>>::Future: Debug,
+{
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Peek(state) => formatter.debug_tuple("Peek").field(state).finish(),
+			Self::Https { future, peer, alpn } => formatter
+				.debug_struct("Https")
+				.field("future", future)
+				.field("peer", peer)
+				.field("alpn", alpn)
+				.finish(),
+		}
+	}
 }
 
 /// Data necessary to peek and proceed to the next state.
 #[derive(Debug)]
-struct PeekState<Service> {
+struct PeekState<Service, Tls: TlsAccept, Fallback> {
 	/// Transport.
 	stream: TcpStream,
 	/// User-provided [`Service`](TowerService)
-	service: DualProtocolServiceBuilder<Service>,
+	service: DualProtocolServiceBuilder<Service, Fallback>,
 	/// Used to proceed to the [`Https`](FutureState::Https) state if
 	/// necessary.
-	rustls: RustlsAcceptor,
+	tls: Tls,
+	/// If set, skip peeking and assume this [`Protocol`].
+	protocol: Option<Protocol>,
+	/// Handlers for non-HTTP protocols negotiated with ALPN.
+	alpn: AlpnHandlers<Tls>,
 }
 
-impl<Service: Clone> DualProtocolAcceptorFuture<Service> {
+impl<Service, Tls, Fallback> DualProtocolAcceptorFuture<Service, Tls, Fallback>
+where
+	Service: Clone,
+	Tls: Accept<TcpStream, DualProtocolService<Service, Fallback>> + TlsAccept,
+{
 	/// Create a new [`DualProtocolAcceptorFuture`] in the
 	/// [`Peek`](FutureState::Peek) state.
 	const fn new(
 		stream: TcpStream,
-		service: DualProtocolServiceBuilder<Service>,
-		rustls: RustlsAcceptor,
+		service: DualProtocolServiceBuilder<Service, Fallback>,
+		tls: Tls,
+		protocol: Option<Protocol>,
+		alpn: AlpnHandlers<Tls>,
 	) -> Self {
 		Self(FutureState::Peek(Some(PeekState {
 			stream,
 			service,
-			rustls,
+			tls,
+			protocol,
+			alpn,
 		})))
 	}
 }
 
-impl<Service: Clone> DualProtocolAcceptorFutureProj<'_, Service> {
+impl<Service, Tls, Fallback> DualProtocolAcceptorFutureProj<'_, Service, Tls, Fallback>
+where
+	Service: Clone,
+	Tls: Accept<TcpStream, DualProtocolService<Service, Fallback>> + TlsAccept,
+{
 	/// Proceed to the [`Https`](FutureState::Https) state.
 	fn upgrade(
 		&mut self,
-		future: <RustlsAcceptor as Accept<TcpStream, DualProtocolService<Service>>>::Future,
+		future: <Tls as Accept<TcpStream, DualProtocolService<Service, Fallback>>>::Future,
+		peer: PeerAddr,
+		alpn: AlpnHandlers<Tls>,
 	) {
-		self.0.set(FutureState::Https(future));
+		self.0.set(FutureState::Https { future, peer, alpn });
 	}
 }
 
-impl<Service: Clone> Future for DualProtocolAcceptorFuture<Service> {
+impl<Service, Tls, Fallback> Future for DualProtocolAcceptorFuture<Service, Tls, Fallback>
+where
+	Service: Clone,
+	Tls: Accept<
+			TcpStream,
+			DualProtocolService<Service, Fallback>,
+			Stream = <Tls as TlsAccept>::Stream,
+			Service = DualProtocolService<Service, Fallback>,
+		> + TlsAccept,
+{
 	type Output = io::Result<(
-		TokioEither<TlsStream<TcpStream>, TcpStream>,
-		DualProtocolService<Service>,
+		TokioEither<<Tls as TlsAccept>::Stream, TcpStream>,
+		DualProtocolService<Service, Fallback>,
 	)>;
 
 	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
@@ -197,39 +712,83 @@ impl<Service: Clone> Future for DualProtocolAcceptorFuture<Service> {
 				FutuereStateProj::Peek(inner) => {
 					let peek = inner.as_mut().expect("polled again after `Poll::Ready`");
 
-					let mut byte = 0;
-					let mut buffer = ReadBuf::new(slice::from_mut(&mut byte));
+					let protocol = if let Some(protocol) = peek.protocol {
+						protocol
+					} else {
+						let mut byte = 0;
+						let mut buffer = ReadBuf::new(slice::from_mut(&mut byte));
 
-					match peek.stream.poll_peek(cx, &mut buffer) {
-						// If `MSG_PEEK` returns `0`, the socket was closed.
-						Poll::Ready(Ok(0)) => {
-							return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()))
+						match peek.stream.poll_peek(cx, &mut buffer) {
+							// If `MSG_PEEK` returns `0`, the socket was closed.
+							Poll::Ready(Ok(0)) => {
+								return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()))
+							}
+							// The first byte in the TLS protocol is always `0x16`.
+							Poll::Ready(Ok(_)) if byte == 0x16 => Protocol::Tls,
+							Poll::Ready(Ok(_)) => Protocol::Plain,
+							Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+							Poll::Pending => return Poll::Pending,
 						}
-						Poll::Ready(Ok(_)) => {
-							let PeekState {
-								stream,
-								service,
-								rustls,
-							} = inner.take().expect("`inner` was already consumed");
+					};
 
-							// The first byte in the TLS protocol is always `0x16`.
-							if byte == 0x16 {
-								this.upgrade(rustls.accept(stream, service.build(Protocol::Tls)));
-							} else {
-								return Poll::Ready(Ok((
-									TokioEither::Right(stream),
-									service.build(Protocol::Plain),
-								)));
-							}
+					let PeekState {
+						stream,
+						service,
+						tls,
+						alpn,
+						..
+					} = inner.take().expect("`inner` was already consumed");
+
+					let peer_addr = PeerAddr(stream.peer_addr()?);
+					let local_addr = LocalAddr(stream.local_addr()?);
+
+					match protocol {
+						Protocol::Tls => {
+							this.upgrade(
+								tls.accept(
+									stream,
+									service.build(Protocol::Tls, peer_addr, local_addr),
+								),
+								peer_addr,
+								alpn,
+							);
+						}
+						Protocol::Plain => {
+							return Poll::Ready(Ok((
+								TokioEither::Right(stream),
+								service.build(Protocol::Plain, peer_addr, local_addr),
+							)))
 						}
-						Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
-						Poll::Pending => return Poll::Pending,
 					}
 				}
-				FutuereStateProj::Https(future) => {
-					return future
-						.poll(cx)
-						.map_ok(|(stream, service)| (TokioEither::Left(stream), service))
+				FutuereStateProj::Https { future, peer, alpn } => {
+					let (stream, service) = ready!(future.poll(cx))?;
+
+					// The challenge was answered by the handshake, so close the connection.
+					if stream.alpn_protocol() == Some(ACME_TLS_ALPN) {
+						return Poll::Ready(Err(io::Error::new(
+							ErrorKind::Other,
+							"answered ACME TLS-ALPN-01 challenge",
+						)));
+					}
+
+					let handler = stream
+						.alpn_protocol()
+						.and_then(|protocol| alpn.get(protocol));
+
+					return if let Some(handler) = handler {
+						// Run the handler in its own task, so it isn't tracked as a HTTP
+						// connection by the server's `Handle`.
+						drop(tokio::spawn(handler(stream, *peer)));
+
+						// The connection was handed to the handler, so don't serve the app.
+						Poll::Ready(Err(io::Error::new(
+							ErrorKind::Other,
+							"connection was handed to ALPN handler",
+						)))
+					} else {
+						Poll::Ready(Ok((TokioEither::Left(stream), service)))
+					};
 				}
 			}
 		}
@@ -238,60 +797,121 @@ impl<Service: Clone> Future for DualProtocolAcceptorFuture<Service> {
 
 /// Hold the user-supplied app until the protocol type is determined.
 #[derive(Debug)]
-struct DualProtocolServiceBuilder<Service>(ServiceServe<Service>);
+struct DualProtocolServiceBuilder<Service, Fallback> {
+	/// The user-supplied [`Service`](TowerService).
+	service: ServiceServe<Service, Fallback>,
+	/// How to determine the [`Protocol`] of requests from trusted proxies.
+	forwarded: Option<ForwardedProtocolLayer>,
+	/// The `Strict-Transport-Security` header configuration.
+	hsts: Option<StrictTransportSecurityLayer>,
+	/// Answers ACME challenges on the plain path.
+	#[cfg(feature = "acme")]
+	acme: Option<Acme>,
+}
 
 /// [`Service`](TowerService) wrapping user-supplied app to apply global
 /// [`Layer`](tower_layer::Layer)s according to configuration.
 #[derive(Clone, Debug)]
-pub struct DualProtocolService<Service: Clone> {
+pub struct DualProtocolService<Service: Clone, Fallback = BadRequest> {
 	/// The user-supplied [`Service`](TowerService).
-	service: ServiceServe<Service>,
+	service: ServiceServe<Service, Fallback>,
 	/// The protocol this connection is using.
 	protocol: Protocol,
+	/// The address of the peer of this connection.
+	peer: PeerAddr,
+	/// The local address of this connection.
+	local: LocalAddr,
+	/// How to determine the [`Protocol`] of requests from trusted proxies.
+	forwarded: Option<ForwardedProtocolLayer>,
+	/// The `Strict-Transport-Security` header configuration.
+	hsts: Option<StrictTransportSecurityLayer>,
+	/// Answers ACME challenges on the plain path.
+	#[cfg(feature = "acme")]
+	acme: Option<Acme>,
 }
 
 /// Holds [`Service`](TowerService) to serve for [`DualProtocolService`].
 #[derive(Clone, Debug)]
-enum ServiceServe<Service> {
+enum ServiceServe<Service, Fallback> {
 	/// No configuration applied, so we will pass-through the user-supplied
 	/// [`Service`](TowerService) as is.
 	Service(Service),
 	/// Configured to automatically upgrade HTTP requests to HTTPS, so we wrap
 	/// the user-supplied [`Service`](TowerService) in the [`UpgradeHttp`]
 	/// [`Service`](TowerService).
-	Upgrade(UpgradeHttp<Service>),
+	Upgrade(UpgradeHttp<Service, Fallback>),
 }
 
-impl<Service: Clone> DualProtocolServiceBuilder<Service> {
+impl<Service: Clone, Fallback> DualProtocolServiceBuilder<Service, Fallback> {
 	/// Create a [`DualProtocolService`] in the
 	/// [`Service`](ServiceServe::Service) state.
-	const fn new_service(service: Service) -> Self {
-		Self(ServiceServe::Service(service))
+	const fn new_service(
+		service: Service,
+		forwarded: Option<ForwardedProtocolLayer>,
+		hsts: Option<StrictTransportSecurityLayer>,
+	) -> Self {
+		Self {
+			service: ServiceServe::Service(service),
+			forwarded,
+			hsts,
+			#[cfg(feature = "acme")]
+			acme: None,
+		}
 	}
 
 	/// Create a [`DualProtocolService`] in the
 	/// [`Upgrade`](ServiceServe::Upgrade) state.
-	const fn new_upgrade(service: Service) -> Self {
-		Self(ServiceServe::Upgrade(UpgradeHttp::new(service)))
+	const fn new_upgrade(
+		service: UpgradeHttp<Service, Fallback>,
+		forwarded: Option<ForwardedProtocolLayer>,
+		hsts: Option<StrictTransportSecurityLayer>,
+	) -> Self {
+		Self {
+			service: ServiceServe::Upgrade(service),
+			forwarded,
+			hsts,
+			#[cfg(feature = "acme")]
+			acme: None,
+		}
+	}
+
+	/// Answer ACME challenges on the plain path.
+	#[cfg(feature = "acme")]
+	fn with_acme(mut self, acme: Option<Acme>) -> Self {
+		self.acme = acme;
+		self
 	}
 
 	/// Create a [`DualProtocolService`] when the protocol is established.
-	fn build(self, protocol: Protocol) -> DualProtocolService<Service> {
+	fn build(
+		self,
+		protocol: Protocol,
+		peer: PeerAddr,
+		local: LocalAddr,
+	) -> DualProtocolService<Service, Fallback> {
 		DualProtocolService {
-			service: self.0,
+			service: self.service,
 			protocol,
+			peer,
+			local,
+			forwarded: self.forwarded,
+			hsts: self.hsts,
+			#[cfg(feature = "acme")]
+			acme: self.acme,
 		}
 	}
 }
 
-impl<Service, RequestBody, ResponseBody> TowerService<Request<RequestBody>>
-	for DualProtocolService<Service>
+impl<Service, Fallback, RequestBody, ResponseBody, FallbackBody> TowerService<Request<RequestBody>>
+	for DualProtocolService<Service, Fallback>
 where
 	Service: Clone + TowerService<Request<RequestBody>, Response = Response<ResponseBody>>,
+	Fallback:
+		TowerService<Request<RequestBody>, Response = Response<FallbackBody>, Error = Infallible>,
 {
-	type Response = Response<BodyEither<ResponseBody, BodyEither<ResponseBody, Empty<Bytes>>>>;
+	type Response = Response<BodyEither<ResponseBody, BodyEither<Full<Bytes>, FallbackBody>>>;
 	type Error = Service::Error;
-	type Future = DualProtocolServiceFuture<Service, RequestBody, ResponseBody>;
+	type Future = DualProtocolServiceFuture<Service, RequestBody, ResponseBody, Fallback>;
 
 	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
 		match &mut self.service {
@@ -302,13 +922,29 @@ where
 
 	fn call(&mut self, mut req: Request<RequestBody>) -> Self::Future {
 		let _ = req.extensions_mut().insert(self.protocol);
+		let _ = req.extensions_mut().insert(self.peer);
+		let _ = req.extensions_mut().insert(self.local);
+
+		#[cfg(feature = "acme")]
+		if self.protocol == Protocol::Plain {
+			if let Some(response) = self.acme.as_ref().and_then(|acme| acme.respond(&req)) {
+				return DualProtocolServiceFuture::new_ready(response, Some(self.protocol), None);
+			}
+		}
+
+		if let Some(forwarded) = &self.forwarded {
+			forwarded.apply(&mut req);
+		}
+
+		let protocol = req.extensions().get::<Protocol>().copied();
+		let hsts = self.hsts.clone();
 
 		match &mut self.service {
 			ServiceServe::Service(service) => {
-				DualProtocolServiceFuture::new_service(service.call(req))
+				DualProtocolServiceFuture::new_service(service.call(req), protocol, hsts)
 			}
 			ServiceServe::Upgrade(service) => {
-				DualProtocolServiceFuture::new_upgrade(service.call(req))
+				DualProtocolServiceFuture::new_upgrade(service.call(req), protocol, hsts)
 			}
 		}
 	}
@@ -316,78 +952,156 @@ where
 
 /// [`Future`](TowerService::Future) type for [`DualProtocolService`].
 #[pin_project]
-pub struct DualProtocolServiceFuture<Service, RequestBody, ResponseBody>(
-	#[pin] FutureServe<Service, RequestBody, ResponseBody>,
-)
+pub struct DualProtocolServiceFuture<Service, RequestBody, ResponseBody, Fallback = BadRequest>
 where
-	Service: TowerService<Request<RequestBody>, Response = Response<ResponseBody>>;
+	Service: TowerService<Request<RequestBody>, Response = Response<ResponseBody>>,
+	Fallback: TowerService<Request<RequestBody>>,
+{
+	/// State.
+	#[pin]
+	future: FutureServe<Service, RequestBody, ResponseBody, Fallback>,
+	/// The protocol of the request.
+	protocol: Option<Protocol>,
+	/// The `Strict-Transport-Security` header configuration.
+	hsts: Option<StrictTransportSecurityLayer>,
+}
 
 /// Holds [`Future`] to serve for [`DualProtocolServiceFuture`].
-#[derive(Debug)]
 #[pin_project(project = DualProtocolServiceFutureProj)]
-enum FutureServe<Service, RequestBody, ResponseBody>
+enum FutureServe<Service, RequestBody, ResponseBody, Fallback>
 where
 	Service: TowerService<Request<RequestBody>, Response = Response<ResponseBody>>,
+	Fallback: TowerService<Request<RequestBody>>,
 {
 	/// Pass-through the user-supplied [`Future`](TowerService::Future).
 	Service(#[pin] Service::Future),
 	/// Use the [`UpgradeHttp`] [`Future`](TowerService::Future).
-	Upgrade(#[pin] <UpgradeHttp<Service> as TowerService<Request<RequestBody>>>::Future),
+	Upgrade(#[pin] UpgradeHttpFuture<Service, Request<RequestBody>, Fallback>),
+	/// Response to an ACME challenge.
+	#[cfg(feature = "acme")]
+	Ready(Option<Response<Full<Bytes>>>),
+}
+
+// Rust can't figure out the correct bounds.
+impl<Service, RequestBody, ResponseBody, Fallback> Debug
+	for FutureServe<Service, RequestBody, ResponseBody, Fallback>
+where
+	Service: TowerService<Request<RequestBody>, Response = Response<ResponseBody>>,
+	Service::Future: Debug,
+	Fallback: TowerService<Request<RequestBody>>,
+	UpgradeHttpFuture<Service, Request<RequestBody>, Fallback>: Debug,
+{
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Service(future) => formatter.debug_tuple("Service").field(future).finish(),
+			Self::Upgrade(future) => formatter.debug_tuple("Upgrade").field(future).finish(),
+			#[cfg(feature = "acme")]
+			Self::Ready(response) => formatter.debug_tuple("Ready").field(response).finish(),
+		}
+	}
 }
 
 // Rust can't figure out the correct bounds.
-impl<Service, RequestBody, ResponseBody> Debug
-	for DualProtocolServiceFuture<Service, RequestBody, ResponseBody>
+impl<Service, RequestBody, ResponseBody, Fallback> Debug
+	for DualProtocolServiceFuture<Service, RequestBody, ResponseBody, Fallback>
 where
 	Service: TowerService<Request<RequestBody>, Response = Response<ResponseBody>>,
-	FutureServe<Service, RequestBody, ResponseBody>: Debug,
+	Fallback: TowerService<Request<RequestBody>>,
+	FutureServe<Service, RequestBody, ResponseBody, Fallback>: Debug,
 {
 	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
 		formatter
-			.debug_tuple("DualProtocolServiceFuture")
-			.field(&self.0)
+			.debug_struct("DualProtocolServiceFuture")
+			.field("future", &self.future)
+			.field("protocol", &self.protocol)
+			.field("hsts", &self.hsts)
 			.finish()
 	}
 }
 
-impl<Service, RequestBody, ResponseBody>
-	DualProtocolServiceFuture<Service, RequestBody, ResponseBody>
+impl<Service, RequestBody, ResponseBody, Fallback>
+	DualProtocolServiceFuture<Service, RequestBody, ResponseBody, Fallback>
 where
 	Service: TowerService<Request<RequestBody>, Response = Response<ResponseBody>>,
+	Fallback: TowerService<Request<RequestBody>>,
 {
 	/// Create a [`DualProtocolServiceFuture`] in the
 	/// [`Service`](FutureServe::Service) state.
-	const fn new_service(future: Service::Future) -> Self {
-		Self(FutureServe::Service(future))
+	const fn new_service(
+		future: Service::Future,
+		protocol: Option<Protocol>,
+		hsts: Option<StrictTransportSecurityLayer>,
+	) -> Self {
+		Self {
+			future: FutureServe::Service(future),
+			protocol,
+			hsts,
+		}
 	}
 
 	/// Create a [`DualProtocolServiceFuture`] in the
 	/// [`Upgrade`](FutureServe::Upgrade) state.
 	const fn new_upgrade(
-		future: <UpgradeHttp<Service> as TowerService<Request<RequestBody>>>::Future,
+		future: UpgradeHttpFuture<Service, Request<RequestBody>, Fallback>,
+		protocol: Option<Protocol>,
+		hsts: Option<StrictTransportSecurityLayer>,
 	) -> Self {
-		Self(FutureServe::Upgrade(future))
+		Self {
+			future: FutureServe::Upgrade(future),
+			protocol,
+			hsts,
+		}
+	}
+
+	/// Create a [`DualProtocolServiceFuture`] in the
+	/// [`Ready`](FutureServe::Ready) state.
+	#[cfg(feature = "acme")]
+	const fn new_ready(
+		response: Response<Full<Bytes>>,
+		protocol: Option<Protocol>,
+		hsts: Option<StrictTransportSecurityLayer>,
+	) -> Self {
+		Self {
+			future: FutureServe::Ready(Some(response)),
+			protocol,
+			hsts,
+		}
 	}
 }
 
-impl<Service, RequestBody, ResponseBody> Future
-	for DualProtocolServiceFuture<Service, RequestBody, ResponseBody>
+impl<Service, RequestBody, ResponseBody, Fallback, FallbackBody> Future
+	for DualProtocolServiceFuture<Service, RequestBody, ResponseBody, Fallback>
 where
 	Service: TowerService<Request<RequestBody>, Response = Response<ResponseBody>>,
+	Fallback:
+		TowerService<Request<RequestBody>, Response = Response<FallbackBody>, Error = Infallible>,
 {
 	type Output = Result<
-		Response<BodyEither<ResponseBody, BodyEither<ResponseBody, Empty<Bytes>>>>,
+		Response<BodyEither<ResponseBody, BodyEither<Full<Bytes>, FallbackBody>>>,
 		Service::Error,
 	>;
 
 	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-		match self.project().0.project() {
+		let this = self.project();
+
+		let poll = match this.future.project() {
 			DualProtocolServiceFutureProj::Service(future) => future
 				.poll(cx)
 				.map_ok(|response| response.map(BodyEither::Left)),
-			DualProtocolServiceFutureProj::Upgrade(future) => future
-				.poll(cx)
-				.map_ok(|response| response.map(BodyEither::Right)),
-		}
+			DualProtocolServiceFutureProj::Upgrade(future) => future.poll(cx),
+			#[cfg(feature = "acme")]
+			DualProtocolServiceFutureProj::Ready(response) => Poll::Ready(Ok(response
+				.take()
+				.expect("polled again after `Poll::Ready`")
+				.map(|body| BodyEither::Right(BodyEither::Left(body))))),
+		};
+
+		poll.map_ok(|mut response| {
+			if let Some(hsts) = this.hsts {
+				hsts.apply(*this.protocol, response.headers_mut());
+			}
+
+			response
+		})
 	}
 }
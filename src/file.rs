@@ -0,0 +1,56 @@
+//! Writing certificates and private keys to disk.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+/// Atomically writes a file by writing to a temporary file first and renaming
+/// it afterwards.
+pub(crate) async fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+	write_with(path, contents, OpenOptions::new()).await
+}
+
+/// Atomically writes a PEM encoded private key, only readable by the current
+/// user on Unix.
+pub(crate) async fn write_private_key(path: &Path, private_key: &[u8]) -> io::Result<()> {
+	#[cfg_attr(not(unix), allow(unused_mut))]
+	let mut options = OpenOptions::new();
+	#[cfg(unix)]
+	let _ = options.mode(0o600);
+
+	write_with(path, private_key, options).await
+}
+
+/// Writes `contents` to a temporary file opened with `options` and renames it
+/// to `path`.
+async fn write_with(path: &Path, contents: &[u8], mut options: OpenOptions) -> io::Result<()> {
+	let temporary = temporary_path(path);
+	let _ = options.write(true).create(true).truncate(true);
+
+	let result = async {
+		let mut file = options.open(&temporary).await?;
+		file.write_all(contents).await?;
+		file.sync_all().await?;
+		drop(file);
+
+		fs::rename(&temporary, path).await
+	}
+	.await;
+
+	if result.is_err() {
+		let _ = fs::remove_file(&temporary).await;
+	}
+
+	result
+}
+
+/// Returns the path of the temporary file used to write `path`.
+fn temporary_path(path: &Path) -> PathBuf {
+	let mut name = path.file_name().map_or_else(OsString::new, OsString::from);
+	name.push(".tmp");
+
+	path.with_file_name(name)
+}
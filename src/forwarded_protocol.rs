@@ -0,0 +1,273 @@
+//! Protocol forwarding by trusted proxies implementation.
+//!
+//! See [`ForwardedProtocol`].
+
+use std::fmt::{self, Debug, Formatter};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::header::FORWARDED;
+use http::{HeaderMap, Request};
+use tower_layer::Layer;
+use tower_service::Service as TowerService;
+
+use crate::{PeerAddr, Protocol};
+
+/// The protocol used by the underlying transport of this connection,
+/// regardless of what a trusted proxy reported. See
+/// [`Request::extensions()`](Request::extensions()).
+///
+/// This is set by [`ForwardedProtocol`], which might overwrite [`Protocol`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct TransportProtocol(pub Protocol);
+
+/// [`Layer`] setting the [`Protocol`] from headers sent by trusted proxies.
+///
+/// See [`ForwardedProtocol`] for more details.
+#[derive(Clone)]
+pub struct ForwardedProtocolLayer {
+	/// Determines if a peer is a trusted proxy.
+	trusted: Arc<dyn Fn(IpAddr) -> bool + Send + Sync>,
+}
+
+impl Debug for ForwardedProtocolLayer {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter
+			.debug_struct("ForwardedProtocolLayer")
+			.finish_non_exhaustive()
+	}
+}
+
+impl ForwardedProtocolLayer {
+	/// Creates a new [`ForwardedProtocolLayer`] trusting the given proxy
+	/// addresses.
+	pub fn new<Proxies: IntoIterator<Item = IpAddr>>(proxies: Proxies) -> Self {
+		let proxies: Vec<_> = proxies.into_iter().map(canonicalize).collect();
+
+		Self::with_predicate(move |address| proxies.contains(&address))
+	}
+
+	/// Creates a new [`ForwardedProtocolLayer`] trusting every proxy address
+	/// the given predicate returns `true` for.
+	pub fn with_predicate<Predicate>(predicate: Predicate) -> Self
+	where
+		Predicate: 'static + Fn(IpAddr) -> bool + Send + Sync,
+	{
+		Self {
+			trusted: Arc::new(predicate),
+		}
+	}
+
+	/// Sets the [`Protocol`] of the request according to the headers sent by
+	/// a trusted proxy and stores the original [`TransportProtocol`].
+	pub(crate) fn apply<Body>(&self, request: &mut Request<Body>) {
+		let Some(transport) = request.extensions().get::<Protocol>().copied() else {
+			return;
+		};
+
+		let _ = request
+			.extensions_mut()
+			.insert(TransportProtocol(transport));
+
+		let trusted = request
+			.extensions()
+			.get::<PeerAddr>()
+			.map_or(false, |peer| (self.trusted)(canonicalize(peer.0.ip())));
+
+		if trusted {
+			if let Some(protocol) = extract_protocol(request.headers()) {
+				let _ = request.extensions_mut().insert(protocol);
+			}
+		}
+	}
+}
+
+impl<Service> Layer<Service> for ForwardedProtocolLayer {
+	type Service = ForwardedProtocol<Service>;
+
+	fn layer(&self, inner: Service) -> Self::Service {
+		ForwardedProtocol::new(inner, self.clone())
+	}
+}
+
+/// [`Service`](TowerService) setting the [`Protocol`] of requests coming from
+/// trusted proxies according to their forwarding headers.
+///
+/// The [`Forwarded`](https://tools.ietf.org/html/rfc7239) `proto` parameter is
+/// preferred over the `X-Forwarded-Proto` header. If a `Forwarded` header is
+/// present, `X-Forwarded-Proto` is ignored, even if the closest proxy didn't
+/// report a `proto`.
+///
+/// This is useful if TLS is terminated by a reverse proxy, in which case all
+/// requests would otherwise arrive as [`Protocol::Plain`]. The protocol of the
+/// underlying transport stays available as [`TransportProtocol`].
+///
+/// Trust is determined by the [`PeerAddr`] of the request, requests without it
+/// are never trusted. To apply this before [`UpgradeHttp`](crate::UpgradeHttp)
+/// when using [`ServerExt::set_upgrade()`](crate::ServerExt::set_upgrade()),
+/// use [`ServerExt::set_forwarded_protocol()`](crate::ServerExt::set_forwarded_protocol()).
+#[derive(Clone, Debug)]
+pub struct ForwardedProtocol<Service> {
+	/// Wrapped user-provided [`Service`](TowerService).
+	service: Service,
+	/// Configuration.
+	layer: ForwardedProtocolLayer,
+}
+
+impl<Service> ForwardedProtocol<Service> {
+	/// Creates a new [`ForwardedProtocol`].
+	pub const fn new(service: Service, layer: ForwardedProtocolLayer) -> Self {
+		Self { service, layer }
+	}
+
+	/// Consumes the [`ForwardedProtocol`], returning the wrapped
+	/// [`Service`](TowerService).
+	pub fn into_inner(self) -> Service {
+		self.service
+	}
+
+	/// Return a reference to the wrapped [`Service`](TowerService).
+	pub const fn get_ref(&self) -> &Service {
+		&self.service
+	}
+
+	/// Return a mutable reference to the wrapped [`Service`](TowerService).
+	pub fn get_mut(&mut self) -> &mut Service {
+		&mut self.service
+	}
+}
+
+impl<Service, RequestBody> TowerService<Request<RequestBody>> for ForwardedProtocol<Service>
+where
+	Service: TowerService<Request<RequestBody>>,
+{
+	type Response = Service::Response;
+	type Error = Service::Error;
+	type Future = Service::Future;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, mut req: Request<RequestBody>) -> Self::Future {
+		self.layer.apply(&mut req);
+		self.service.call(req)
+	}
+}
+
+/// Maps IPv4-mapped IPv6 addresses to their IPv4 counterpart, so they can be
+/// compared with addresses supplied by the user.
+fn canonicalize(address: IpAddr) -> IpAddr {
+	match address {
+		IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(address, IpAddr::V4),
+		IpAddr::V4(_) => address,
+	}
+}
+
+/// Extracts the protocol reported by the closest proxy. If present, the
+/// `Forwarded` header is authoritative, otherwise `X-Forwarded-Proto` is used.
+pub(crate) fn extract_protocol(headers: &HeaderMap) -> Option<Protocol> {
+	/// `X-Forwarded-Proto` header string.
+	const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+	// Proxies append their own element, so the last one is from the closest proxy.
+	if headers.contains_key(FORWARDED) {
+		let proto = forwarded_proto(headers)?;
+		parse_scheme(&proto)
+	} else {
+		headers
+			.get_all(X_FORWARDED_PROTO)
+			.iter()
+			.filter_map(|header| header.to_str().ok())
+			.flat_map(|header| header.split(','))
+			.last()
+			.and_then(|scheme| parse_scheme(scheme.trim().as_bytes()))
+	}
+}
+
+/// Returns the `proto` parameter of the last element of the
+/// [`Forwarded`](https://tools.ietf.org/html/rfc7239#section-4) headers, if
+/// it has one. Quoted strings are unescaped and may contain
+/// delimiters.
+fn forwarded_proto(headers: &HeaderMap) -> Option<Vec<u8>> {
+	// `proto` of the last non-empty element.
+	let mut last = None;
+
+	for header in headers.get_all(FORWARDED) {
+		let mut bytes = header.as_bytes().iter().copied();
+		// Name of the current parameter.
+		let mut name = Vec::new();
+		// Value of the current parameter, `None` while parsing its name.
+		let mut value: Option<Vec<u8>> = None;
+		// `proto` of the current element.
+		let mut proto = None;
+		// Empty elements are allowed and ignored.
+		let mut empty = true;
+
+		loop {
+			let byte = bytes.next();
+
+			match byte {
+				None | Some(b',' | b';') => {
+					if let Some(value) = value.take() {
+						if name.eq_ignore_ascii_case(b"proto") {
+							proto = Some(value);
+						}
+					}
+
+					name.clear();
+
+					if byte != Some(b';') {
+						if !empty {
+							last = proto.take();
+						}
+
+						proto = None;
+						empty = true;
+					}
+
+					if byte.is_none() {
+						break;
+					}
+				}
+				// Whitespace is only allowed around delimiters.
+				Some(b' ' | b'\t') => (),
+				Some(byte) => {
+					empty = false;
+
+					if let Some(value) = &mut value {
+						if byte == b'"' {
+							while let Some(byte) = bytes.next() {
+								match byte {
+									b'"' => break,
+									b'\\' => value.extend(bytes.next()),
+									_ => value.push(byte),
+								}
+							}
+						} else {
+							value.push(byte);
+						}
+					} else if byte == b'=' {
+						value = Some(Vec::new());
+					} else {
+						name.push(byte);
+					}
+				}
+			}
+		}
+	}
+
+	last
+}
+
+/// Maps a URI scheme to its [`Protocol`].
+fn parse_scheme(scheme: &[u8]) -> Option<Protocol> {
+	if scheme.eq_ignore_ascii_case(b"https") || scheme.eq_ignore_ascii_case(b"wss") {
+		Some(Protocol::Tls)
+	} else if scheme.eq_ignore_ascii_case(b"http") || scheme.eq_ignore_ascii_case(b"ws") {
+		Some(Protocol::Plain)
+	} else {
+		None
+	}
+}
@@ -0,0 +1,354 @@
+//! TLS [`Accept`]or resolving the [`ServerConfig`] asynchronously.
+//!
+//! See [`LazyRustlsAcceptor`].
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::io::{self, ErrorKind};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use axum_server::accept::Accept;
+use rustls::server::{Acceptor, ClientHello};
+use rustls::{ServerConfig, SignatureScheme};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::OnceCell;
+use tokio::time;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::LazyConfigAcceptor;
+
+use crate::TlsAccept;
+
+/// Type-erased [`Future`] returned by the resolver.
+type ResolveFuture = Pin<Box<dyn Future<Output = io::Result<Arc<ServerConfig>>> + Send>>;
+
+/// Type-erased resolver.
+type ConfigResolver = dyn Fn(ClientHelloInfo) -> ResolveFuture + Send + Sync;
+
+/// Resolved or currently resolving [`ServerConfig`] with the time it was
+/// resolved at.
+type CacheEntry = Arc<OnceCell<(Instant, Arc<ServerConfig>)>>;
+
+/// Cached [`ServerConfig`]s.
+type Cache = HashMap<ClientHelloInfo, CacheEntry>;
+
+/// [`Future`](Accept::Future) type for [`LazyRustlsAcceptor`].
+pub type LazyRustlsAcceptorFuture<Io, Service> =
+	Pin<Box<dyn Future<Output = io::Result<(TlsStream<Io>, Service)>> + Send>>;
+
+/// TLS [`Accept`]or that reads the `ClientHello` first and then asks an async
+/// resolver for the [`ServerConfig`] to complete the handshake with.
+///
+/// Unlike rustls' [`ResolvesServerCert`](rustls::server::ResolvesServerCert),
+/// the resolver can e.g. fetch certificates from a remote secret store on
+/// demand. It receives the [`ClientHelloInfo`] and an error aborts the
+/// connection. Resolved [`ServerConfig`]s are cached for the same
+/// [`ClientHelloInfo`], see [`cache_duration()`](Self::cache_duration()) and
+/// [`cache_capacity()`](Self::cache_capacity()). Concurrent handshakes with the
+/// same [`ClientHelloInfo`] share a single call to the resolver.
+///
+/// Use it with
+/// [`DualProtocolAcceptor::with_tls()`](crate::DualProtocolAcceptor::with_tls()).
+/// The [`ServerConfig`]s have to set their
+/// [`alpn_protocols`](ServerConfig::alpn_protocols) to negotiate HTTP/2.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::io;
+/// # use std::net::SocketAddr;
+/// # use std::sync::Arc;
+/// # use axum::{routing, Router};
+/// use axum_server::Server;
+/// use axum_server_dual_protocol::{DualProtocolAcceptor, LazyRustlsAcceptor};
+/// use rustls::ServerConfig;
+///
+/// # async fn fetch(_: Option<&str>) -> io::Result<Arc<ServerConfig>> { unimplemented!() }
+/// # #[tokio::main]
+/// # async fn main() -> io::Result<()> {
+/// # let app = Router::new().route("/", routing::get(|| async { "Hello, world!" }));
+/// # let address = SocketAddr::from(([127, 0, 0, 1], 0));
+/// let acceptor =
+/// 	LazyRustlsAcceptor::new(
+/// 		|client_hello| async move { fetch(client_hello.server_name()).await },
+/// 	);
+///
+/// Server::bind(address)
+/// 	.acceptor(DualProtocolAcceptor::with_tls(acceptor))
+/// 	.serve(app.into_make_service())
+/// 	.await?;
+/// #
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct LazyRustlsAcceptor {
+	/// Resolves the [`ServerConfig`] for a `ClientHello`.
+	resolver: Arc<ConfigResolver>,
+	/// Previously resolved [`ServerConfig`]s.
+	cache: Arc<Mutex<Cache>>,
+	/// How long resolved [`ServerConfig`]s are re-used.
+	cache_duration: Duration,
+	/// Maximum number of cached [`ServerConfig`]s.
+	cache_capacity: usize,
+	/// Timeout for the whole handshake, including resolving the
+	/// [`ServerConfig`].
+	handshake_timeout: Duration,
+}
+
+impl Debug for LazyRustlsAcceptor {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter
+			.debug_struct("LazyRustlsAcceptor")
+			.field("cache_duration", &self.cache_duration)
+			.field("cache_capacity", &self.cache_capacity)
+			.field("handshake_timeout", &self.handshake_timeout)
+			.finish_non_exhaustive()
+	}
+}
+
+impl LazyRustlsAcceptor {
+	/// Create a new [`LazyRustlsAcceptor`] with the given resolver.
+	///
+	/// Up to 1024 resolved [`ServerConfig`]s are cached for 5 minutes and
+	/// handshakes time out after 10 seconds by default.
+	pub fn new<Resolver, Fut>(resolver: Resolver) -> Self
+	where
+		Resolver: 'static + Fn(ClientHelloInfo) -> Fut + Send + Sync,
+		Fut: 'static + Future<Output = io::Result<Arc<ServerConfig>>> + Send,
+	{
+		let resolver: Arc<ConfigResolver> =
+			Arc::new(move |client_hello| Box::pin(resolver(client_hello)));
+
+		Self {
+			resolver,
+			cache: Arc::default(),
+			cache_duration: Duration::from_secs(5 * 60),
+			cache_capacity: 1024,
+			handshake_timeout: Duration::from_secs(10),
+		}
+	}
+
+	/// Set how long a resolved [`ServerConfig`] is re-used for the same
+	/// [`ClientHelloInfo`]. Errors are never cached. Zero disables caching.
+	#[must_use]
+	pub const fn cache_duration(mut self, duration: Duration) -> Self {
+		self.cache_duration = duration;
+		self
+	}
+
+	/// Set the maximum number of cached [`ServerConfig`]s. The cache key is
+	/// controlled by clients, so this limits how much memory they can make it
+	/// use. When it is full, [`ServerConfig`]s for new [`ClientHelloInfo`]s
+	/// are resolved without caching until entries expire.
+	#[must_use]
+	pub const fn cache_capacity(mut self, capacity: usize) -> Self {
+		self.cache_capacity = capacity;
+		self
+	}
+
+	/// Set the timeout for the whole handshake, including resolving the
+	/// [`ServerConfig`].
+	#[must_use]
+	pub const fn handshake_timeout(mut self, timeout: Duration) -> Self {
+		self.handshake_timeout = timeout;
+		self
+	}
+
+	/// Remove all cached [`ServerConfig`]s, e.g. after certificates were
+	/// renewed. Shared by all clones.
+	pub fn clear_cache(&self) {
+		self.cache
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clear();
+	}
+
+	/// Returns the [`ServerConfig`] for `client_hello` from the cache or the
+	/// resolver.
+	async fn resolve(&self, client_hello: ClientHelloInfo) -> io::Result<Arc<ServerConfig>> {
+		let Some(entry) = self.entry(&client_hello) else {
+			return (self.resolver)(client_hello).await;
+		};
+
+		let entry = PendingEntry {
+			cache: &self.cache,
+			client_hello: &client_hello,
+			entry,
+		};
+
+		let (_, config) = entry
+			.entry
+			.get_or_try_init(|| async {
+				let config = (self.resolver)(client_hello.clone()).await?;
+				Ok::<_, io::Error>((Instant::now(), config))
+			})
+			.await?;
+
+		Ok(Arc::clone(config))
+	}
+
+	/// Returns the cache entry for `client_hello`, creating it if it doesn't
+	/// exist or has expired. Returns [`None`] if caching is disabled or the
+	/// cache is full.
+	fn entry(&self, client_hello: &ClientHelloInfo) -> Option<CacheEntry> {
+		if self.cache_duration.is_zero() {
+			return None;
+		}
+
+		let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+		let is_fresh = |entry: &OnceCell<(Instant, Arc<ServerConfig>)>| {
+			// Entries that are still resolving are kept.
+			entry.get().map_or(true, |(resolved, _)| {
+				resolved.elapsed() < self.cache_duration
+			})
+		};
+
+		if let Some(entry) = cache.get(client_hello).filter(|entry| is_fresh(entry)) {
+			return Some(Arc::clone(entry));
+		}
+
+		// Only pay for removing expired entries when the cache is full.
+		if cache.len() >= self.cache_capacity && !cache.contains_key(client_hello) {
+			cache.retain(|_, entry| is_fresh(entry));
+
+			if cache.len() >= self.cache_capacity {
+				return None;
+			}
+		}
+
+		let entry = Arc::new(OnceCell::new());
+		let _ = cache.insert(client_hello.clone(), Arc::clone(&entry));
+		drop(cache);
+
+		Some(entry)
+	}
+}
+
+/// Removes a cache entry that wasn't resolved when dropped.
+///
+/// Errors are not cached, so later handshakes try again. Resolving can also be
+/// cancelled, e.g. by the handshake timeout, which must not leave an empty
+/// entry occupying the cache.
+struct PendingEntry<'cache> {
+	/// Cache holding the entry.
+	cache: &'cache Mutex<Cache>,
+	/// Key of the entry.
+	client_hello: &'cache ClientHelloInfo,
+	/// The entry.
+	entry: CacheEntry,
+}
+
+impl Drop for PendingEntry<'_> {
+	fn drop(&mut self) {
+		if self.entry.initialized() {
+			return;
+		}
+
+		let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+
+		if cache
+			.get(self.client_hello)
+			.map_or(false, |current| Arc::ptr_eq(current, &self.entry))
+		{
+			let _ = cache.remove(self.client_hello);
+		}
+	}
+}
+
+impl<Io, Service> Accept<Io, Service> for LazyRustlsAcceptor
+where
+	Io: 'static + AsyncRead + AsyncWrite + Send + Unpin,
+	Service: 'static + Send,
+{
+	type Stream = TlsStream<Io>;
+	type Service = Service;
+	type Future = LazyRustlsAcceptorFuture<Io, Service>;
+
+	fn accept(&self, stream: Io, service: Service) -> Self::Future {
+		let acceptor = self.clone();
+
+		Box::pin(async move {
+			let handshake = async {
+				let start = LazyConfigAcceptor::new(Acceptor::default(), stream).await?;
+				let client_hello = ClientHelloInfo::new(&start.client_hello());
+				let config = acceptor.resolve(client_hello).await?;
+
+				start.into_stream(config).await
+			};
+
+			let stream = time::timeout(acceptor.handshake_timeout, handshake)
+				.await
+				.map_err(|_| io::Error::new(ErrorKind::TimedOut, "TLS handshake timed out"))??;
+
+			Ok((stream, service))
+		})
+	}
+}
+
+impl TlsAccept for LazyRustlsAcceptor {
+	type Stream = TlsStream<TcpStream>;
+}
+
+/// Parts of the `ClientHello` [`LazyRustlsAcceptor`] passes to its resolver.
+///
+/// Resolved [`ServerConfig`]s are cached by all of them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClientHelloInfo {
+	/// Server name requested with SNI.
+	server_name: Option<String>,
+	/// Protocols offered with ALPN.
+	alpn: Vec<Vec<u8>>,
+	/// Supported signature schemes.
+	signature_schemes: Vec<SignatureScheme>,
+}
+
+impl ClientHelloInfo {
+	/// Copies the relevant parts of `client_hello`.
+	fn new(client_hello: &ClientHello<'_>) -> Self {
+		Self {
+			server_name: client_hello.server_name().map(str::to_owned),
+			alpn: client_hello
+				.alpn()
+				.into_iter()
+				.flatten()
+				.map(<[u8]>::to_vec)
+				.collect(),
+			signature_schemes: client_hello.signature_schemes().to_vec(),
+		}
+	}
+
+	/// Returns the server name requested with SNI, if any.
+	#[must_use]
+	pub fn server_name(&self) -> Option<&str> {
+		self.server_name.as_deref()
+	}
+
+	/// Returns the protocols offered with ALPN, in order of preference.
+	#[must_use]
+	pub fn alpn(&self) -> &[Vec<u8>] {
+		&self.alpn
+	}
+
+	/// Returns the supported signature schemes.
+	#[must_use]
+	pub fn signature_schemes(&self) -> &[SignatureScheme] {
+		&self.signature_schemes
+	}
+}
+
+// `SignatureScheme` doesn't implement `Hash`.
+impl Hash for ClientHelloInfo {
+	fn hash<H: Hasher>(&self, state: &mut H) {
+		self.server_name.hash(state);
+		self.alpn.hash(state);
+
+		for scheme in &self.signature_schemes {
+			u16::from(*scheme).hash(state);
+		}
+	}
+}
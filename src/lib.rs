@@ -78,18 +78,59 @@
 //! # use axum_server_dual_protocol::UpgradeHttpLayer;
 //! let app = Router::new()
 //! 	.route("/", routing::get(|| async { "Hello, world!" }))
-//! 	.layer(UpgradeHttpLayer);
+//! 	.layer(UpgradeHttpLayer::new());
 //! # // To help with type inference.
 //! # axum_server::bind(std::net::SocketAddr::from(([127, 0, 0, 1], 0)))
 //! # 	.serve(app.into_make_service());
 //! ```
 //!
+//! Outside of [`bind_dual_protocol()`] the [`Protocol`] of requests has to be
+//! provided, see [`UpgradeHttpLayer`] for more details.
+//!
 //! # Features
 //!
 //! ## `default`
 //!
 //! By default the [`aws-lc-rs`] [`CryptoProvider`] is enabled.
 //!
+//! ## `acme`
+//!
+//! Adds `Acme`, which obtains and renews certificates from an ACME server,
+//! e.g. [Let's Encrypt](https://letsencrypt.org), and answers HTTP-01
+//! challenges on the plain path with `ServerExt::set_acme()` or TLS-ALPN-01
+//! challenges on the TLS path. Certificates are cached on disk.
+//!
+//! ## `axum`
+//!
+//! Implements [`axum`]s `FromRequestParts` for [`Protocol`] and adds the
+//! `RequireTls` and `RequirePlain` extractors, which let individual routes
+//! insist on a protocol. See [`RequireProtocol`] for the equivalent
+//! [`Layer`](tower_layer::Layer)s.
+//!
+//! ## `certificate-store`
+//!
+//! Adds `CertificateStore`, which loads all certificates from a directory,
+//! selects them by the server name the client requested and reloads them when
+//! the directory changes.
+//!
+//! ## `dev-certs`
+//!
+//! Adds `DevCerts`, which creates a local certificate authority and a
+//! certificate signed by it for development. Both are stored on disk and
+//! re-used, so the certificate authority only has to be trusted once.
+//!
+//! ## `openssl`
+//!
+//! Adds `bind_dual_protocol_openssl()` and `from_tcp_dual_protocol_openssl()`,
+//! which use [`axum-server`](axum_server)s OpenSSL acceptor for HTTPS instead
+//! of `rustls`. Any other TLS [`Accept`]or can be used with
+//! [`DualProtocolAcceptor::with_tls()`].
+//!
+//! ## `reload`
+//!
+//! Adds `CertificateReloader`, which serves a certificate and private key from
+//! disk and reloads them when they change or, on Unix, on `SIGHUP`.
+//!
 //! # Conditional Configurations
 //!
 //! ## `docsrs`
@@ -126,6 +167,7 @@
 //! [CHANGELOG]: https://github.com/daxpedda/axum-server-dual-protocol/blob/v0.7.0/CHANGELOG.md
 //! [LICENSE-MIT]: https://github.com/daxpedda/axum-server-dual-protocol/blob/v0.7.0/LICENSE-MIT
 //! [LICENSE-APACHE]: https://github.com/daxpedda/axum-server-dual-protocol/blob/v0.7.0/LICENSE-APACHE
+//! [`Accept`]: axum_server::accept::Accept
 //! [`aws-lc-rs`]: https://docs.rs/aws-lc-rs/1
 //! [`axum`]: https://docs.rs/axum/0.7
 //! [`CryptoProvider`]: tokio_rustls::rustls::crypto::CryptoProvider
@@ -133,14 +175,77 @@
 //! [`Router`]: https://docs.rs/axum/0.7/axum/struct.Router.html
 //! [`tower`]: https://docs.rs/tower/0.4
 
+#![cfg_attr(docsrs, feature(doc_cfg))]
+
+#[cfg(feature = "acme")]
+mod acme;
+#[cfg(feature = "certificate-store")]
+mod certificate_store;
+#[cfg(feature = "dev-certs")]
+mod dev_certs;
 mod dual_protocol;
+#[cfg(any(feature = "acme", feature = "dev-certs"))]
+mod file;
+mod forwarded_protocol;
+mod lazy_rustls;
+mod multi_address;
+#[cfg(feature = "reload")]
+mod reload;
+mod require_protocol;
+mod split;
+mod split_protocol;
+mod strict_transport_security;
+#[cfg(unix)]
+mod systemd;
 mod upgrade_http;
+#[cfg(any(feature = "certificate-store", feature = "reload"))]
+mod watch;
 
+#[cfg(feature = "acme")]
+pub use acme::{Acme, AcmeChallenge, AcmeConfig, AcmeError};
+pub use axum_server;
+pub use bytes;
+#[cfg(feature = "certificate-store")]
+pub use certificate_store::{CertificateStore, CertificateStoreError};
+#[cfg(feature = "dev-certs")]
+pub use dev_certs::{DevCerts, DevCertsError};
 pub use dual_protocol::{
-	bind_dual_protocol, from_tcp_dual_protocol, DualProtocolAcceptor, DualProtocolAcceptorFuture,
-	DualProtocolService, DualProtocolServiceFuture, Protocol, ServerExt,
+	bind_dual_protocol, from_tcp_dual_protocol, AlpnStream, DualProtocolAcceptor,
+	DualProtocolAcceptorFuture, DualProtocolService, DualProtocolServiceFuture, LocalAddr,
+	PeerAddr, Protocol, ServerExt, TlsAccept,
+};
+#[cfg(feature = "openssl")]
+pub use dual_protocol::{bind_dual_protocol_openssl, from_tcp_dual_protocol_openssl};
+pub use forwarded_protocol::{ForwardedProtocol, ForwardedProtocolLayer, TransportProtocol};
+pub use http;
+pub use http_body_util;
+pub use lazy_rustls::{ClientHelloInfo, LazyRustlsAcceptor, LazyRustlsAcceptorFuture};
+pub use multi_address::{
+	bind_dual_protocol_all, from_tcp_dual_protocol_all, MultiAddressHandle, MultiAddressServer,
+};
+#[cfg(feature = "reload")]
+pub use reload::{CertificateReloader, ReloadError};
+#[cfg(feature = "axum")]
+pub use require_protocol::{
+	MissingProtocol, RequirePlain, RequireProtocolRejection, RequireTls, WrongProtocol,
+};
+pub use require_protocol::{
+	ProtocolRejection, RequirePlainLayer, RequireProtocol, RequireProtocolFuture, RequireTlsLayer,
+};
+pub use split::{SplitMakeService, SplitMakeServiceFuture, SplitService, SplitServiceFuture};
+pub use split_protocol::{
+	bind_split_protocol, from_tcp_split_protocol, ProtocolLayer, SetProtocol, SplitProtocolServer,
+};
+pub use strict_transport_security::{
+	StrictTransportSecurity, StrictTransportSecurityFuture, StrictTransportSecurityLayer,
 };
-pub use upgrade_http::{UpgradeHttp, UpgradeHttpFuture, UpgradeHttpLayer};
-pub use {
-	axum_server, bytes, http, http_body_util, tokio, tokio_rustls, tokio_util, tower_service,
+#[cfg(unix)]
+pub use systemd::{from_systemd_dual_protocol, systemd_listeners, ActivationError};
+pub use tokio;
+pub use tokio_rustls;
+pub use tokio_util;
+pub use tower_service;
+pub use upgrade_http::{
+	BadRequest, CanonicalHost, HostFallback, PreflightPolicy, RedirectFailure, RedirectLoopPolicy,
+	UpgradeHttp, UpgradeHttpFuture, UpgradeHttpLayer, WebSocketPolicy,
 };
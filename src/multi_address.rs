@@ -0,0 +1,294 @@
+//! Multi-address server implementation.
+//!
+//! See [`bind_dual_protocol_all()`] and [`MultiAddressServer`].
+
+use std::future::Future;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use axum_server::service::{MakeService, SendService};
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use axum_server::{Handle, Server};
+use http::Request;
+use hyper::body::Incoming;
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+
+use crate::split_protocol::try_join_all;
+#[cfg(feature = "acme")]
+use crate::Acme;
+use crate::{
+	BadRequest, DualProtocolAcceptor, DualProtocolAcceptorFuture, DualProtocolService,
+	ForwardedProtocolLayer, PeerAddr, ServerExt, StrictTransportSecurityLayer, UpgradeHttpLayer,
+};
+
+/// Create a [`MultiAddressServer`] that will bind to all provided addresses,
+/// accepting both HTTP and HTTPS on each port.
+///
+/// All addresses are bound immediately, so binding to port `0` reports the
+/// actual ports through [`MultiAddressHandle::listening()`].
+///
+/// # Errors
+///
+/// If no addresses are provided or binding to any of them fails.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+/// # use axum::{routing, Router};
+/// # use axum_server::tls_rustls::RustlsConfig;
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let app = Router::new();
+/// # let certificate = rcgen::generate_simple_self_signed([])?;
+/// # let private_key = certificate.key_pair.serialize_der();
+/// # let certificate = vec![certificate.cert.der().to_vec()];
+/// # let config = RustlsConfig::from_der(certificate, private_key).await?;
+/// #
+/// axum_server_dual_protocol::bind_dual_protocol_all(
+/// 	[
+/// 		SocketAddr::from((Ipv4Addr::UNSPECIFIED, 443)),
+/// 		SocketAddr::from((Ipv6Addr::UNSPECIFIED, 443)),
+/// 	],
+/// 	config,
+/// )?
+/// .serve(app.into_make_service())
+/// .await?;
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub fn bind_dual_protocol_all<Addresses>(
+	addresses: Addresses,
+	config: RustlsConfig,
+) -> io::Result<MultiAddressServer>
+where
+	Addresses: IntoIterator<Item = SocketAddr>,
+{
+	let listeners = addresses
+		.into_iter()
+		.map(TcpListener::bind)
+		.collect::<io::Result<Vec<_>>>()?;
+
+	from_tcp_dual_protocol_all(listeners, config)
+}
+
+/// Create a [`MultiAddressServer`] from existing [`TcpListener`]s, accepting
+/// both HTTP and HTTPS on each port.
+///
+/// # Errors
+///
+/// If no listeners are provided or the local address of any of them can't be
+/// determined.
+pub fn from_tcp_dual_protocol_all<Listeners>(
+	listeners: Listeners,
+	config: RustlsConfig,
+) -> io::Result<MultiAddressServer>
+where
+	Listeners: IntoIterator<Item = TcpListener>,
+{
+	MultiAddressServer::new(listeners.into_iter().collect(), config)
+}
+
+/// Serves the same app with one [`DualProtocolAcceptor`] on multiple
+/// listeners, e.g. on IPv4 and IPv6 or on several interfaces.
+///
+/// All configuration through [`ServerExt`] applies to every listener and a
+/// single [`MultiAddressHandle`] controls all of them. See
+/// [`bind_dual_protocol_all()`] for easy creation.
+#[derive(Debug)]
+pub struct MultiAddressServer<Fallback = BadRequest> {
+	/// Listeners to serve on.
+	listeners: Vec<TcpListener>,
+	/// Local addresses of all listeners.
+	addresses: Vec<SocketAddr>,
+	/// [`DualProtocolAcceptor`] shared by all listeners.
+	acceptor: DualProtocolAcceptor<RustlsAcceptor, Fallback>,
+	/// [`MultiAddressHandle`] shared by all listeners.
+	handle: MultiAddressHandle,
+}
+
+impl MultiAddressServer {
+	/// Creates a new [`MultiAddressServer`].
+	fn new(listeners: Vec<TcpListener>, config: RustlsConfig) -> io::Result<Self> {
+		if listeners.is_empty() {
+			return Err(io::Error::new(
+				ErrorKind::InvalidInput,
+				"no addresses or listeners to serve on",
+			));
+		}
+
+		let addresses = listeners
+			.iter()
+			.map(TcpListener::local_addr)
+			.collect::<io::Result<_>>()?;
+
+		Ok(Self {
+			listeners,
+			addresses,
+			acceptor: DualProtocolAcceptor::new(config),
+			handle: MultiAddressHandle::new(),
+		})
+	}
+}
+
+impl<Fallback> MultiAddressServer<Fallback> {
+	/// Returns the local addresses of all listeners.
+	#[must_use]
+	pub fn addresses(&self) -> &[SocketAddr] {
+		&self.addresses
+	}
+
+	/// Provide a [`MultiAddressHandle`] for additional utilities.
+	#[must_use]
+	pub fn handle(mut self, handle: MultiAddressHandle) -> Self {
+		self.handle = handle;
+		self
+	}
+
+	/// Returns a reference to the [`DualProtocolAcceptor`].
+	#[must_use]
+	pub const fn get_ref(&self) -> &DualProtocolAcceptor<RustlsAcceptor, Fallback> {
+		&self.acceptor
+	}
+
+	/// Returns a mutable reference to the [`DualProtocolAcceptor`].
+	pub fn get_mut(&mut self) -> &mut DualProtocolAcceptor<RustlsAcceptor, Fallback> {
+		&mut self.acceptor
+	}
+
+	/// Serve the provided make-[`Service`](tower_service::Service) on all
+	/// listeners.
+	///
+	/// # Errors
+	///
+	/// If any [`Server::serve()`] fails.
+	pub async fn serve<M>(self, make_service: M) -> io::Result<()>
+	where
+		M: MakeService<SocketAddr, Request<Incoming>> + Clone,
+		M::Service: Clone,
+		Fallback: 'static + Clone + Send + Sync,
+		DualProtocolService<M::Service, Fallback>: SendService<Request<Incoming>> + Send,
+		DualProtocolAcceptorFuture<M::Service, RustlsAcceptor, Fallback>: Send,
+	{
+		*self
+			.handle
+			.addresses
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = self.addresses;
+
+		let servers = self.listeners.into_iter().map(|listener| {
+			Box::pin(
+				Server::from_tcp(listener)
+					.acceptor(self.acceptor.clone())
+					.handle(self.handle.handle.clone())
+					.serve(make_service.clone()),
+			)
+		});
+
+		try_join_all(servers).await
+	}
+}
+
+impl<Fallback> ServerExt for MultiAddressServer<Fallback> {
+	type TlsStream = TlsStream<TcpStream>;
+	type WithFallback<NewFallback> = MultiAddressServer<NewFallback>;
+
+	fn set_upgrade(mut self, upgrade: bool) -> Self {
+		self.acceptor.set_upgrade(upgrade);
+		self
+	}
+
+	fn set_upgrade_layer<NewFallback: Clone>(
+		self,
+		layer: UpgradeHttpLayer<NewFallback>,
+	) -> Self::WithFallback<NewFallback> {
+		MultiAddressServer {
+			listeners: self.listeners,
+			addresses: self.addresses,
+			acceptor: self.acceptor.set_upgrade_layer(layer),
+			handle: self.handle,
+		}
+	}
+
+	fn set_forwarded_protocol(mut self, layer: ForwardedProtocolLayer) -> Self {
+		self.acceptor.set_forwarded_protocol(layer);
+		self
+	}
+
+	fn set_strict_transport_security(mut self, layer: StrictTransportSecurityLayer) -> Self {
+		self.acceptor.set_strict_transport_security(layer);
+		self
+	}
+
+	#[cfg(feature = "acme")]
+	fn set_acme(mut self, acme: Acme) -> Self {
+		self.acceptor.set_acme(acme);
+		self
+	}
+
+	fn set_alpn_handler<Id, Handler, Fut>(mut self, protocol: Id, handler: Handler) -> Self
+	where
+		Id: Into<Vec<u8>>,
+		Handler: 'static + Fn(Self::TlsStream, PeerAddr) -> Fut + Send + Sync,
+		Fut: 'static + Future<Output = ()> + Send,
+	{
+		self.acceptor.set_alpn_handler(protocol, handler);
+		self
+	}
+}
+
+/// Handle for a [`MultiAddressServer`], controlling all of its listeners at
+/// once.
+///
+/// This mirrors [`Handle`], but reports the addresses of all listeners.
+#[derive(Clone, Debug, Default)]
+pub struct MultiAddressHandle {
+	/// [`Handle`] shared by all listeners.
+	handle: Handle,
+	/// Local addresses of all listeners, set when serving starts.
+	addresses: Arc<Mutex<Vec<SocketAddr>>>,
+}
+
+impl MultiAddressHandle {
+	/// Create a new [`MultiAddressHandle`].
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Get the number of connections across all listeners.
+	#[must_use]
+	pub fn connection_count(&self) -> usize {
+		self.handle.connection_count()
+	}
+
+	/// Shutdown all listeners.
+	pub fn shutdown(&self) {
+		self.handle.shutdown();
+	}
+
+	/// Gracefully shutdown all listeners.
+	///
+	/// `None` means indefinite grace period.
+	pub fn graceful_shutdown(&self, duration: Option<Duration>) {
+		self.handle.graceful_shutdown(duration);
+	}
+
+	/// Returns the local addresses of all listeners when serving starts.
+	///
+	/// Returns [`None`] if serving failed.
+	pub async fn listening(&self) -> Option<Vec<SocketAddr>> {
+		self.handle.listening().await?;
+
+		Some(
+			self.addresses
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner)
+				.clone(),
+		)
+	}
+}
@@ -0,0 +1,328 @@
+//! Certificate reloading.
+//!
+//! See [`CertificateReloader`].
+
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use axum_server::tls_rustls::RustlsConfig;
+use rustls::crypto::aws_lc_rs::default_provider;
+use rustls::crypto::aws_lc_rs::sign::any_supported_type;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use tokio::fs;
+#[cfg(unix)]
+use tokio::signal::unix::{self, Signal, SignalKind};
+
+use crate::watch::{self, Callback};
+
+/// Serves a certificate and private key from disk, reloading them when they
+/// change.
+///
+/// The files are loaded when [`run()`](Self::run()) starts and then checked
+/// for changes every [`interval()`](Self::interval()), on Unix additionally
+/// on `SIGHUP` if enabled with [`sighup()`](Self::sighup()). A new pair is
+/// validated before it is swapped in, otherwise the current one is kept, e.g.
+/// if only one of the files was replaced so far. The outcome of every reload is
+/// reported to the callback set with [`on_reload()`](Self::on_reload()).
+///
+/// [`CertificateReloader`] is a [`ResolvesServerCert`], use
+/// [`rustls_config()`](Self::rustls_config()) or pass it to
+/// [`ConfigBuilder::with_cert_resolver()`](rustls::ConfigBuilder::with_cert_resolver())
+/// for a custom [`ServerConfig`]. It can also be called from another
+/// certificate resolver, e.g. one answering ACME challenges first.
+///
+/// All clones share the loaded certificate.
+///
+/// # Example
+///
+/// ```
+/// # use std::net::SocketAddr;
+/// # use std::time::Duration;
+/// # use axum::{routing, Router};
+/// use axum_server_dual_protocol::CertificateReloader;
+///
+/// # async fn test() -> Result<(), Box<dyn std::error::Error>> {
+/// # let app = Router::new().route("/", routing::get(|| async { "Hello, world!" }));
+/// # let address = SocketAddr::from(([127, 0, 0, 1], 0));
+/// let reloader = CertificateReloader::new("certificate.pem", "private-key.pem")
+/// 	.interval(Duration::from_secs(10))
+/// 	.on_reload(|result| {
+/// 		if let Err(error) = result {
+/// 			eprintln!("{error}");
+/// 		}
+/// 	});
+/// let config = reloader.rustls_config().await?;
+///
+/// tokio::spawn(async move { reloader.run().await });
+///
+/// axum_server_dual_protocol::bind_dual_protocol(address, config)
+/// 	.serve(app.into_make_service())
+/// 	.await?;
+/// # Ok(())
+/// # }
+/// ```
+#[cfg_attr(docsrs, doc(cfg(feature = "reload")))]
+#[derive(Clone)]
+pub struct CertificateReloader {
+	/// Path to the PEM encoded certificate chain.
+	certificate: PathBuf,
+	/// Path to the PEM encoded private key.
+	private_key: PathBuf,
+	/// How often to check the files for changes.
+	interval: Duration,
+	/// If the files are reloaded on `SIGHUP`.
+	#[cfg(unix)]
+	sighup: bool,
+	/// Receives the outcome of every reload.
+	callback: Option<Callback<ReloadError>>,
+	/// Currently loaded certificate, if any.
+	certified_key: Arc<Mutex<Option<Arc<CertifiedKey>>>>,
+}
+
+impl Debug for CertificateReloader {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		let mut debug = formatter.debug_struct("CertificateReloader");
+		let _ = debug
+			.field("certificate", &self.certificate)
+			.field("private_key", &self.private_key)
+			.field("interval", &self.interval);
+		#[cfg(unix)]
+		let _ = debug.field("sighup", &self.sighup);
+		debug
+			.field(
+				"loaded",
+				&self
+					.certified_key
+					.lock()
+					.unwrap_or_else(PoisonError::into_inner)
+					.is_some(),
+			)
+			.finish_non_exhaustive()
+	}
+}
+
+impl CertificateReloader {
+	/// Creates a new [`CertificateReloader`] for the given PEM encoded
+	/// certificate chain and private key.
+	///
+	/// Nothing is loaded until [`reload()`](Self::reload()),
+	/// [`rustls_config()`](Self::rustls_config()) or [`run()`](Self::run()) is
+	/// called, no certificate is served until then. The files are checked for
+	/// changes every 5 seconds by default.
+	pub fn new<Certificate, PrivateKey>(certificate: Certificate, private_key: PrivateKey) -> Self
+	where
+		Certificate: Into<PathBuf>,
+		PrivateKey: Into<PathBuf>,
+	{
+		Self {
+			certificate: certificate.into(),
+			private_key: private_key.into(),
+			interval: Duration::from_secs(5),
+			#[cfg(unix)]
+			sighup: false,
+			callback: None,
+			certified_key: Arc::default(),
+		}
+	}
+
+	/// Sets how often the files are checked for changes. Their contents are
+	/// compared.
+	///
+	/// # Panics
+	///
+	/// If `interval` is zero.
+	#[must_use]
+	pub fn interval(mut self, interval: Duration) -> Self {
+		assert!(!interval.is_zero(), "`interval` must be non-zero");
+		self.interval = interval;
+		self
+	}
+
+	/// Sets if the files are reloaded on `SIGHUP`, even if they didn't change.
+	/// Defaults to `false`.
+	#[cfg(unix)]
+	#[cfg_attr(docsrs, doc(cfg(unix)))]
+	#[must_use]
+	pub const fn sighup(mut self, sighup: bool) -> Self {
+		self.sighup = sighup;
+		self
+	}
+
+	/// Sets the callback receiving the outcome of every reload.
+	#[must_use]
+	pub fn on_reload<Callback>(mut self, callback: Callback) -> Self
+	where
+		Callback: 'static + Fn(Result<(), ReloadError>) + Send + Sync,
+	{
+		self.callback = Some(Arc::new(callback));
+		self
+	}
+
+	/// Loads the certificate and private key and creates a [`RustlsConfig`]
+	/// serving them.
+	///
+	/// # Errors
+	///
+	/// See [`reload()`](Self::reload()).
+	pub async fn rustls_config(&self) -> Result<RustlsConfig, ReloadError> {
+		self.reload().await?;
+
+		let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+			.with_safe_default_protocol_versions()
+			.map_err(|error| ReloadError::Crypto(error.into()))?
+			.with_no_client_auth()
+			.with_cert_resolver(Arc::new(self.clone()));
+		config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+
+		Ok(RustlsConfig::from_config(Arc::new(config)))
+	}
+
+	/// Loads the certificate and private key, replacing the current ones.
+	///
+	/// # Errors
+	///
+	/// If the files can't be read, are invalid or don't match. The current
+	/// certificate is kept in that case.
+	pub async fn reload(&self) -> Result<(), ReloadError> {
+		let (certificate, private_key) = self.read().await?;
+
+		self.load(&certificate, &private_key)
+	}
+
+	/// Load the certificate and private key and reload them whenever they
+	/// change, reporting the outcome to the callback.
+	///
+	/// # Errors
+	///
+	/// If listening for `SIGHUP` fails.
+	pub async fn run(&self) -> io::Result<Infallible> {
+		#[cfg(unix)]
+		let mut hangup = if self.sighup {
+			Some(unix::signal(SignalKind::hangup())?)
+		} else {
+			None
+		};
+
+		Ok(watch::watch(
+			self.interval,
+			|| self.read(),
+			|(certificate, private_key)| self.load(certificate, private_key),
+			|cx| {
+				poll_hangup(
+					#[cfg(unix)]
+					hangup.as_mut(),
+					cx,
+				)
+			},
+			self.callback.as_ref(),
+		)
+		.await)
+	}
+
+	/// Reads the certificate and private key.
+	async fn read(&self) -> io::Result<(Vec<u8>, Vec<u8>)> {
+		Ok((
+			fs::read(&self.certificate).await?,
+			fs::read(&self.private_key).await?,
+		))
+	}
+
+	/// Validates the PEM encoded certificate chain and private key and swaps
+	/// them in.
+	fn load(&self, certificate: &[u8], private_key: &[u8]) -> Result<(), ReloadError> {
+		let chain = CertificateDer::pem_slice_iter(certificate)
+			.collect::<Result<Vec<_>, _>>()
+			.map_err(invalid)?;
+
+		if chain.is_empty() {
+			return Err(invalid("no certificate found"));
+		}
+
+		let private_key = PrivateKeyDer::from_pem_slice(private_key).map_err(invalid)?;
+		let private_key = any_supported_type(&private_key).map_err(invalid)?;
+		let certified_key = CertifiedKey::new(chain, private_key);
+		certified_key.keys_match().map_err(invalid)?;
+
+		*self
+			.certified_key
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(certified_key));
+
+		Ok(())
+	}
+}
+
+impl ResolvesServerCert for CertificateReloader {
+	fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+		self.certified_key
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner)
+			.clone()
+	}
+}
+
+/// Polls for `SIGHUP`, never ready if not listening for it.
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn poll_hangup(#[cfg(unix)] hangup: Option<&mut Signal>, cx: &mut Context<'_>) -> Poll<()> {
+	#[cfg(unix)]
+	if let Some(hangup) = hangup {
+		if let Poll::Ready(Some(())) = hangup.poll_recv(cx) {
+			return Poll::Ready(());
+		}
+	}
+
+	Poll::Pending
+}
+
+/// Reason why [`CertificateReloader`] failed to reload the certificate and
+/// private key.
+#[cfg_attr(docsrs, doc(cfg(feature = "reload")))]
+#[derive(Debug)]
+pub enum ReloadError {
+	/// Reading the certificate or private key failed.
+	Io(io::Error),
+	/// The certificate or private key is invalid or they don't match.
+	Invalid(Box<dyn Error + Send + Sync>),
+	/// Creating the [`ServerConfig`] failed.
+	Crypto(Box<dyn Error + Send + Sync>),
+}
+
+impl Display for ReloadError {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Io(error) => write!(formatter, "failed to read the certificate: {error}"),
+			Self::Invalid(error) => write!(formatter, "invalid certificate: {error}"),
+			Self::Crypto(error) => write!(formatter, "cryptographic operation failed: {error}"),
+		}
+	}
+}
+
+impl Error for ReloadError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::Io(error) => Some(error),
+			Self::Invalid(error) | Self::Crypto(error) => Some(error.as_ref()),
+		}
+	}
+}
+
+impl From<io::Error> for ReloadError {
+	fn from(error: io::Error) -> Self {
+		Self::Io(error)
+	}
+}
+
+/// Creates a [`ReloadError::Invalid`].
+fn invalid<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> ReloadError {
+	ReloadError::Invalid(error.into())
+}
@@ -0,0 +1,442 @@
+//! Guards requiring a specific [`Protocol`] implementation.
+//!
+//! See [`RequireProtocol`].
+
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+#[cfg(feature = "axum")]
+use axum::async_trait;
+#[cfg(feature = "axum")]
+use axum::extract::FromRequestParts;
+#[cfg(feature = "axum")]
+use axum::response::{IntoResponse, Response as AxumResponse};
+use bytes::Bytes;
+use http::header::CONTENT_TYPE;
+#[cfg(feature = "axum")]
+use http::request::Parts;
+use http::{HeaderValue, Request, Response, StatusCode};
+use http_body_util::{Either, Full};
+use pin_project::pin_project;
+use tower_layer::Layer;
+use tower_service::Service as TowerService;
+
+use crate::Protocol;
+
+/// How requests not using the required [`Protocol`] are rejected.
+///
+/// The extractors `RequireTls` and `RequirePlain` use the
+/// [`ProtocolRejection`] found in
+/// [`Request::extensions()`](Request::extensions()), so it can be configured
+/// with an `axum::Extension` layer. The layers are configured with
+/// [`RequireTlsLayer::rejection()`] and [`RequirePlainLayer::rejection()`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ProtocolRejection {
+	/// Respond with a
+	/// [403 "Forbidden"](https://tools.ietf.org/html/rfc7231#section-6.5.3)
+	/// status code.
+	#[default]
+	Forbidden,
+	/// Respond with a
+	/// [421 "Misdirected Request"](https://tools.ietf.org/html/rfc7540#section-9.1.2)
+	/// status code.
+	MisdirectedRequest,
+	/// Respond with a
+	/// [426 "Upgrade Required"](https://tools.ietf.org/html/rfc7231#section-6.5.15)
+	/// status code. No `Upgrade` header is sent, HTTPS isn't a protocol that
+	/// can be upgraded to on the same connection.
+	UpgradeRequired,
+}
+
+impl ProtocolRejection {
+	/// Builds the response rejecting a request not using the required
+	/// [`Protocol`].
+	fn response(self, required: Protocol) -> Response<Full<Bytes>> {
+		let status = match self {
+			Self::Forbidden => StatusCode::FORBIDDEN,
+			Self::MisdirectedRequest => StatusCode::MISDIRECTED_REQUEST,
+			Self::UpgradeRequired => StatusCode::UPGRADE_REQUIRED,
+		};
+
+		let body: &[u8] = match required {
+			Protocol::Tls => b"This resource requires HTTPS.",
+			Protocol::Plain => b"This resource requires HTTP.",
+		};
+
+		Response::builder()
+			.status(status)
+			.header(
+				CONTENT_TYPE,
+				HeaderValue::from_static("text/plain; charset=utf-8"),
+			)
+			.body(Full::new(Bytes::from_static(body)))
+			.expect("invalid header or body")
+	}
+}
+
+/// Builds the response to a request without a [`Protocol`].
+fn missing_protocol() -> Response<Full<Bytes>> {
+	Response::builder()
+		.status(StatusCode::INTERNAL_SERVER_ERROR)
+		.header(
+			CONTENT_TYPE,
+			HeaderValue::from_static("text/plain; charset=utf-8"),
+		)
+		.body(Full::new(Bytes::from_static(
+			b"Couldn't determine the protocol of the request. Insert it with `ProtocolLayer`.",
+		)))
+		.expect("invalid header or body")
+}
+
+/// [`Layer`] rejecting requests not using TLS.
+///
+/// See [`RequireProtocol`] for more details.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct RequireTlsLayer {
+	/// How to reject requests.
+	rejection: ProtocolRejection,
+}
+
+impl RequireTlsLayer {
+	/// Creates a new [`RequireTlsLayer`].
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			rejection: ProtocolRejection::Forbidden,
+		}
+	}
+
+	/// Set how requests not using TLS are rejected. Defaults to
+	/// [`ProtocolRejection::Forbidden`].
+	#[must_use]
+	pub const fn rejection(mut self, rejection: ProtocolRejection) -> Self {
+		self.rejection = rejection;
+		self
+	}
+}
+
+impl<Service> Layer<Service> for RequireTlsLayer {
+	type Service = RequireProtocol<Service>;
+
+	fn layer(&self, inner: Service) -> Self::Service {
+		RequireProtocol::new(inner, Protocol::Tls, self.rejection)
+	}
+}
+
+/// [`Layer`] rejecting requests using TLS.
+///
+/// See [`RequireProtocol`] for more details.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct RequirePlainLayer {
+	/// How to reject requests.
+	rejection: ProtocolRejection,
+}
+
+impl RequirePlainLayer {
+	/// Creates a new [`RequirePlainLayer`].
+	#[must_use]
+	pub const fn new() -> Self {
+		Self {
+			rejection: ProtocolRejection::Forbidden,
+		}
+	}
+
+	/// Set how requests using TLS are rejected. Defaults to
+	/// [`ProtocolRejection::Forbidden`].
+	#[must_use]
+	pub const fn rejection(mut self, rejection: ProtocolRejection) -> Self {
+		self.rejection = rejection;
+		self
+	}
+}
+
+impl<Service> Layer<Service> for RequirePlainLayer {
+	type Service = RequireProtocol<Service>;
+
+	fn layer(&self, inner: Service) -> Self::Service {
+		RequireProtocol::new(inner, Protocol::Plain, self.rejection)
+	}
+}
+
+/// [`Service`](TowerService) rejecting requests not using the required
+/// [`Protocol`], e.g. to only serve a login form over TLS while the rest of the
+/// app is available over plain HTTP.
+///
+/// Requests without a [`Protocol`] are responded to with a
+/// [500 "Internal Server Error"](https://tools.ietf.org/html/rfc7231#section-6.6.1)
+/// status code, as it is only inserted by
+/// [`DualProtocolService`](crate::DualProtocolService). With a different
+/// server insert it with [`ProtocolLayer`](crate::ProtocolLayer). See
+/// [`RequireTlsLayer`] and [`RequirePlainLayer`].
+#[derive(Clone, Debug)]
+pub struct RequireProtocol<Service> {
+	/// Wrapped user-provided [`Service`](TowerService).
+	service: Service,
+	/// The required [`Protocol`].
+	required: Protocol,
+	/// How to reject requests.
+	rejection: ProtocolRejection,
+}
+
+impl<Service> RequireProtocol<Service> {
+	/// Creates a new [`RequireProtocol`].
+	pub const fn new(service: Service, required: Protocol, rejection: ProtocolRejection) -> Self {
+		Self {
+			service,
+			required,
+			rejection,
+		}
+	}
+
+	/// Consumes the [`RequireProtocol`], returning the wrapped
+	/// [`Service`](TowerService).
+	pub fn into_inner(self) -> Service {
+		self.service
+	}
+
+	/// Return a reference to the wrapped [`Service`](TowerService).
+	pub const fn get_ref(&self) -> &Service {
+		&self.service
+	}
+
+	/// Return a mutable reference to the wrapped [`Service`](TowerService).
+	pub fn get_mut(&mut self) -> &mut Service {
+		&mut self.service
+	}
+}
+
+impl<Service, RequestBody, ResponseBody> TowerService<Request<RequestBody>>
+	for RequireProtocol<Service>
+where
+	Service: TowerService<Request<RequestBody>, Response = Response<ResponseBody>>,
+{
+	type Response = Response<Either<ResponseBody, Full<Bytes>>>;
+	type Error = Service::Error;
+	type Future = RequireProtocolFuture<Service, Request<RequestBody>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: Request<RequestBody>) -> Self::Future {
+		let response = match req.extensions().get::<Protocol>() {
+			Some(protocol) if *protocol == self.required => {
+				return RequireProtocolFuture(FutureServe::Service(self.service.call(req)))
+			}
+			Some(_) => self.rejection.response(self.required),
+			None => missing_protocol(),
+		};
+
+		RequireProtocolFuture(FutureServe::Reject(Some(response)))
+	}
+}
+
+/// [`Future`](TowerService::Future) type for [`RequireProtocol`].
+#[pin_project]
+pub struct RequireProtocolFuture<Service, Request>(#[pin] FutureServe<Service, Request>)
+where
+	Service: TowerService<Request>;
+
+/// Holds [`Future`] to serve for [`RequireProtocolFuture`].
+#[derive(Debug)]
+#[pin_project(project = RequireProtocolFutureProj)]
+enum FutureServe<Service, Request>
+where
+	Service: TowerService<Request>,
+{
+	/// The request uses the required [`Protocol`], so we will pass-through the
+	/// wrapped [`Service`](TowerService).
+	Service(#[pin] Service::Future),
+	/// The request doesn't use the required [`Protocol`], so we will reject
+	/// it.
+	Reject(Option<Response<Full<Bytes>>>),
+}
+
+// Rust can't figure out the correct bounds.
+impl<Service, Request> Debug for RequireProtocolFuture<Service, Request>
+where
+	Service: TowerService<Request>,
+	FutureServe<Service, Request>: Debug,
+{
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter
+			.debug_tuple("RequireProtocolFuture")
+			.field(&self.0)
+			.finish()
+	}
+}
+
+impl<Service, Request, ResponseBody> Future for RequireProtocolFuture<Service, Request>
+where
+	Service: TowerService<Request, Response = Response<ResponseBody>>,
+{
+	type Output = Result<Response<Either<ResponseBody, Full<Bytes>>>, Service::Error>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		match self.project().0.project() {
+			RequireProtocolFutureProj::Service(future) => future
+				.poll(cx)
+				.map_ok(|response| response.map(Either::Left)),
+			RequireProtocolFutureProj::Reject(response) => Poll::Ready(Ok(response
+				.take()
+				.expect("polled again after `Poll::Ready`")
+				.map(Either::Right))),
+		}
+	}
+}
+
+/// Rejection of [`Protocol`] when used as an extractor, if the request has no
+/// [`Protocol`]. Responds with a
+/// [500 "Internal Server Error"](https://tools.ietf.org/html/rfc7231#section-6.6.1)
+/// status code.
+#[cfg(feature = "axum")]
+#[cfg_attr(docsrs, doc(cfg(feature = "axum")))]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct MissingProtocol;
+
+#[cfg(feature = "axum")]
+impl IntoResponse for MissingProtocol {
+	fn into_response(self) -> AxumResponse {
+		missing_protocol().into_response()
+	}
+}
+
+#[cfg(feature = "axum")]
+#[async_trait]
+impl<State: Sync> FromRequestParts<State> for Protocol {
+	type Rejection = MissingProtocol;
+
+	async fn from_request_parts(parts: &mut Parts, _: &State) -> Result<Self, Self::Rejection> {
+		parts
+			.extensions
+			.get::<Self>()
+			.copied()
+			.ok_or(MissingProtocol)
+	}
+}
+
+/// Rejection of [`RequireTls`] and [`RequirePlain`], if the request doesn't
+/// use the required [`Protocol`].
+///
+/// See [`ProtocolRejection`].
+#[cfg(feature = "axum")]
+#[cfg_attr(docsrs, doc(cfg(feature = "axum")))]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct WrongProtocol {
+	/// The required [`Protocol`].
+	required: Protocol,
+	/// How to reject the request.
+	rejection: ProtocolRejection,
+}
+
+#[cfg(feature = "axum")]
+impl WrongProtocol {
+	/// Checks the [`Protocol`] of the request.
+	fn check(parts: &Parts, required: Protocol) -> Result<(), RequireProtocolRejection> {
+		match parts.extensions.get::<Protocol>() {
+			Some(protocol) if *protocol == required => Ok(()),
+			Some(_) => Err(RequireProtocolRejection::WrongProtocol(Self {
+				required,
+				rejection: parts
+					.extensions
+					.get::<ProtocolRejection>()
+					.copied()
+					.unwrap_or_default(),
+			})),
+			None => Err(RequireProtocolRejection::MissingProtocol(MissingProtocol)),
+		}
+	}
+
+	/// Returns the required [`Protocol`].
+	#[must_use]
+	pub const fn required(&self) -> Protocol {
+		self.required
+	}
+}
+
+#[cfg(feature = "axum")]
+impl IntoResponse for WrongProtocol {
+	fn into_response(self) -> AxumResponse {
+		self.rejection.response(self.required).into_response()
+	}
+}
+
+/// Rejection of [`RequireTls`] and [`RequirePlain`].
+#[cfg(feature = "axum")]
+#[cfg_attr(docsrs, doc(cfg(feature = "axum")))]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RequireProtocolRejection {
+	/// The request has no [`Protocol`].
+	MissingProtocol(MissingProtocol),
+	/// The request doesn't use the required [`Protocol`].
+	WrongProtocol(WrongProtocol),
+}
+
+#[cfg(feature = "axum")]
+impl IntoResponse for RequireProtocolRejection {
+	fn into_response(self) -> AxumResponse {
+		match self {
+			Self::MissingProtocol(rejection) => rejection.into_response(),
+			Self::WrongProtocol(rejection) => rejection.into_response(),
+		}
+	}
+}
+
+/// Extractor rejecting requests not using TLS with a [`WrongProtocol`].
+///
+/// Requests without a [`Protocol`] are rejected with a [`MissingProtocol`],
+/// see [`RequireProtocol`] for details. See
+/// [`ProtocolRejection`] to configure the rejection and [`RequireTlsLayer`]
+/// to guard entire [`Service`](TowerService)s.
+///
+/// ```
+/// # use axum::{routing, Router};
+/// use axum_server_dual_protocol::RequireTls;
+///
+/// let app = Router::new()
+/// 	.route("/", routing::get(|| async { "Hello, World!" }))
+/// 	.route(
+/// 		"/login",
+/// 		routing::get(|_: RequireTls| async { "Hello, secure World!" }),
+/// 	);
+/// # // To help with type inference.
+/// # axum_server::bind(std::net::SocketAddr::from(([127, 0, 0, 1], 0)))
+/// # 	.serve(app.into_make_service());
+/// ```
+#[cfg(feature = "axum")]
+#[cfg_attr(docsrs, doc(cfg(feature = "axum")))]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct RequireTls;
+
+#[cfg(feature = "axum")]
+#[async_trait]
+impl<State: Sync> FromRequestParts<State> for RequireTls {
+	type Rejection = RequireProtocolRejection;
+
+	async fn from_request_parts(parts: &mut Parts, _: &State) -> Result<Self, Self::Rejection> {
+		WrongProtocol::check(parts, Protocol::Tls).map(|()| Self)
+	}
+}
+
+/// Extractor rejecting requests using TLS with a [`WrongProtocol`].
+///
+/// Requests without a [`Protocol`] are rejected with a [`MissingProtocol`],
+/// see [`RequireProtocol`] for details. See
+/// [`ProtocolRejection`] to configure the rejection and [`RequirePlainLayer`]
+/// to guard entire [`Service`](TowerService)s.
+#[cfg(feature = "axum")]
+#[cfg_attr(docsrs, doc(cfg(feature = "axum")))]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct RequirePlain;
+
+#[cfg(feature = "axum")]
+#[async_trait]
+impl<State: Sync> FromRequestParts<State> for RequirePlain {
+	type Rejection = RequireProtocolRejection;
+
+	async fn from_request_parts(parts: &mut Parts, _: &State) -> Result<Self, Self::Rejection> {
+		WrongProtocol::check(parts, Protocol::Plain).map(|()| Self)
+	}
+}
@@ -0,0 +1,273 @@
+//! Separate services for HTTP and HTTPS implementation.
+//!
+//! See [`SplitMakeService`] and [`SplitService`].
+
+use std::fmt::{self, Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use http::{Request, Response};
+use http_body_util::Either;
+use pin_project::pin_project;
+use tower_service::Service as TowerService;
+
+use crate::Protocol;
+
+/// Make-[`Service`](TowerService) creating a [`SplitService`] from two
+/// make-[`Service`](TowerService)s, one serving HTTP and one serving HTTPS.
+///
+/// This can be passed to [`Server::serve()`](axum_server::Server::serve()):
+/// ```no_run
+/// # use axum::{routing, Router};
+/// # use axum_server::tls_rustls::RustlsConfig;
+/// use axum_server_dual_protocol::{ServerExt, SplitMakeService, UpgradeHttpLayer};
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let address = std::net::SocketAddr::from(([127, 0, 0, 1], 0));
+/// # let certificate = rcgen::generate_simple_self_signed([])?;
+/// # let private_key = certificate.key_pair.serialize_der();
+/// # let certificate = vec![certificate.cert.der().to_vec()];
+/// # let config = RustlsConfig::from_der(certificate, private_key).await?;
+/// let plain = Router::new().route("/health", routing::get(|| async { "OK" }));
+/// let tls = Router::new().route("/", routing::get(|| async { "Hello, secure World!" }));
+///
+/// axum_server_dual_protocol::bind_dual_protocol(address, config)
+/// 	.set_upgrade_layer(UpgradeHttpLayer::new().exempt_path_prefix("/health"))
+/// 	.serve(SplitMakeService::new(
+/// 		plain.into_make_service(),
+/// 		tls.into_make_service(),
+/// 	))
+/// 	.await?;
+/// #
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct SplitMakeService<Plain, Tls> {
+	/// Make-[`Service`](TowerService) for HTTP.
+	plain: Plain,
+	/// Make-[`Service`](TowerService) for HTTPS.
+	tls: Tls,
+}
+
+impl<Plain, Tls> SplitMakeService<Plain, Tls> {
+	/// Creates a new [`SplitMakeService`].
+	pub const fn new(plain: Plain, tls: Tls) -> Self {
+		Self { plain, tls }
+	}
+}
+
+impl<Plain, Tls, Target> TowerService<Target> for SplitMakeService<Plain, Tls>
+where
+	Plain: TowerService<Target>,
+	Tls: TowerService<Target, Error = Plain::Error>,
+	Target: Clone,
+{
+	type Response = SplitService<Plain::Response, Tls::Response>;
+	type Error = Plain::Error;
+	type Future = SplitMakeServiceFuture<Plain, Tls, Target>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		match (self.plain.poll_ready(cx)?, self.tls.poll_ready(cx)?) {
+			(Poll::Ready(()), Poll::Ready(())) => Poll::Ready(Ok(())),
+			_ => Poll::Pending,
+		}
+	}
+
+	fn call(&mut self, req: Target) -> Self::Future {
+		SplitMakeServiceFuture {
+			plain: self.plain.call(req.clone()),
+			plain_service: None,
+			tls: self.tls.call(req),
+			tls_service: None,
+		}
+	}
+}
+
+/// [`Future`](TowerService::Future) type for [`SplitMakeService`].
+#[pin_project]
+pub struct SplitMakeServiceFuture<Plain, Tls, Target>
+where
+	Plain: TowerService<Target>,
+	Tls: TowerService<Target>,
+{
+	/// [`Future`] creating the [`Service`](TowerService) for HTTP.
+	#[pin]
+	plain: Plain::Future,
+	/// The [`Service`](TowerService) for HTTP, once created.
+	plain_service: Option<Plain::Response>,
+	/// [`Future`] creating the [`Service`](TowerService) for HTTPS.
+	#[pin]
+	tls: Tls::Future,
+	/// The [`Service`](TowerService) for HTTPS, once created.
+	tls_service: Option<Tls::Response>,
+}
+
+// Rust can't figure out the correct bounds.
+impl<Plain, Tls, Target> Debug for SplitMakeServiceFuture<Plain, Tls, Target>
+where
+	Plain: TowerService<Target>,
+	Plain::Future: Debug,
+	Plain::Response: Debug,
+	Tls: TowerService<Target>,
+	Tls::Future: Debug,
+	Tls::Response: Debug,
+{
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter
+			.debug_struct("SplitMakeServiceFuture")
+			.field("plain", &self.plain)
+			.field("plain_service", &self.plain_service)
+			.field("tls", &self.tls)
+			.field("tls_service", &self.tls_service)
+			.finish()
+	}
+}
+
+impl<Plain, Tls, Target> Future for SplitMakeServiceFuture<Plain, Tls, Target>
+where
+	Plain: TowerService<Target>,
+	Tls: TowerService<Target, Error = Plain::Error>,
+{
+	type Output = Result<SplitService<Plain::Response, Tls::Response>, Plain::Error>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = self.project();
+
+		if this.plain_service.is_none() {
+			if let Poll::Ready(service) = this.plain.poll(cx)? {
+				*this.plain_service = Some(service);
+			}
+		}
+
+		if this.tls_service.is_none() {
+			if let Poll::Ready(service) = this.tls.poll(cx)? {
+				*this.tls_service = Some(service);
+			}
+		}
+
+		if this.plain_service.is_some() && this.tls_service.is_some() {
+			Poll::Ready(Ok(SplitService::new(
+				this.plain_service.take().expect("checked above"),
+				this.tls_service.take().expect("checked above"),
+			)))
+		} else {
+			Poll::Pending
+		}
+	}
+}
+
+/// [`Service`](TowerService) passing requests to one of two
+/// [`Service`](TowerService)s depending on their [`Protocol`].
+///
+/// This allows serving only a minimal app over HTTP, e.g. redirects, ACME
+/// challenges and health checks, while serving the full app over HTTPS.
+/// Requests without a [`Protocol`] are passed to the HTTP
+/// [`Service`](TowerService).
+///
+/// See [`SplitMakeService`] for usage with [`Server`](axum_server::Server).
+#[derive(Clone, Debug)]
+pub struct SplitService<Plain, Tls> {
+	/// [`Service`](TowerService) for HTTP.
+	plain: Plain,
+	/// [`Service`](TowerService) for HTTPS.
+	tls: Tls,
+}
+
+impl<Plain, Tls> SplitService<Plain, Tls> {
+	/// Creates a new [`SplitService`].
+	pub const fn new(plain: Plain, tls: Tls) -> Self {
+		Self { plain, tls }
+	}
+
+	/// Consumes the [`SplitService`], returning the wrapped
+	/// [`Service`](TowerService)s for HTTP and HTTPS.
+	pub fn into_inner(self) -> (Plain, Tls) {
+		(self.plain, self.tls)
+	}
+}
+
+impl<Plain, Tls, RequestBody, PlainBody, TlsBody> TowerService<Request<RequestBody>>
+	for SplitService<Plain, Tls>
+where
+	Plain: TowerService<Request<RequestBody>, Response = Response<PlainBody>>,
+	Tls: TowerService<Request<RequestBody>, Response = Response<TlsBody>, Error = Plain::Error>,
+{
+	type Response = Response<Either<PlainBody, TlsBody>>;
+	type Error = Plain::Error;
+	type Future = SplitServiceFuture<Plain, Tls, Request<RequestBody>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		// We don't know which one will be called, so both have to be ready.
+		match (self.plain.poll_ready(cx)?, self.tls.poll_ready(cx)?) {
+			(Poll::Ready(()), Poll::Ready(())) => Poll::Ready(Ok(())),
+			_ => Poll::Pending,
+		}
+	}
+
+	fn call(&mut self, req: Request<RequestBody>) -> Self::Future {
+		match req.extensions().get::<Protocol>() {
+			Some(Protocol::Tls) => SplitServiceFuture(FutureServe::Tls(self.tls.call(req))),
+			Some(Protocol::Plain) | None => {
+				SplitServiceFuture(FutureServe::Plain(self.plain.call(req)))
+			}
+		}
+	}
+}
+
+/// [`Future`](TowerService::Future) type for [`SplitService`].
+#[pin_project]
+pub struct SplitServiceFuture<Plain, Tls, Request>(#[pin] FutureServe<Plain, Tls, Request>)
+where
+	Plain: TowerService<Request>,
+	Tls: TowerService<Request>;
+
+/// Holds [`Future`] to serve for [`SplitServiceFuture`].
+#[derive(Debug)]
+#[pin_project(project = SplitServiceFutureProj)]
+enum FutureServe<Plain, Tls, Request>
+where
+	Plain: TowerService<Request>,
+	Tls: TowerService<Request>,
+{
+	/// The request was using the HTTP protocol.
+	Plain(#[pin] Plain::Future),
+	/// The request was using the HTTPS protocol.
+	Tls(#[pin] Tls::Future),
+}
+
+// Rust can't figure out the correct bounds.
+impl<Plain, Tls, Request> Debug for SplitServiceFuture<Plain, Tls, Request>
+where
+	Plain: TowerService<Request>,
+	Tls: TowerService<Request>,
+	FutureServe<Plain, Tls, Request>: Debug,
+{
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		formatter
+			.debug_tuple("SplitServiceFuture")
+			.field(&self.0)
+			.finish()
+	}
+}
+
+impl<Plain, Tls, Request, PlainBody, TlsBody> Future for SplitServiceFuture<Plain, Tls, Request>
+where
+	Plain: TowerService<Request, Response = Response<PlainBody>>,
+	Tls: TowerService<Request, Response = Response<TlsBody>, Error = Plain::Error>,
+{
+	type Output = Result<Response<Either<PlainBody, TlsBody>>, Plain::Error>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		match self.project().0.project() {
+			SplitServiceFutureProj::Plain(future) => future
+				.poll(cx)
+				.map_ok(|response| response.map(Either::Left)),
+			SplitServiceFutureProj::Tls(future) => future
+				.poll(cx)
+				.map_ok(|response| response.map(Either::Right)),
+		}
+	}
+}
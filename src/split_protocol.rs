@@ -0,0 +1,376 @@
+//! Split-port server implementation.
+//!
+//! See [`bind_split_protocol()`] and [`ProtocolLayer`].
+
+use std::future::{self, Future};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum_server::service::{MakeService, SendService};
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use axum_server::{Handle, Server};
+use http::Request;
+use hyper::body::Incoming;
+use tokio::net::TcpStream;
+use tokio_rustls::server::TlsStream;
+use tower_layer::Layer;
+use tower_service::Service as TowerService;
+
+#[cfg(feature = "acme")]
+use crate::Acme;
+use crate::{
+	BadRequest, DualProtocolAcceptor, DualProtocolAcceptorFuture, DualProtocolService,
+	ForwardedProtocolLayer, PeerAddr, Protocol, ServerExt, StrictTransportSecurityLayer,
+	UpgradeHttpLayer,
+};
+
+/// Create a [`SplitProtocolServer`] that will bind to the provided addresses,
+/// accepting HTTP on `plain` and HTTPS on `tls`.
+///
+/// Both addresses are bound immediately, so the ports are known before
+/// serving, e.g. when binding to port `0`.
+///
+/// # Errors
+///
+/// If binding to either address fails.
+///
+/// # Example
+///
+/// ```no_run
+/// # use axum::{routing, Router};
+/// # use axum_server::tls_rustls::RustlsConfig;
+/// use axum_server_dual_protocol::ServerExt;
+///
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let app = Router::new();
+/// # let certificate = rcgen::generate_simple_self_signed([])?;
+/// # let private_key = certificate.key_pair.serialize_der();
+/// # let certificate = vec![certificate.cert.der().to_vec()];
+/// # let config = RustlsConfig::from_der(certificate, private_key).await?;
+/// #
+/// axum_server_dual_protocol::bind_split_protocol(
+/// 	([0, 0, 0, 0], 80).into(),
+/// 	([0, 0, 0, 0], 443).into(),
+/// 	config,
+/// )?
+/// // Redirects to port 443.
+/// .set_upgrade(true)
+/// .serve(app.into_make_service())
+/// .await?;
+/// #
+/// # Ok(())
+/// # }
+/// ```
+pub fn bind_split_protocol(
+	plain: SocketAddr,
+	tls: SocketAddr,
+	config: RustlsConfig,
+) -> io::Result<SplitProtocolServer> {
+	from_tcp_split_protocol(TcpListener::bind(plain)?, TcpListener::bind(tls)?, config)
+}
+
+/// Create a [`SplitProtocolServer`] from existing [`TcpListener`]s, accepting
+/// HTTP on `plain` and HTTPS on `tls`.
+///
+/// # Errors
+///
+/// If the local address of either listener can't be determined.
+pub fn from_tcp_split_protocol(
+	plain: TcpListener,
+	tls: TcpListener,
+	config: RustlsConfig,
+) -> io::Result<SplitProtocolServer> {
+	SplitProtocolServer::new(plain, tls, config)
+}
+
+/// [`Server`] of one listener of a [`SplitProtocolServer`].
+type SplitServer<Fallback> = Server<DualProtocolAcceptor<RustlsAcceptor, Fallback>>;
+
+/// Serves the same app on separate HTTP and HTTPS listeners.
+///
+/// Connections are not inspected, instead [`Protocol::Plain`] or
+/// [`Protocol::Tls`] is inserted depending on the listener they were accepted
+/// on. When upgrading HTTP connections, redirects point to the port of the
+/// HTTPS listener, unless a port was already configured with
+/// [`UpgradeHttpLayer::redirect_port()`].
+///
+/// All configuration through [`ServerExt`] applies to both listeners. See
+/// [`bind_split_protocol()`] for easy creation.
+#[derive(Debug)]
+pub struct SplitProtocolServer<Fallback = BadRequest> {
+	/// [`Server`] accepting HTTP.
+	plain: SplitServer<Fallback>,
+	/// [`Server`] accepting HTTPS.
+	tls: SplitServer<Fallback>,
+	/// Local address of the HTTP listener.
+	plain_addr: SocketAddr,
+	/// Local address of the HTTPS listener.
+	tls_addr: SocketAddr,
+}
+
+impl SplitProtocolServer {
+	/// Creates a new [`SplitProtocolServer`].
+	fn new(plain: TcpListener, tls: TcpListener, config: RustlsConfig) -> io::Result<Self> {
+		let plain_addr = plain.local_addr()?;
+		let tls_addr = tls.local_addr()?;
+		let handle = Handle::new();
+
+		let mut plain_acceptor = DualProtocolAcceptor::new(config.clone());
+		plain_acceptor.set_protocol(Some(Protocol::Plain));
+		let mut tls_acceptor = DualProtocolAcceptor::new(config);
+		tls_acceptor.set_protocol(Some(Protocol::Tls));
+
+		Ok(Self {
+			plain: Server::from_tcp(plain)
+				.acceptor(plain_acceptor)
+				.handle(handle.clone()),
+			tls: Server::from_tcp(tls).acceptor(tls_acceptor).handle(handle),
+			plain_addr,
+			tls_addr,
+		})
+	}
+}
+
+impl<Fallback> SplitProtocolServer<Fallback> {
+	/// Provide a [`Handle`] shared by both listeners.
+	///
+	/// Note that [`Handle::listening()`] only reports the address of whichever
+	/// listener was ready first, use [`plain_addr()`](Self::plain_addr()) and
+	/// [`tls_addr()`](Self::tls_addr()) instead.
+	#[must_use]
+	pub fn handle(self, handle: Handle) -> Self {
+		Self {
+			plain: self.plain.handle(handle.clone()),
+			tls: self.tls.handle(handle),
+			..self
+		}
+	}
+
+	/// Returns the local address of the HTTP listener.
+	#[must_use]
+	pub const fn plain_addr(&self) -> SocketAddr {
+		self.plain_addr
+	}
+
+	/// Returns the local address of the HTTPS listener.
+	#[must_use]
+	pub const fn tls_addr(&self) -> SocketAddr {
+		self.tls_addr
+	}
+
+	/// Consumes the [`SplitProtocolServer`], returning the [`Server`]s
+	/// accepting HTTP and HTTPS respectively.
+	#[must_use]
+	pub fn into_inner(self) -> (SplitServer<Fallback>, SplitServer<Fallback>) {
+		(self.plain, self.tls)
+	}
+
+	/// Serve the provided make-[`Service`](TowerService) on both listeners.
+	///
+	/// # Errors
+	///
+	/// If either [`Server::serve()`] fails.
+	pub async fn serve<M>(self, make_service: M) -> io::Result<()>
+	where
+		M: MakeService<SocketAddr, Request<Incoming>> + Clone,
+		M::Service: Clone,
+		Fallback: 'static + Clone + Send + Sync,
+		DualProtocolService<M::Service, Fallback>: SendService<Request<Incoming>> + Send,
+		DualProtocolAcceptorFuture<M::Service, RustlsAcceptor, Fallback>: Send,
+	{
+		try_join_all([
+			Box::pin(self.plain.serve(make_service.clone())),
+			Box::pin(self.tls.serve(make_service)),
+		])
+		.await
+	}
+}
+
+impl<Fallback> ServerExt for SplitProtocolServer<Fallback> {
+	type TlsStream = TlsStream<TcpStream>;
+	type WithFallback<NewFallback> = SplitProtocolServer<NewFallback>;
+
+	fn set_upgrade(mut self, upgrade: bool) -> Self {
+		let port = self.tls_addr.port();
+
+		// Only a default, a port configured through `set_upgrade_layer()` is kept.
+		for server in [&mut self.plain, &mut self.tls] {
+			let acceptor = server.get_mut();
+			acceptor.set_upgrade(upgrade);
+			acceptor.set_default_redirect_port(port);
+		}
+
+		self
+	}
+
+	fn set_upgrade_layer<NewFallback: Clone>(
+		self,
+		mut layer: UpgradeHttpLayer<NewFallback>,
+	) -> Self::WithFallback<NewFallback> {
+		layer.set_default_redirect_port(self.tls_addr.port());
+
+		SplitProtocolServer {
+			plain: self.plain.set_upgrade_layer(layer.clone()),
+			tls: self.tls.set_upgrade_layer(layer),
+			plain_addr: self.plain_addr,
+			tls_addr: self.tls_addr,
+		}
+	}
+
+	fn set_forwarded_protocol(mut self, layer: ForwardedProtocolLayer) -> Self {
+		self.plain.get_mut().set_forwarded_protocol(layer.clone());
+		self.tls.get_mut().set_forwarded_protocol(layer);
+		self
+	}
+
+	fn set_strict_transport_security(mut self, layer: StrictTransportSecurityLayer) -> Self {
+		self.plain
+			.get_mut()
+			.set_strict_transport_security(layer.clone());
+		self.tls.get_mut().set_strict_transport_security(layer);
+		self
+	}
+
+	#[cfg(feature = "acme")]
+	fn set_acme(mut self, acme: Acme) -> Self {
+		self.plain.get_mut().set_acme(acme.clone());
+		self.tls.get_mut().set_acme(acme);
+		self
+	}
+
+	fn set_alpn_handler<Id, Handler, Fut>(mut self, protocol: Id, handler: Handler) -> Self
+	where
+		Id: Into<Vec<u8>>,
+		Handler: 'static + Fn(Self::TlsStream, PeerAddr) -> Fut + Send + Sync,
+		Fut: 'static + Future<Output = ()> + Send,
+	{
+		self.tls.get_mut().set_alpn_handler(protocol, handler);
+		self
+	}
+}
+
+/// Polls all `futures` to completion, returning early on the first error.
+pub(crate) async fn try_join_all<Futures, Fut>(futures: Futures) -> io::Result<()>
+where
+	Futures: IntoIterator<Item = Pin<Box<Fut>>>,
+	Fut: Future<Output = io::Result<()>> + ?Sized,
+{
+	let mut futures: Vec<_> = futures.into_iter().map(Some).collect();
+
+	future::poll_fn(|cx| {
+		for slot in &mut futures {
+			if let Some(pending) = slot {
+				if let Poll::Ready(result) = pending.as_mut().poll(cx) {
+					result?;
+					*slot = None;
+				}
+			}
+		}
+
+		if futures.iter().all(Option::is_none) {
+			Poll::Ready(Ok(()))
+		} else {
+			Poll::Pending
+		}
+	})
+	.await
+}
+
+/// [`Layer`] inserting a fixed [`Protocol`] into every request.
+///
+/// This is useful when hosting HTTP and HTTPS on separate listeners with a
+/// custom server setup. See [`bind_split_protocol()`] for a ready-made
+/// solution.
+///
+/// # Example
+///
+/// ```
+/// # use axum::{routing, Router};
+/// use axum_server_dual_protocol::{Protocol, ProtocolLayer};
+///
+/// let app = Router::new().route("/", routing::get(|| async { "Hello, world!" }));
+/// let plain = app.clone().layer(ProtocolLayer::new(Protocol::Plain));
+/// let tls = app.layer(ProtocolLayer::new(Protocol::Tls));
+/// # // To help with type inference.
+/// # axum_server::bind(std::net::SocketAddr::from(([127, 0, 0, 1], 0)))
+/// # 	.serve(plain.into_make_service());
+/// # axum_server::bind(std::net::SocketAddr::from(([127, 0, 0, 1], 0)))
+/// # 	.serve(tls.into_make_service());
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ProtocolLayer(
+	/// The [`Protocol`] to insert.
+	Protocol,
+);
+
+impl ProtocolLayer {
+	/// Creates a new [`ProtocolLayer`].
+	#[must_use]
+	pub const fn new(protocol: Protocol) -> Self {
+		Self(protocol)
+	}
+}
+
+impl<Service> Layer<Service> for ProtocolLayer {
+	type Service = SetProtocol<Service>;
+
+	fn layer(&self, inner: Service) -> Self::Service {
+		SetProtocol::new(inner, self.0)
+	}
+}
+
+/// [`Service`](TowerService) inserting a fixed [`Protocol`] into every
+/// request, replacing any existing one.
+///
+/// See [`ProtocolLayer`] for more details.
+#[derive(Clone, Debug)]
+pub struct SetProtocol<Service> {
+	/// Wrapped user-provided [`Service`](TowerService).
+	service: Service,
+	/// The [`Protocol`] to insert.
+	protocol: Protocol,
+}
+
+impl<Service> SetProtocol<Service> {
+	/// Creates a new [`SetProtocol`].
+	pub const fn new(service: Service, protocol: Protocol) -> Self {
+		Self { service, protocol }
+	}
+
+	/// Consumes the [`SetProtocol`], returning the wrapped
+	/// [`Service`](TowerService).
+	pub fn into_inner(self) -> Service {
+		self.service
+	}
+
+	/// Return a reference to the wrapped [`Service`](TowerService).
+	pub const fn get_ref(&self) -> &Service {
+		&self.service
+	}
+
+	/// Return a mutable reference to the wrapped [`Service`](TowerService).
+	pub fn get_mut(&mut self) -> &mut Service {
+		&mut self.service
+	}
+}
+
+impl<Service, RequestBody> TowerService<Request<RequestBody>> for SetProtocol<Service>
+where
+	Service: TowerService<Request<RequestBody>>,
+{
+	type Response = Service::Response;
+	type Error = Service::Error;
+	type Future = Service::Future;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, mut req: Request<RequestBody>) -> Self::Future {
+		let _ = req.extensions_mut().insert(self.protocol);
+		self.service.call(req)
+	}
+}
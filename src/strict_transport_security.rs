@@ -0,0 +1,229 @@
+//! HTTP Strict Transport Security implementation.
+//!
+//! See [`StrictTransportSecurity`].
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use http::header::{CONTENT_SECURITY_POLICY, STRICT_TRANSPORT_SECURITY};
+use http::{HeaderMap, HeaderValue, Request, Response};
+use pin_project::pin_project;
+use tower_layer::Layer;
+use tower_service::Service as TowerService;
+
+use crate::Protocol;
+
+/// [`Layer`] adding the `Strict-Transport-Security` header to HTTPS responses.
+///
+/// See [`StrictTransportSecurity`] for more details.
+#[derive(Clone, Debug)]
+pub struct StrictTransportSecurityLayer {
+	/// The `max-age` directive in seconds.
+	max_age: u64,
+	/// If the `includeSubDomains` directive is added.
+	include_subdomains: bool,
+	/// If the `preload` directive is added.
+	preload: bool,
+	/// If the `upgrade-insecure-requests` CSP directive is added.
+	upgrade_insecure_requests: bool,
+	/// The pre-computed `Strict-Transport-Security` header.
+	header: HeaderValue,
+}
+
+impl StrictTransportSecurityLayer {
+	/// Creates a new [`StrictTransportSecurityLayer`] with the given
+	/// `max-age`. Sub-second precision is ignored.
+	#[must_use]
+	pub fn new(max_age: Duration) -> Self {
+		Self {
+			max_age: max_age.as_secs(),
+			include_subdomains: false,
+			preload: false,
+			upgrade_insecure_requests: false,
+			header: HeaderValue::from(0),
+		}
+		.update()
+	}
+
+	/// Set if the `includeSubDomains` directive should be added. Defaults to
+	/// `false`.
+	#[must_use]
+	pub fn include_subdomains(mut self, include_subdomains: bool) -> Self {
+		self.include_subdomains = include_subdomains;
+		self.update()
+	}
+
+	/// Set if the `preload` directive should be added. Defaults to `false`.
+	///
+	/// Note that preload lists require a `max-age` of at least one year and
+	/// the `includeSubDomains` directive.
+	#[must_use]
+	pub fn preload(mut self, preload: bool) -> Self {
+		self.preload = preload;
+		self.update()
+	}
+
+	/// Set if a `Content-Security-Policy` header with the
+	/// [`upgrade-insecure-requests`](https://www.w3.org/TR/upgrade-insecure-requests)
+	/// directive should be added. Defaults to `false`.
+	///
+	/// The header is added in addition to any existing
+	/// `Content-Security-Policy` header, which browsers enforce together.
+	#[must_use]
+	pub const fn upgrade_insecure_requests(mut self, upgrade_insecure_requests: bool) -> Self {
+		self.upgrade_insecure_requests = upgrade_insecure_requests;
+		self
+	}
+
+	/// Re-computes the `Strict-Transport-Security` header.
+	fn update(mut self) -> Self {
+		let mut header = format!("max-age={}", self.max_age);
+
+		if self.include_subdomains {
+			header.push_str("; includeSubDomains");
+		}
+
+		if self.preload {
+			header.push_str("; preload");
+		}
+
+		self.header = HeaderValue::try_from(header).expect("ASCII string is valid");
+		self
+	}
+
+	/// Adds the configured headers to responses over TLS and removes the
+	/// `Strict-Transport-Security` header from all other responses.
+	pub(crate) fn apply(&self, protocol: Option<Protocol>, headers: &mut HeaderMap) {
+		if protocol == Some(Protocol::Tls) {
+			let _ = headers
+				.entry(STRICT_TRANSPORT_SECURITY)
+				.or_insert_with(|| self.header.clone());
+
+			if self.upgrade_insecure_requests {
+				headers.append(
+					CONTENT_SECURITY_POLICY,
+					HeaderValue::from_static("upgrade-insecure-requests"),
+				);
+			}
+		} else {
+			// See <https://tools.ietf.org/html/rfc6797#section-7.2>.
+			let _ = headers.remove(STRICT_TRANSPORT_SECURITY);
+		}
+	}
+}
+
+impl<Service> Layer<Service> for StrictTransportSecurityLayer {
+	type Service = StrictTransportSecurity<Service>;
+
+	fn layer(&self, inner: Service) -> Self::Service {
+		StrictTransportSecurity::new(inner, self.clone())
+	}
+}
+
+/// [`Service`](TowerService) adding the
+/// [`Strict-Transport-Security`](https://tools.ietf.org/html/rfc6797) header
+/// to responses over TLS.
+///
+/// As required by the specification, the header is never sent over plain
+/// HTTP, even if set by the wrapped [`Service`](TowerService). Requests
+/// without a [`Protocol`] are treated as plain.
+///
+/// Optionally this also adds the `upgrade-insecure-requests`
+/// `Content-Security-Policy` directive, see
+/// [`StrictTransportSecurityLayer::upgrade_insecure_requests()`].
+#[derive(Clone, Debug)]
+pub struct StrictTransportSecurity<Service> {
+	/// Wrapped user-provided [`Service`](TowerService).
+	service: Service,
+	/// Configuration.
+	layer: StrictTransportSecurityLayer,
+}
+
+impl<Service> StrictTransportSecurity<Service> {
+	/// Creates a new [`StrictTransportSecurity`].
+	pub const fn new(service: Service, layer: StrictTransportSecurityLayer) -> Self {
+		Self { service, layer }
+	}
+
+	/// Consumes the [`StrictTransportSecurity`], returning the wrapped
+	/// [`Service`](TowerService).
+	pub fn into_inner(self) -> Service {
+		self.service
+	}
+
+	/// Return a reference to the wrapped [`Service`](TowerService).
+	pub const fn get_ref(&self) -> &Service {
+		&self.service
+	}
+
+	/// Return a mutable reference to the wrapped [`Service`](TowerService).
+	pub fn get_mut(&mut self) -> &mut Service {
+		&mut self.service
+	}
+}
+
+impl<Service, RequestBody, ResponseBody> TowerService<Request<RequestBody>>
+	for StrictTransportSecurity<Service>
+where
+	Service: TowerService<Request<RequestBody>, Response = Response<ResponseBody>>,
+{
+	type Response = Response<ResponseBody>;
+	type Error = Service::Error;
+	type Future = StrictTransportSecurityFuture<Service::Future>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.service.poll_ready(cx)
+	}
+
+	fn call(&mut self, req: Request<RequestBody>) -> Self::Future {
+		let protocol = req.extensions().get::<Protocol>().copied();
+
+		StrictTransportSecurityFuture::new(self.service.call(req), protocol, self.layer.clone())
+	}
+}
+
+/// [`Future`](TowerService::Future) type for [`StrictTransportSecurity`].
+#[derive(Debug)]
+#[pin_project]
+pub struct StrictTransportSecurityFuture<Future> {
+	/// The wrapped [`Future`].
+	#[pin]
+	future: Future,
+	/// The protocol of the request.
+	protocol: Option<Protocol>,
+	/// Configuration.
+	layer: StrictTransportSecurityLayer,
+}
+
+impl<Future> StrictTransportSecurityFuture<Future> {
+	/// Create a new [`StrictTransportSecurityFuture`].
+	const fn new(
+		future: Future,
+		protocol: Option<Protocol>,
+		layer: StrictTransportSecurityLayer,
+	) -> Self {
+		Self {
+			future,
+			protocol,
+			layer,
+		}
+	}
+}
+
+impl<InnerFuture, ResponseBody, Error> Future for StrictTransportSecurityFuture<InnerFuture>
+where
+	InnerFuture: Future<Output = Result<Response<ResponseBody>, Error>>,
+{
+	type Output = InnerFuture::Output;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+		let this = self.project();
+
+		this.future.poll(cx).map_ok(|mut response| {
+			this.layer.apply(*this.protocol, response.headers_mut());
+			response
+		})
+	}
+}
@@ -0,0 +1,279 @@
+//! systemd socket activation.
+//!
+//! See [`from_systemd_dual_protocol()`] and [`systemd_listeners()`].
+
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, ErrorKind};
+use std::net::TcpListener;
+use std::os::unix::io::{BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{env, process};
+
+use axum_server::tls_rustls::RustlsConfig;
+use socket2::{SockRef, Type};
+
+use crate::MultiAddressServer;
+
+/// First file descriptor passed by systemd, see
+/// [`sd_listen_fds(3)`](https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html).
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Whether the passed file descriptors are being or were already taken, they
+/// must only be owned once.
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// Create a [`MultiAddressServer`] from all sockets passed by systemd socket
+/// activation, accepting both HTTP and HTTPS on each of them.
+///
+/// See [`systemd_listeners()`] for details and to select specific sockets.
+///
+/// # Errors
+///
+/// If the process wasn't socket activated or the activation environment is
+/// invalid, see [`ActivationError`].
+///
+/// # Example
+///
+/// With a socket unit like:
+/// ```ini
+/// [Socket]
+/// ListenStream=443
+/// ```
+///
+/// ```no_run
+/// # use axum::{routing, Router};
+/// # use axum_server::tls_rustls::RustlsConfig;
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let app = Router::new();
+/// # let certificate = rcgen::generate_simple_self_signed([])?;
+/// # let private_key = certificate.key_pair.serialize_der();
+/// # let certificate = vec![certificate.cert.der().to_vec()];
+/// # let config = RustlsConfig::from_der(certificate, private_key).await?;
+/// #
+/// axum_server_dual_protocol::from_systemd_dual_protocol(config)?
+/// 	.serve(app.into_make_service())
+/// 	.await?;
+/// #
+/// # Ok(())
+/// # }
+/// ```
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+pub fn from_systemd_dual_protocol(
+	config: RustlsConfig,
+) -> Result<MultiAddressServer, ActivationError> {
+	let listeners = systemd_listeners()?
+		.into_iter()
+		.map(|(_, listener)| listener);
+
+	crate::from_tcp_dual_protocol_all(listeners, config).map_err(ActivationError::Io)
+}
+
+/// Takes all sockets passed by systemd socket activation, returning them
+/// together with their name.
+///
+/// Sockets are determined by `LISTEN_PID`, `LISTEN_FDS` and `LISTEN_FDNAMES`.
+/// Sockets without a name are called `unknown`, like
+/// [`sd_listen_fds_with_names(3)`](https://www.freedesktop.org/software/systemd/man/latest/sd_listen_fds.html)
+/// does.
+///
+/// The environment is left untouched, so this is safe to call from a
+/// multi-threaded runtime. Sockets are only taken once, later calls return
+/// [`ActivationError::NotActivated`]. Child processes don't pick them up
+/// because `LISTEN_PID` doesn't match their process ID, and the sockets are
+/// marked close-on-exec so they aren't inherited either.
+///
+/// Only TCP stream sockets, e.g. from `ListenStream=`, are supported. All
+/// sockets are validated before any of them is taken, so on error they are left
+/// open.
+///
+/// # Errors
+///
+/// If the process wasn't socket activated or the activation environment is
+/// invalid, see [`ActivationError`].
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+pub fn systemd_listeners() -> Result<Vec<(String, TcpListener)>, ActivationError> {
+	if TAKEN.swap(true, Ordering::AcqRel) {
+		return Err(ActivationError::NotActivated);
+	}
+
+	let result = take_listeners();
+
+	if result.is_err() {
+		TAKEN.store(false, Ordering::Release);
+	}
+
+	result
+}
+
+/// Implementation of [`systemd_listeners()`], only called once at a time.
+fn take_listeners() -> Result<Vec<(String, TcpListener)>, ActivationError> {
+	let (Some(pid), Some(fds)) = (env::var_os("LISTEN_PID"), env::var_os("LISTEN_FDS")) else {
+		return Err(ActivationError::NotActivated);
+	};
+
+	let pid: u32 = parse("LISTEN_PID", &pid)?;
+
+	if pid != process::id() {
+		return Err(ActivationError::WrongProcess { pid });
+	}
+
+	let count: RawFd = parse("LISTEN_FDS", &fds)?;
+
+	if count == 0 {
+		return Err(ActivationError::NoListeners);
+	} else if count < 0 || count.checked_add(LISTEN_FDS_START).is_none() {
+		return Err(invalid("LISTEN_FDS", &fds));
+	}
+
+	let names: Vec<String> = if let Some(names) = env::var_os("LISTEN_FDNAMES") {
+		let names = names
+			.into_string()
+			.map_err(|names| invalid("LISTEN_FDNAMES", &names))?;
+		let list: Vec<_> = names.split(':').map(String::from).collect();
+
+		if list.len() != usize::try_from(count).unwrap_or(usize::MAX) {
+			return Err(invalid("LISTEN_FDNAMES", names.as_ref()));
+		}
+
+		list
+	} else {
+		(0..count).map(|_| String::from("unknown")).collect()
+	};
+
+	let fds = LISTEN_FDS_START..LISTEN_FDS_START + count;
+
+	for fd in fds.clone() {
+		validate(fd).map_err(|error| ActivationError::NotTcpListener { fd, error })?;
+	}
+
+	Ok(fds
+		.zip(names)
+		.map(|(fd, name)| {
+			// SAFETY: systemd passes ownership of these file descriptors to the process
+			// named by `LISTEN_PID`, which was checked above. `TAKEN` stays set on success,
+			// so ownership is only taken once.
+			#[allow(unsafe_code)]
+			let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+			(name, TcpListener::from(fd))
+		})
+		.collect())
+}
+
+/// Makes sure `fd` is a TCP listener and marks it close-on-exec, without taking
+/// ownership of it.
+fn validate(fd: RawFd) -> io::Result<()> {
+	// SAFETY: systemd guarantees that the file descriptors it passes are open. They
+	// aren't owned by anything else yet, so they stay open while borrowed here.
+	#[allow(unsafe_code)]
+	let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+	let socket = SockRef::from(&fd);
+
+	if socket.r#type()? != Type::STREAM {
+		return Err(io::Error::new(
+			ErrorKind::InvalidInput,
+			"not a stream socket",
+		));
+	}
+
+	if socket.local_addr()?.as_socket().is_none() {
+		return Err(io::Error::new(
+			ErrorKind::InvalidInput,
+			"not an IPv4 or IPv6 socket",
+		));
+	}
+
+	socket.set_cloexec(true)
+}
+
+/// Parses an activation environment variable.
+fn parse<T: FromStr>(variable: &'static str, value: &OsStr) -> Result<T, ActivationError> {
+	value
+		.to_str()
+		.and_then(|string| string.parse().ok())
+		.ok_or_else(|| invalid(variable, value))
+}
+
+/// Creates an [`ActivationError::InvalidEnvironment`].
+fn invalid(variable: &'static str, value: &OsStr) -> ActivationError {
+	ActivationError::InvalidEnvironment {
+		variable,
+		value: value.to_string_lossy().into_owned(),
+	}
+}
+
+/// Reason why sockets from systemd socket activation couldn't be taken.
+///
+/// See [`systemd_listeners()`].
+#[cfg_attr(docsrs, doc(cfg(unix)))]
+#[derive(Debug)]
+pub enum ActivationError {
+	/// `LISTEN_PID` or `LISTEN_FDS` is not set, the process wasn't socket
+	/// activated or the sockets were already taken.
+	NotActivated,
+	/// An environment variable has an invalid value.
+	InvalidEnvironment {
+		/// Name of the environment variable.
+		variable: &'static str,
+		/// The invalid value.
+		value: String,
+	},
+	/// The sockets were passed to a different process.
+	WrongProcess {
+		/// The process ID the sockets were passed to.
+		pid: u32,
+	},
+	/// `LISTEN_FDS` is `0`.
+	NoListeners,
+	/// A passed file descriptor is not a TCP socket.
+	NotTcpListener {
+		/// The file descriptor.
+		fd: RawFd,
+		/// The error when validating it.
+		error: io::Error,
+	},
+	/// Creating the server failed.
+	Io(io::Error),
+}
+
+impl Display for ActivationError {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::NotActivated => {
+				formatter.write_str("not socket activated, `LISTEN_PID` or `LISTEN_FDS` is not set")
+			}
+			Self::InvalidEnvironment { variable, value } => {
+				write!(formatter, "invalid value for `{variable}`: \"{value}\"")
+			}
+			Self::WrongProcess { pid } => write!(
+				formatter,
+				"sockets were passed to process {pid}, not {}",
+				process::id()
+			),
+			Self::NoListeners => formatter.write_str("no sockets were passed"),
+			Self::NotTcpListener { fd, error } => {
+				write!(
+					formatter,
+					"file descriptor {fd} is not a TCP socket: {error}"
+				)
+			}
+			Self::Io(error) => write!(formatter, "failed to create server: {error}"),
+		}
+	}
+}
+
+impl Error for ActivationError {
+	fn source(&self) -> Option<&(dyn Error + 'static)> {
+		match self {
+			Self::NotTcpListener { error, .. } | Self::Io(error) => Some(error),
+			Self::NotActivated
+			| Self::InvalidEnvironment { .. }
+			| Self::WrongProcess { .. }
+			| Self::NoListeners => None,
+		}
+	}
+}
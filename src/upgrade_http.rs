@@ -2,33 +2,879 @@
 //!
 //! See [`UpgradeHttp`].
 
-use std::fmt::{self, Debug, Formatter};
-use std::future::Future;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::future::{self, Future, Ready};
+use std::net::{IpAddr, Ipv4Addr};
 use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use bytes::Bytes;
-use http::header::{HOST, LOCATION, UPGRADE};
+use http::header::{
+	HeaderName, ACCESS_CONTROL_REQUEST_METHOD, CACHE_CONTROL, CONNECTION, CONTENT_TYPE, HOST,
+	LOCATION, UPGRADE, UPGRADE_INSECURE_REQUESTS, VARY,
+};
+use http::request::Parts;
 use http::uri::{Authority, Scheme};
-use http::{HeaderValue, Request, Response, StatusCode, Uri};
-use http_body_util::{Either, Empty};
+use http::{HeaderMap, HeaderValue, Method, Request, Response, StatusCode, Uri, Version};
+use http_body_util::{Either, Full};
+use hyper::ext::Protocol as ExtendedConnectProtocol;
 use pin_project::pin_project;
 use tower_layer::Layer;
 use tower_service::Service as TowerService;
 
-use crate::Protocol;
+use crate::{forwarded_protocol, LocalAddr, PeerAddr, Protocol};
 
 /// [`Layer`] upgrading HTTP requests to HTTPS.
 ///
+/// [`UpgradeHttp`] has to know the [`Protocol`] of every request, which
+/// [`DualProtocolService`](crate::DualProtocolService) inserts. With a
+/// different server, e.g. [`axum_server::bind()`], HTTP/1.1 requests carry no
+/// URI scheme to infer it from, so they are responded to with a
+/// [500 "Internal Server Error"](https://tools.ietf.org/html/rfc7231#section-6.6.1)
+/// status code. In this case insert the [`Protocol`] with
+/// [`ProtocolLayer`](crate::ProtocolLayer) or set a
+/// [default](Self::default_protocol()).
+///
 /// See [`UpgradeHttp`] for more details.
-#[derive(Clone, Copy, Debug)]
-pub struct UpgradeHttpLayer;
+///
+/// # Example
+///
+/// ```
+/// # use axum::{routing, Router};
+/// use axum_server_dual_protocol::{Protocol, ProtocolLayer, UpgradeHttpLayer};
+///
+/// let app = Router::new()
+/// 	.route("/", routing::get(|| async { "Hello, world!" }))
+/// 	.layer(UpgradeHttpLayer::new())
+/// 	// Applied first, because it is added last.
+/// 	.layer(ProtocolLayer::new(Protocol::Plain));
+/// # // To help with type inference.
+/// # axum_server::bind(std::net::SocketAddr::from(([127, 0, 0, 1], 0)))
+/// # 	.serve(app.into_make_service());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct UpgradeHttpLayer<Fallback = BadRequest> {
+	/// Configuration, shared between all [`UpgradeHttp`]s created from this.
+	config: Arc<Config>,
+	/// [`Service`](TowerService) receiving requests that can't be redirected.
+	fallback: Fallback,
+}
+
+/// Configuration of [`UpgradeHttpLayer`].
+#[derive(Clone, Debug, Default)]
+struct Config {
+	/// Plain requests matching any of these are passed through to the wrapped
+	/// [`Service`](TowerService).
+	exemptions: Vec<Exemption>,
+	/// Body of redirect responses.
+	body: Option<BodyTemplate>,
+	/// `Cache-Control` header of redirect responses.
+	cache_control: Option<HeaderValue>,
+	/// Additional headers of redirect responses.
+	headers: Vec<(HeaderName, HeaderValue)>,
+	/// Only upgrade requests sending the `Upgrade-Insecure-Requests` header.
+	upgrade_insecure_requests: bool,
+	/// How to handle plain WebSocket handshakes.
+	websocket: WebSocketPolicy,
+	/// How to handle plain CORS preflight requests.
+	preflight: PreflightPolicy,
+	/// [`Protocol`] to assume if it is missing and can't be inferred.
+	default_protocol: Option<Protocol>,
+	/// Where to redirect requests without a host to.
+	host_fallback: Option<HostFallback>,
+	/// Rule to normalize the host of redirect targets.
+	canonical_host: Option<CanonicalHost>,
+	/// Also redirect TLS requests to the canonical host.
+	canonicalize_tls: bool,
+	/// How to handle detected redirect loops.
+	redirect_loop: RedirectLoopPolicy,
+	/// Tracks redirects to detect repeated ones.
+	redirect_tracker: Option<Arc<RedirectTracker>>,
+	/// Port to redirect to instead of the port of the request.
+	redirect_port: Option<u16>,
+}
+
+/// Where [`UpgradeHttp`] redirects HTTP requests to that don't specify a
+/// host, e.g. from HTTP/1.0 clients.
+///
+/// See [`UpgradeHttpLayer::host_fallback()`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum HostFallback {
+	/// Redirect to the given host, e.g. the canonical host of this server.
+	Authority(Authority),
+	/// Redirect to the local address the connection arrived on, see
+	/// [`LocalAddr`].
+	LocalAddr,
+}
+
+/// Rule to normalize the host [`UpgradeHttp`] redirects to, so non-canonical
+/// hosts are upgraded with a single redirect.
+///
+/// The port of the request is kept unless replaced by
+/// [`CanonicalHost::Primary`]. See [`UpgradeHttpLayer::canonical_host()`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum CanonicalHost {
+	/// Strip the `www.` prefix, e.g. `www.example.com` to `example.com`.
+	StripWww,
+	/// Add the `www.` prefix, e.g. `example.com` to `www.example.com`. IP
+	/// addresses and hosts without a dot, like `localhost`, are left as is.
+	AddWww,
+	/// Map the given aliases to the primary host. Only the hosts are compared,
+	/// case-insensitively.
+	Primary {
+		/// The primary host.
+		primary: Authority,
+		/// Hosts to map to the primary host.
+		aliases: Vec<Authority>,
+	},
+}
+
+impl CanonicalHost {
+	/// Returns the canonical host, or [`None`] if the host already is.
+	fn apply(&self, authority: &Authority) -> Option<Authority> {
+		/// The `www.` prefix.
+		const WWW: &str = "www.";
+
+		let host = authority.host();
+
+		let canonical = match self {
+			Self::StripWww => host
+				.get(..WWW.len())
+				.filter(|prefix| prefix.eq_ignore_ascii_case(WWW))
+				.and_then(|_| host.get(WWW.len()..))
+				.filter(|host| !host.is_empty())?
+				.to_owned(),
+			Self::AddWww => {
+				if host
+					.get(..WWW.len())
+					.map_or(false, |prefix| prefix.eq_ignore_ascii_case(WWW))
+					|| !host.contains('.')
+					|| host.parse::<Ipv4Addr>().is_ok()
+				{
+					return None;
+				}
+
+				format!("{WWW}{host}")
+			}
+			Self::Primary { primary, aliases } => {
+				if !aliases
+					.iter()
+					.any(|alias| alias.host().eq_ignore_ascii_case(host))
+				{
+					return None;
+				}
+
+				if primary.port().is_some() {
+					return Some(primary.clone());
+				}
+
+				primary.host().to_owned()
+			}
+		};
+
+		let canonical = if let Some(port) = authority.port() {
+			format!("{canonical}:{port}")
+		} else {
+			canonical
+		};
+
+		Authority::try_from(canonical).ok()
+	}
+}
+
+/// Reason why [`UpgradeHttp`] couldn't redirect a HTTP request. See
+/// [`Request::extensions()`](Request::extensions()).
+///
+/// See [`UpgradeHttpLayer::fallback()`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RedirectFailure {
+	/// The request has no host and no [`HostFallback`] applied.
+	MissingHost,
+	/// The host of the request is invalid.
+	InvalidHost,
+	/// The request has an unknown URI scheme.
+	UnknownScheme,
+}
+
+impl Display for RedirectFailure {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::MissingHost => formatter.write_str("request has no host"),
+			Self::InvalidHost => formatter.write_str("request has an invalid host"),
+			Self::UnknownScheme => formatter.write_str("request has an unknown URI scheme"),
+		}
+	}
+}
+
+impl Error for RedirectFailure {}
+
+/// Default fallback [`Service`](TowerService) of [`UpgradeHttp`], responding
+/// to all requests with an empty
+/// [400 "Bad Request"](https://tools.ietf.org/html/rfc7231#section-6.5.1)
+/// status code.
+///
+/// See [`UpgradeHttpLayer::fallback()`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BadRequest;
+
+impl<RequestBody> TowerService<Request<RequestBody>> for BadRequest {
+	type Response = Response<Full<Bytes>>;
+	type Error = Infallible;
+	type Future = Ready<Result<Self::Response, Self::Error>>;
+
+	fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn call(&mut self, _: Request<RequestBody>) -> Self::Future {
+		future::ready(Ok(Response::builder()
+			.status(StatusCode::BAD_REQUEST)
+			.body(Full::default())
+			.expect("invalid header or body")))
+	}
+}
+
+/// How [`UpgradeHttp`] handles plain WebSocket handshakes.
+///
+/// See [`UpgradeHttpLayer::websocket()`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum WebSocketPolicy {
+	/// Redirect to the `wss` URI scheme, like any other HTTP request. Note
+	/// that most WebSocket clients, including browsers, don't follow
+	/// redirects during the handshake.
+	#[default]
+	Redirect,
+	/// Pass plain WebSocket handshakes through to the wrapped
+	/// [`Service`](TowerService).
+	PassThrough,
+	/// Reject plain WebSocket handshakes with a
+	/// [426 "Upgrade Required"](https://tools.ietf.org/html/rfc7231#section-6.5.15)
+	/// status code and a body describing the problem.
+	Reject,
+}
+
+/// How [`UpgradeHttp`] handles plain
+/// [CORS preflight requests](https://fetch.spec.whatwg.org/#cors-preflight-request),
+/// which browsers don't redirect.
+///
+/// See [`UpgradeHttpLayer::preflight()`].
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub enum PreflightPolicy {
+	/// Redirect to HTTPS, like any other HTTP request. Browsers will report
+	/// this as a CORS error without further details.
+	#[default]
+	Redirect,
+	/// Pass plain CORS preflight requests through to the wrapped
+	/// [`Service`](TowerService).
+	PassThrough,
+	/// Respond with the given status code and plain text body, `{location}` in
+	/// the body is replaced by the redirect target. See
+	/// [`PreflightPolicy::reject()`].
+	Respond {
+		/// Status code of the response.
+		status: StatusCode,
+		/// Template of the plain text body.
+		body: String,
+	},
+}
+
+impl PreflightPolicy {
+	/// Respond with a
+	/// [403 "Forbidden"](https://tools.ietf.org/html/rfc7231#section-6.5.3)
+	/// status code and a body explaining that HTTPS is required. Browsers
+	/// show the body in their developer tools.
+	#[must_use]
+	pub fn reject() -> Self {
+		Self::Respond {
+			status: StatusCode::FORBIDDEN,
+			body: String::from("CORS requests require HTTPS, send them to \"{location}\" instead."),
+		}
+	}
+}
+
+/// How [`UpgradeHttp`] handles detected redirect loops, which typically
+/// happen if a proxy terminates TLS and forwards requests over HTTP.
+///
+/// A redirect loop is detected if the request carries a `Forwarded` or
+/// `X-Forwarded-Proto` header reporting HTTPS, or if a client is redirected
+/// too often for the same URI, see [`UpgradeHttpLayer::redirect_loop_limit()`].
+/// To properly support such proxies, apply
+/// [`ForwardedProtocolLayer`](crate::ForwardedProtocolLayer) instead.
+///
+/// See [`UpgradeHttpLayer::redirect_loop()`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum RedirectLoopPolicy {
+	/// Don't detect redirect loops and redirect to HTTPS as usual.
+	#[default]
+	Redirect,
+	/// Pass the request through to the wrapped [`Service`](TowerService).
+	Serve,
+	/// Respond with a
+	/// [508 "Loop Detected"](https://tools.ietf.org/html/rfc5842#section-7.2)
+	/// status code and a body describing the problem.
+	Reject,
+}
+
+/// Tracks redirects per client and URI to detect redirect loops.
+#[derive(Debug)]
+struct RedirectTracker {
+	/// Number of redirects allowed in the window.
+	limit: u32,
+	/// Time window in which redirects are counted.
+	window: Duration,
+	/// Start of the window and number of redirects per client and redirect
+	/// target.
+	redirects: Mutex<HashMap<(IpAddr, Uri), (Instant, u32)>>,
+}
+
+impl RedirectTracker {
+	/// Maximum number of tracked client and URI pairs, to bound memory usage.
+	const CAPACITY: usize = 4096;
+
+	/// Records a redirect and returns `true` if the limit was exceeded.
+	fn record(&self, address: IpAddr, location: &Uri) -> bool {
+		let now = Instant::now();
+		let mut redirects = self
+			.redirects
+			.lock()
+			.unwrap_or_else(PoisonError::into_inner);
+		let key = (address, location.clone());
+
+		if redirects.len() >= Self::CAPACITY && !redirects.contains_key(&key) {
+			redirects.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+
+			// Stop tracking new clients until older ones expire.
+			if redirects.len() >= Self::CAPACITY {
+				return false;
+			}
+		}
+
+		let (start, count) = redirects.entry(key).or_insert((now, 0));
+
+		if now.duration_since(*start) >= self.window {
+			*start = now;
+			*count = 0;
+		}
+
+		*count = count.saturating_add(1);
+		let exceeded = *count > self.limit;
+		drop(redirects);
+
+		exceeded
+	}
+}
+
+impl Config {
+	/// Determines how to respond to a HTTP request. Returns [`None`] if the
+	/// request should be passed through to the wrapped
+	/// [`Service`](TowerService) and [`Err`] if it should be passed to the
+	/// fallback [`Service`](TowerService).
+	fn handle(&self, parts: &Parts) -> Option<Result<Response<Full<Bytes>>, RedirectFailure>> {
+		if self
+			.exemptions
+			.iter()
+			.any(|exemption| exemption.matches(parts))
+		{
+			return None;
+		}
+
+		// Checked first, so no other response depends on the header.
+		if self.upgrade_insecure_requests
+			&& parts
+				.headers
+				.get(UPGRADE_INSECURE_REQUESTS)
+				.map_or(true, |value| value != "1")
+		{
+			return None;
+		}
+
+		if is_websocket(parts) {
+			match self.websocket {
+				WebSocketPolicy::Redirect => (),
+				WebSocketPolicy::PassThrough => return None,
+				WebSocketPolicy::Reject => {
+					return Some(Ok(reject_websocket(self.location(parts).ok())))
+				}
+			}
+		}
+
+		if is_preflight(parts) {
+			match &self.preflight {
+				PreflightPolicy::Redirect => (),
+				PreflightPolicy::PassThrough => return None,
+				PreflightPolicy::Respond { status, body } => {
+					let location = match self.location(parts) {
+						Ok(location) => location.to_string(),
+						Err(failure) => return Some(Err(failure)),
+					};
+
+					return Some(Ok(Response::builder()
+						.status(status)
+						.header(
+							CONTENT_TYPE,
+							HeaderValue::from_static("text/plain; charset=utf-8"),
+						)
+						.body(Full::new(Bytes::from(
+							body.replace(BodyTemplate::LOCATION, &location),
+						)))
+						.expect("invalid header or body")));
+				}
+			}
+		}
+
+		let location = match self.location(parts) {
+			Ok(location) => location,
+			Err(failure) => return Some(Err(failure)),
+		};
+
+		if self.redirect_loop != RedirectLoopPolicy::Redirect
+			&& self.is_redirect_loop(parts, &location)
+		{
+			match self.redirect_loop {
+				RedirectLoopPolicy::Redirect | RedirectLoopPolicy::Serve => return None,
+				RedirectLoopPolicy::Reject => return Some(Ok(redirect_loop())),
+			}
+		}
+
+		Some(Ok(self.redirect(&location)))
+	}
+
+	/// Returns `true` if redirecting the request would create a redirect loop.
+	fn is_redirect_loop(&self, parts: &Parts, location: &Uri) -> bool {
+		if forwarded_protocol::extract_protocol(&parts.headers) == Some(Protocol::Tls) {
+			return true;
+		}
+
+		match (&self.redirect_tracker, parts.extensions.get::<PeerAddr>()) {
+			(Some(tracker), Some(PeerAddr(address))) => tracker.record(address.ip(), location),
+			_ => false,
+		}
+	}
+
+	/// Builds the [`Uri`] to redirect a HTTP request to.
+	fn location(&self, parts: &Parts) -> Result<Uri, RedirectFailure> {
+		let authority = match extract_authority(parts) {
+			Ok(authority) => self
+				.canonical_host
+				.as_ref()
+				.and_then(|canonical_host| canonical_host.apply(&authority))
+				.unwrap_or(authority),
+			Err(RedirectFailure::MissingHost) => match &self.host_fallback {
+				Some(HostFallback::Authority(authority)) => authority.clone(),
+				Some(HostFallback::LocalAddr) => parts
+					.extensions
+					.get::<LocalAddr>()
+					.and_then(|LocalAddr(address)| Authority::try_from(address.to_string()).ok())
+					.ok_or(RedirectFailure::MissingHost)?,
+				None => return Err(RedirectFailure::MissingHost),
+			},
+			Err(error) => return Err(error),
+		};
+		let authority = match self.redirect_port {
+			// Omit the default HTTPS port.
+			Some(443) => Authority::try_from(authority.host()),
+			Some(port) => Authority::try_from(format!("{}:{port}", authority.host())),
+			None => Ok(authority),
+		}
+		.map_err(|_| RedirectFailure::InvalidHost)?;
+		let uri = &parts.uri;
+
+		// Depending on the scheme we need a different scheme to redirect to.
+
+		// WebSocket handshakes often don't send a scheme, so we check the headers as
+		// well.
+		let scheme = if is_websocket(parts) {
+			Scheme::try_from("wss").expect("ASCII string is valid")
+		}
+		// HTTP requests often don't send a scheme.
+		else if uri.scheme() == Some(&Scheme::HTTP) || uri.scheme_str().is_none() {
+			Scheme::HTTPS
+		}
+		// Unknown scheme, abort.
+		else {
+			return Err(RedirectFailure::UnknownScheme);
+		};
+
+		// Build URI to redirect to.
+		let mut uri = Uri::builder().scheme(scheme).authority(authority);
+
+		if let Some(path_and_query) = parts.uri.path_and_query() {
+			uri = uri.path_and_query(path_and_query.clone());
+		}
+
+		Ok(uri.build().expect("invalid path and query"))
+	}
+
+	/// Determines if a TLS request has to be redirected to the canonical host.
+	/// Returns [`None`] if the request should be passed through to the wrapped
+	/// [`Service`](TowerService).
+	fn handle_tls(&self, parts: &Parts) -> Option<Response<Full<Bytes>>> {
+		// WebSocket clients don't follow redirects.
+		if !self.canonicalize_tls || is_websocket(parts) {
+			return None;
+		}
+
+		let authority = extract_authority(parts).ok()?;
+		let authority = self.canonical_host.as_ref()?.apply(&authority)?;
+
+		let mut location = Uri::builder().scheme(Scheme::HTTPS).authority(authority);
+
+		if let Some(path_and_query) = parts.uri.path_and_query() {
+			location = location.path_and_query(path_and_query.clone());
+		}
 
-impl<Service> Layer<Service> for UpgradeHttpLayer {
-	type Service = UpgradeHttp<Service>;
+		let location = location.build().expect("invalid path and query");
+
+		Some(self.redirect(&location))
+	}
+
+	/// Builds a redirect response to the given location.
+	fn redirect(&self, location: &Uri) -> Response<Full<Bytes>> {
+		let location = location.to_string();
+		let mut response = Response::builder().status(StatusCode::MOVED_PERMANENTLY);
+
+		if let Some(cache_control) = &self.cache_control {
+			response = response.header(CACHE_CONTROL, cache_control);
+		}
+
+		for (name, value) in &self.headers {
+			response = response.header(name, value);
+		}
+
+		let body = if let Some(body) = &self.body {
+			response = response.header(CONTENT_TYPE, &body.content_type);
+			body.render(&location)
+		} else {
+			Full::default()
+		};
+
+		response
+			.header(LOCATION, location)
+			.body(body)
+			.expect("invalid header or body")
+	}
+}
+
+/// Template for the body of redirect responses.
+#[derive(Clone, Debug)]
+struct BodyTemplate {
+	/// `Content-Type` header of the body.
+	content_type: HeaderValue,
+	/// The template, where `{location}` is replaced by the redirect target.
+	template: String,
+	/// If the redirect target has to be escaped for HTML.
+	html: bool,
+}
+
+impl BodyTemplate {
+	/// Placeholder for the redirect target.
+	const LOCATION: &'static str = "{location}";
+
+	/// Replaces `{location}` in the template with the given redirect target.
+	fn render(&self, location: &str) -> Full<Bytes> {
+		let body = if self.html {
+			// `Uri` already doesn't allow `"`, `<` and `>`.
+			let location = location.replace('&', "&amp;").replace('\'', "&#39;");
+			self.template.replace(Self::LOCATION, &location)
+		} else {
+			self.template.replace(Self::LOCATION, location)
+		};
+
+		Full::new(Bytes::from(body))
+	}
+}
+
+/// Rule to exempt plain requests from being upgraded.
+#[derive(Clone)]
+enum Exemption {
+	/// Exempt requests whose path starts with this prefix.
+	PathPrefix(String),
+	/// Exempt requests with this method.
+	Method(Method),
+	/// Exempt requests the predicate returns `true` for.
+	Predicate(Arc<dyn Fn(&Parts) -> bool + Send + Sync>),
+}
+
+impl Debug for Exemption {
+	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::PathPrefix(prefix) => formatter.debug_tuple("PathPrefix").field(prefix).finish(),
+			Self::Method(method) => formatter.debug_tuple("Method").field(method).finish(),
+			Self::Predicate(_) => formatter.write_str("Predicate"),
+		}
+	}
+}
+
+impl Exemption {
+	/// Returns `true` if the request matches this [`Exemption`].
+	fn matches(&self, parts: &Parts) -> bool {
+		match self {
+			Self::PathPrefix(prefix) => parts.uri.path().starts_with(prefix.as_str()),
+			Self::Method(method) => parts.method == method,
+			Self::Predicate(predicate) => predicate(parts),
+		}
+	}
+}
+
+impl UpgradeHttpLayer {
+	/// Creates a new [`UpgradeHttpLayer`] upgrading all HTTP requests.
+	#[must_use]
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+impl<Fallback> UpgradeHttpLayer<Fallback> {
+	/// Set the [`Service`](TowerService) receiving HTTP requests that can't be
+	/// redirected, e.g. because of an unknown URI scheme or an invalid host.
+	/// The reason is attached to the request as a [`RedirectFailure`].
+	///
+	/// This can be used to render an error page, log the request or pass it
+	/// through to the wrapped [`Service`](TowerService). The fallback
+	/// [`Service`](TowerService) can't fail, e.g. an `axum::Router`.
+	/// Defaults to [`BadRequest`].
+	#[must_use]
+	pub fn fallback<NewFallback>(self, fallback: NewFallback) -> UpgradeHttpLayer<NewFallback> {
+		UpgradeHttpLayer {
+			config: self.config,
+			fallback,
+		}
+	}
+
+	/// Exempt HTTP requests whose path starts with the given prefix from being
+	/// upgraded, e.g. `/.well-known/acme-challenge/`.
+	///
+	/// See [`UpgradeHttpLayer::exempt_if()`] for more details.
+	#[must_use]
+	pub fn exempt_path_prefix<Prefix: Into<String>>(self, prefix: Prefix) -> Self {
+		self.exempt(Exemption::PathPrefix(prefix.into()))
+	}
+
+	/// Exempt HTTP requests with the given [`Method`] from being upgraded.
+	///
+	/// See [`UpgradeHttpLayer::exempt_if()`] for more details.
+	#[must_use]
+	pub fn exempt_method(self, method: Method) -> Self {
+		self.exempt(Exemption::Method(method))
+	}
+
+	/// Exempt HTTP requests the given predicate returns `true` for from being
+	/// upgraded.
+	///
+	/// Exempted requests are passed through to the wrapped
+	/// [`Service`](TowerService) and are still tagged with
+	/// [`Protocol::Plain`]. Requests matching any exemption are exempted.
+	#[must_use]
+	pub fn exempt_if<Predicate>(self, predicate: Predicate) -> Self
+	where
+		Predicate: 'static + Fn(&Parts) -> bool + Send + Sync,
+	{
+		self.exempt(Exemption::Predicate(Arc::new(predicate)))
+	}
+
+	/// Send the given HTML body with redirect responses, `{location}` in the
+	/// template is replaced by the escaped redirect target. For example:
+	///
+	/// ```
+	/// # use axum_server_dual_protocol::UpgradeHttpLayer;
+	/// let layer = UpgradeHttpLayer::new()
+	/// 	.html_body("<!DOCTYPE html><title>Moved</title><a href=\"{location}\">{location}</a>");
+	/// ```
+	///
+	/// By default redirect responses have an empty body.
+	#[must_use]
+	pub fn html_body<Template: Into<String>>(mut self, template: Template) -> Self {
+		Arc::make_mut(&mut self.config).body = Some(BodyTemplate {
+			content_type: HeaderValue::from_static("text/html; charset=utf-8"),
+			template: template.into(),
+			html: true,
+		});
+		self
+	}
+
+	/// Send the given plain text body with redirect responses, `{location}` in
+	/// the template is replaced by the redirect target.
+	///
+	/// By default redirect responses have an empty body.
+	#[must_use]
+	pub fn text_body<Template: Into<String>>(mut self, template: Template) -> Self {
+		Arc::make_mut(&mut self.config).body = Some(BodyTemplate {
+			content_type: HeaderValue::from_static("text/plain; charset=utf-8"),
+			template: template.into(),
+			html: false,
+		});
+		self
+	}
+
+	/// Set the `Cache-Control` header of redirect responses, e.g.
+	/// `max-age=3600`, to prevent intermediaries from caching them
+	/// indefinitely.
+	#[must_use]
+	pub fn cache_control(mut self, value: HeaderValue) -> Self {
+		Arc::make_mut(&mut self.config).cache_control = Some(value);
+		self
+	}
+
+	/// Add the given header to redirect responses.
+	#[must_use]
+	pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+		Arc::make_mut(&mut self.config).headers.push((name, value));
+		self
+	}
+
+	/// Only upgrade HTTP requests sending the
+	/// [`Upgrade-Insecure-Requests`](https://www.w3.org/TR/upgrade-insecure-requests/#preference)
+	/// header, which browsers send to signal their preference for HTTPS. Other
+	/// HTTP requests are passed through to the wrapped
+	/// [`Service`](TowerService), including WebSocket handshakes and CORS
+	/// preflight requests regardless of [`websocket()`](Self::websocket())
+	/// and [`preflight()`](Self::preflight()). All responses to HTTP requests
+	/// will contain `Vary: Upgrade-Insecure-Requests`.
+	///
+	/// This allows upgrading browsers while still serving non-browser clients
+	/// over HTTP. Defaults to `false`.
+	#[must_use]
+	pub fn only_upgrade_insecure_requests(mut self, only: bool) -> Self {
+		Arc::make_mut(&mut self.config).upgrade_insecure_requests = only;
+		self
+	}
+
+	/// Set how plain WebSocket handshakes are handled. Defaults to
+	/// [`WebSocketPolicy::Redirect`].
+	///
+	/// WebSocket handshakes are detected by the `ws` URI scheme, the
+	/// `Connection: upgrade` and `Upgrade: websocket` headers or the HTTP/2
+	/// extended `CONNECT` method with the `websocket` protocol
+	/// ([RFC 8441](https://tools.ietf.org/html/rfc8441)).
+	#[must_use]
+	pub fn websocket(mut self, policy: WebSocketPolicy) -> Self {
+		Arc::make_mut(&mut self.config).websocket = policy;
+		self
+	}
+
+	/// Set how plain
+	/// [CORS preflight requests](https://fetch.spec.whatwg.org/#cors-preflight-request)
+	/// are handled. Defaults to [`PreflightPolicy::Redirect`].
+	///
+	/// CORS preflight requests are detected by the `OPTIONS` method and the
+	/// `Access-Control-Request-Method` header.
+	#[must_use]
+	pub fn preflight(mut self, policy: PreflightPolicy) -> Self {
+		Arc::make_mut(&mut self.config).preflight = policy;
+		self
+	}
+
+	/// Set how detected redirect loops are handled, see
+	/// [`RedirectLoopPolicy`]. Defaults to [`RedirectLoopPolicy::Redirect`].
+	#[must_use]
+	pub fn redirect_loop(mut self, policy: RedirectLoopPolicy) -> Self {
+		Arc::make_mut(&mut self.config).redirect_loop = policy;
+		self
+	}
+
+	/// Detect a redirect loop if the same client, identified by its
+	/// [`PeerAddr`], is redirected to the same URI more than `limit` times
+	/// within `window`. Only applies if a [`RedirectLoopPolicy`] other than
+	/// [`RedirectLoopPolicy::Redirect`] is set.
+	///
+	/// Clients behind the same proxy or NAT share their address, so choose
+	/// generous limits.
+	#[must_use]
+	pub fn redirect_loop_limit(mut self, limit: u32, window: Duration) -> Self {
+		Arc::make_mut(&mut self.config).redirect_tracker = Some(Arc::new(RedirectTracker {
+			limit,
+			window,
+			redirects: Mutex::default(),
+		}));
+		self
+	}
+
+	/// Redirect to the given port instead of the port of the request, e.g. if
+	/// HTTP and HTTPS are served on separate ports. Port 443 is omitted from
+	/// the redirect target.
+	///
+	/// If no port is configured,
+	/// [`SplitProtocolServer`](crate::SplitProtocolServer) defaults it to the
+	/// port of its HTTPS listener.
+	#[must_use]
+	pub fn redirect_port(mut self, port: u16) -> Self {
+		Arc::make_mut(&mut self.config).redirect_port = Some(port);
+		self
+	}
+
+	/// Set the port to redirect to, unless already configured.
+	pub(crate) fn set_default_redirect_port(&mut self, port: u16) {
+		if self.config.redirect_port.is_none() {
+			Arc::make_mut(&mut self.config).redirect_port = Some(port);
+		}
+	}
+
+	/// Set the [`Protocol`] to assume for requests without one.
+	///
+	/// [`Protocol`] is always set by
+	/// [`DualProtocolService`](crate::DualProtocolService), but might be
+	/// missing if [`UpgradeHttp`] is used with a different server.
+	/// In this case the [`Protocol`] is inferred from the URI scheme of the
+	/// request, if present, otherwise the default is used. If there is no
+	/// default, requests without a [`Protocol`] are responded to with a
+	/// [500 "Internal Server Error"](https://tools.ietf.org/html/rfc7231#section-6.6.1)
+	/// status code.
+	#[must_use]
+	pub fn default_protocol(mut self, protocol: Protocol) -> Self {
+		Arc::make_mut(&mut self.config).default_protocol = Some(protocol);
+		self
+	}
+
+	/// Set where to redirect HTTP requests to that don't specify a host, e.g.
+	/// from HTTP/1.0 clients. By default they are responded to with a
+	/// [400 "Bad Request"](https://tools.ietf.org/html/rfc7231#section-6.5.1)
+	/// status code.
+	#[must_use]
+	pub fn host_fallback(mut self, fallback: HostFallback) -> Self {
+		Arc::make_mut(&mut self.config).host_fallback = Some(fallback);
+		self
+	}
+
+	/// Set a rule to normalize the host HTTP requests are redirected to, e.g.
+	/// redirecting `http://www.example.com` directly to `https://example.com`.
+	/// By default the host of the request is kept.
+	#[must_use]
+	pub fn canonical_host(mut self, rule: CanonicalHost) -> Self {
+		Arc::make_mut(&mut self.config).canonical_host = Some(rule);
+		self
+	}
+
+	/// Set if TLS requests to a non-canonical host should be redirected to the
+	/// canonical host as well, see [`UpgradeHttpLayer::canonical_host()`].
+	/// Exemptions are not applied to TLS requests and WebSocket handshakes are
+	/// never redirected. Defaults to `false`.
+	#[must_use]
+	pub fn canonicalize_tls(mut self, canonicalize: bool) -> Self {
+		Arc::make_mut(&mut self.config).canonicalize_tls = canonicalize;
+		self
+	}
+
+	/// Adds an [`Exemption`].
+	fn exempt(mut self, exemption: Exemption) -> Self {
+		Arc::make_mut(&mut self.config).exemptions.push(exemption);
+		self
+	}
+}
+
+impl<Service, Fallback: Clone> Layer<Service> for UpgradeHttpLayer<Fallback> {
+	type Service = UpgradeHttp<Service, Fallback>;
 
 	fn layer(&self, inner: Service) -> Self::Service {
-		UpgradeHttp::new(inner)
+		UpgradeHttp {
+			service: inner,
+			fallback: self.fallback.clone(),
+			config: Arc::clone(&self.config),
+		}
 	}
 }
 
@@ -40,18 +886,30 @@ impl<Service> Layer<Service> for UpgradeHttpLayer {
 /// path and query. Depending on how you apply this [`Service`](TowerService) it
 /// will redirect even in the case of a resulting 404 "Not Found" status code at
 /// the destination.
+///
+/// Requests can be exempted from being upgraded, see
+/// [`UpgradeHttpLayer::exempt_if()`]. Requests that can't be redirected are
+/// passed to a fallback [`Service`](TowerService), see
+/// [`UpgradeHttpLayer::fallback()`].
 #[derive(Clone, Debug)]
-pub struct UpgradeHttp<Service> {
+pub struct UpgradeHttp<Service, Fallback = BadRequest> {
 	/// Wrapped user-provided [`Service`](TowerService).
 	service: Service,
+	/// [`Service`](TowerService) receiving requests that can't be redirected.
+	fallback: Fallback,
+	/// Configuration.
+	config: Arc<Config>,
 }
 
 impl<Service> UpgradeHttp<Service> {
-	/// Creates a new [`UpgradeHttp`].
-	pub const fn new(service: Service) -> Self {
-		Self { service }
+	/// Creates a new [`UpgradeHttp`] upgrading all HTTP requests. Use
+	/// [`UpgradeHttpLayer`] for more configuration options.
+	pub fn new(service: Service) -> Self {
+		UpgradeHttpLayer::new().layer(service)
 	}
+}
 
+impl<Service, Fallback> UpgradeHttp<Service, Fallback> {
 	/// Consumes the [`UpgradeHttp`], returning the wrapped
 	/// [`Service`](TowerService).
 	pub fn into_inner(self) -> Service {
@@ -69,76 +927,80 @@ impl<Service> UpgradeHttp<Service> {
 	}
 }
 
-impl<Service, RequestBody, ResponseBody> TowerService<Request<RequestBody>> for UpgradeHttp<Service>
+impl<Service, Fallback, RequestBody, ResponseBody, FallbackBody> TowerService<Request<RequestBody>>
+	for UpgradeHttp<Service, Fallback>
 where
 	Service: TowerService<Request<RequestBody>, Response = Response<ResponseBody>>,
+	Fallback:
+		TowerService<Request<RequestBody>, Response = Response<FallbackBody>, Error = Infallible>,
 {
-	type Response = Response<Either<ResponseBody, Empty<Bytes>>>;
+	type Response = Response<Either<ResponseBody, Either<Full<Bytes>, FallbackBody>>>;
 	type Error = Service::Error;
-	type Future = UpgradeHttpFuture<Service, Request<RequestBody>>;
+	type Future = UpgradeHttpFuture<Service, Request<RequestBody>, Fallback>;
 
 	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-		self.service.poll_ready(cx)
+		// We don't know if the fallback will be called, so both have to be ready.
+		match self.fallback.poll_ready(cx) {
+			Poll::Ready(Ok(())) => self.service.poll_ready(cx),
+			Poll::Ready(Err(error)) => match error {},
+			Poll::Pending => Poll::Pending,
+		}
 	}
 
-	fn call(&mut self, req: Request<RequestBody>) -> Self::Future {
-		match req
-			.extensions()
-			.get::<Protocol>()
-			.expect("`Protocol` should always be set by `DualProtocolService`")
-		{
-			Protocol::Tls => UpgradeHttpFuture::new_service(self.service.call(req)),
-			Protocol::Plain => {
-				let response = Response::builder();
-
-				let response = if let Some((authority, scheme)) =
-					extract_authority(&req).and_then(|authority| {
-						let uri = req.uri();
-
-						// Depending on the scheme we need a different scheme to redirect to.
-
-						// WebSocket handshakes often don't send a scheme, so we check the "Upgrade"
-						// header as well.
-						if uri.scheme_str() == Some("ws")
-							|| req.headers().get(UPGRADE)
-								== Some(&HeaderValue::from_static("websocket"))
-						{
-							Some((
-								authority,
-								Scheme::try_from("wss").expect("ASCII string is valid"),
-							))
-						}
-						// HTTP requests often don't send a scheme.
-						else if uri.scheme() == Some(&Scheme::HTTP) || uri.scheme_str().is_none()
-						{
-							Some((authority, Scheme::HTTPS))
-						}
-						// Unknown scheme, abort.
-						else {
-							None
-						}
-					}) {
-					// Build URI to redirect to.
-					let mut uri = Uri::builder().scheme(scheme).authority(authority);
+	fn call(&mut self, mut req: Request<RequestBody>) -> Self::Future {
+		let protocol = if let Some(protocol) = req.extensions().get::<Protocol>() {
+			*protocol
+		} else {
+			let protocol = match req.uri().scheme_str() {
+				Some("https" | "wss") => Some(Protocol::Tls),
+				Some("http" | "ws") => Some(Protocol::Plain),
+				_ => self.config.default_protocol,
+			};
 
-					if let Some(path_and_query) = req.uri().path_and_query() {
-						uri = uri.path_and_query(path_and_query.clone());
-					}
+			let Some(protocol) = protocol else {
+				return UpgradeHttpFuture::new_upgrade(missing_protocol());
+			};
+
+			let _ = req.extensions_mut().insert(protocol);
+			protocol
+		};
 
-					let uri = uri.build().expect("invalid path and query");
+		match protocol {
+			Protocol::Tls if self.config.canonicalize_tls => {
+				let (parts, body) = req.into_parts();
 
-					response
-						.status(StatusCode::MOVED_PERMANENTLY)
-						.header(LOCATION, uri.to_string())
+				if let Some(response) = self.config.handle_tls(&parts) {
+					UpgradeHttpFuture::new_upgrade(response)
 				} else {
-					// If we can't extract the host or have an unknown scheme, tell the client there
-					// is something wrong with their request.
-					response.status(StatusCode::BAD_REQUEST)
+					UpgradeHttpFuture::new_service(
+						self.service.call(Request::from_parts(parts, body)),
+						false,
+					)
 				}
-				.body(Empty::new())
-				.expect("invalid header or body");
+			}
+			Protocol::Tls => UpgradeHttpFuture::new_service(self.service.call(req), false),
+			Protocol::Plain => {
+				let (parts, body) = req.into_parts();
+				let vary = self.config.upgrade_insecure_requests;
 
-				UpgradeHttpFuture::new_upgrade(response)
+				match self.config.handle(&parts) {
+					None => UpgradeHttpFuture::new_service(
+						self.service.call(Request::from_parts(parts, body)),
+						vary,
+					),
+					Some(Ok(mut response)) => {
+						if vary {
+							append_vary(response.headers_mut());
+						}
+
+						UpgradeHttpFuture::new_upgrade(response)
+					}
+					Some(Err(failure)) => {
+						let mut req = Request::from_parts(parts, body);
+						let _ = req.extensions_mut().insert(failure);
+						UpgradeHttpFuture::new_fallback(self.fallback.call(req), vary)
+					}
+				}
 			}
 		}
 	}
@@ -146,30 +1008,52 @@ where
 
 /// [`Future`](TowerService::Future) type for [`UpgradeHttp`].
 #[pin_project]
-pub struct UpgradeHttpFuture<Service, Request>(#[pin] FutureServe<Service, Request>)
+pub struct UpgradeHttpFuture<Service, Request, Fallback = BadRequest>(
+	#[pin] FutureServe<Service, Request, Fallback>,
+)
 where
-	Service: TowerService<Request>;
+	Service: TowerService<Request>,
+	Fallback: TowerService<Request>;
 
 /// Holds [`Future`] to serve for [`UpgradeHttpFuture`].
 #[derive(Debug)]
 #[pin_project(project = UpgradeHttpFutureProj)]
-enum FutureServe<Service, Request>
+enum FutureServe<Service, Request, Fallback>
 where
 	Service: TowerService<Request>,
+	Fallback: TowerService<Request>,
 {
-	/// The request was using the HTTPS protocol, so we
+	/// The request was using the HTTPS protocol or was exempted, so we
 	/// will pass-through the wrapped [`Service`](TowerService).
-	Service(#[pin] Service::Future),
-	/// The request was using the HTTP protocol, so we
-	/// will upgrade the connection.
-	Upgrade(Option<Response<Empty<Bytes>>>),
+	Service {
+		/// The wrapped [`Future`](TowerService::Future).
+		#[pin]
+		future: Service::Future,
+		/// If `Vary: Upgrade-Insecure-Requests` should be added to the
+		/// response.
+		vary: bool,
+	},
+	/// The request was using the HTTP protocol, so we will upgrade the
+	/// connection or otherwise respond directly.
+	Upgrade(Option<Response<Full<Bytes>>>),
+	/// The request couldn't be redirected, so we will pass-through the
+	/// fallback [`Service`](TowerService).
+	Fallback {
+		/// The fallback [`Future`](TowerService::Future).
+		#[pin]
+		future: Fallback::Future,
+		/// If `Vary: Upgrade-Insecure-Requests` should be added to the
+		/// response.
+		vary: bool,
+	},
 }
 
 // Rust can't figure out the correct bounds.
-impl<Service, Request> Debug for UpgradeHttpFuture<Service, Request>
+impl<Service, Request, Fallback> Debug for UpgradeHttpFuture<Service, Request, Fallback>
 where
 	Service: TowerService<Request>,
-	FutureServe<Service, Request>: Debug,
+	Fallback: TowerService<Request>,
+	FutureServe<Service, Request, Fallback>: Debug,
 {
 	fn fmt(&self, formatter: &mut Formatter<'_>) -> fmt::Result {
 		formatter
@@ -179,53 +1063,181 @@ where
 	}
 }
 
-impl<Service, Request> UpgradeHttpFuture<Service, Request>
+impl<Service, Request, Fallback> UpgradeHttpFuture<Service, Request, Fallback>
 where
 	Service: TowerService<Request>,
+	Fallback: TowerService<Request>,
 {
 	/// Create a [`UpgradeHttpFuture`] in the [`Service`](FutureServe::Service)
 	/// state.
-	const fn new_service(future: Service::Future) -> Self {
-		Self(FutureServe::Service(future))
+	const fn new_service(future: Service::Future, vary: bool) -> Self {
+		Self(FutureServe::Service { future, vary })
 	}
 
 	/// Create a [`UpgradeHttpFuture`] in the [`Upgrade`](FutureServe::Upgrade)
 	/// state.
-	const fn new_upgrade(response: Response<Empty<Bytes>>) -> Self {
+	const fn new_upgrade(response: Response<Full<Bytes>>) -> Self {
 		Self(FutureServe::Upgrade(Some(response)))
 	}
+
+	/// Create a [`UpgradeHttpFuture`] in the
+	/// [`Fallback`](FutureServe::Fallback) state.
+	const fn new_fallback(future: Fallback::Future, vary: bool) -> Self {
+		Self(FutureServe::Fallback { future, vary })
+	}
 }
 
-impl<Service, Request, ResponseBody> Future for UpgradeHttpFuture<Service, Request>
+impl<Service, Request, Fallback, ResponseBody, FallbackBody> Future
+	for UpgradeHttpFuture<Service, Request, Fallback>
 where
 	Service: TowerService<Request, Response = Response<ResponseBody>>,
+	Fallback: TowerService<Request, Response = Response<FallbackBody>, Error = Infallible>,
 {
-	type Output = Result<Response<Either<ResponseBody, Empty<Bytes>>>, Service::Error>;
+	type Output =
+		Result<Response<Either<ResponseBody, Either<Full<Bytes>, FallbackBody>>>, Service::Error>;
 
 	fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
 		match self.project().0.project() {
-			UpgradeHttpFutureProj::Service(future) => {
-				future.poll(cx).map_ok(|result| result.map(Either::Left))
+			UpgradeHttpFutureProj::Service { future, vary } => {
+				future.poll(cx).map_ok(|mut result| {
+					if *vary {
+						append_vary(result.headers_mut());
+					}
+
+					result.map(Either::Left)
+				})
 			}
 			UpgradeHttpFutureProj::Upgrade(response) => Poll::Ready(Ok(response
 				.take()
 				.expect("polled again after `Poll::Ready`")
-				.map(Either::Right))),
+				.map(|body| Either::Right(Either::Left(body))))),
+			UpgradeHttpFutureProj::Fallback { future, vary } => {
+				future.poll(cx).map(|result| match result {
+					Ok(mut response) => {
+						if *vary {
+							append_vary(response.headers_mut());
+						}
+
+						Ok(response.map(|body| Either::Right(Either::Right(body))))
+					}
+					Err(error) => match error {},
+				})
+			}
 		}
 	}
 }
 
+/// Adds `Vary: Upgrade-Insecure-Requests` to the headers of a response to a
+/// HTTP request.
+fn append_vary(headers: &mut HeaderMap) {
+	let _ = headers.append(VARY, HeaderValue::from_static("upgrade-insecure-requests"));
+}
+
+/// Returns `true` if the request is a WebSocket handshake.
+fn is_websocket(parts: &Parts) -> bool {
+	/// Returns `true` if any of the comma-separated values in the given header
+	/// matches the given token case-insensitively.
+	fn contains_token(headers: &HeaderMap, name: HeaderName, token: &str) -> bool {
+		headers
+			.get_all(name)
+			.iter()
+			.filter_map(|header| header.to_str().ok())
+			.flat_map(|header| header.split(','))
+			.any(|value| value.trim().eq_ignore_ascii_case(token))
+	}
+
+	if parts.uri.scheme_str() == Some("ws") {
+		return true;
+	}
+
+	// HTTP/2 extended `CONNECT`, see <https://tools.ietf.org/html/rfc8441#section-4>.
+	if parts.version >= Version::HTTP_2 && parts.method == Method::CONNECT {
+		return parts
+			.extensions
+			.get::<ExtendedConnectProtocol>()
+			.map_or(false, |protocol| {
+				protocol.as_str().eq_ignore_ascii_case("websocket")
+			});
+	}
+
+	contains_token(&parts.headers, CONNECTION, "upgrade")
+		&& contains_token(&parts.headers, UPGRADE, "websocket")
+}
+
+/// Returns `true` if the request is a CORS preflight request.
+fn is_preflight(parts: &Parts) -> bool {
+	parts.method == Method::OPTIONS && parts.headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// Builds the response to a request causing a redirect loop.
+fn redirect_loop() -> Response<Full<Bytes>> {
+	Response::builder()
+		.status(StatusCode::LOOP_DETECTED)
+		.header(
+			CONTENT_TYPE,
+			HeaderValue::from_static("text/plain; charset=utf-8"),
+		)
+		.body(Full::new(Bytes::from_static(
+			b"Redirect loop detected: the request was sent over HTTPS, but arrived over HTTP. If a \
+			  proxy terminates TLS, `ForwardedProtocolLayer` has to be configured to trust it.",
+		)))
+		.expect("invalid header or body")
+}
+
+/// Builds the response to a request without a [`Protocol`].
+fn missing_protocol() -> Response<Full<Bytes>> {
+	Response::builder()
+		.status(StatusCode::INTERNAL_SERVER_ERROR)
+		.header(
+			CONTENT_TYPE,
+			HeaderValue::from_static("text/plain; charset=utf-8"),
+		)
+		.body(Full::new(Bytes::from_static(
+			b"`UpgradeHttp` couldn't determine the protocol of the request. Insert it with \
+			  `ProtocolLayer` or set `UpgradeHttpLayer::default_protocol()`.",
+		)))
+		.expect("invalid header or body")
+}
+
+/// Builds the response rejecting a plain WebSocket handshake.
+fn reject_websocket(location: Option<Uri>) -> Response<Full<Bytes>> {
+	let body = if let Some(location) = location {
+		format!("WebSocket connections require TLS, connect to \"{location}\" instead.")
+	} else {
+		String::from("WebSocket connections require TLS.")
+	};
+
+	Response::builder()
+		.status(StatusCode::UPGRADE_REQUIRED)
+		.header(
+			CONTENT_TYPE,
+			HeaderValue::from_static("text/plain; charset=utf-8"),
+		)
+		.body(Full::new(Bytes::from(body)))
+		.expect("invalid header or body")
+}
+
 /// Extracts the host from a request, converting it to an [`Authority`].
-fn extract_authority<Body>(request: &Request<Body>) -> Option<Authority> {
+fn extract_authority(parts: &Parts) -> Result<Authority, RedirectFailure> {
 	/// `X-Forwarded-Host` header string.
 	const X_FORWARDED_HOST: &str = "x-forwarded-host";
 
-	let headers = request.headers();
+	let headers = &parts.headers;
+
+	let host = if let Some(header) = headers.get(X_FORWARDED_HOST).or_else(|| headers.get(HOST)) {
+		header.to_str().map_err(|_| RedirectFailure::InvalidHost)?
+	} else {
+		// HTTP/2 requests only send `:authority`, which may include a port.
+		let authority = parts
+			.uri
+			.authority()
+			.ok_or(RedirectFailure::MissingHost)?
+			.as_str();
+		// Drop the user info, if any.
+		authority
+			.rsplit_once('@')
+			.map_or(authority, |(_, host)| host)
+	};
 
-	headers
-		.get(X_FORWARDED_HOST)
-		.or_else(|| headers.get(HOST))
-		.and_then(|header| header.to_str().ok())
-		.or_else(|| request.uri().host())
-		.and_then(|host| Authority::try_from(host).ok())
+	Authority::try_from(host).map_err(|_| RedirectFailure::InvalidHost)
 }
@@ -0,0 +1,67 @@
+//! Watching files for changes.
+//!
+//! See [`watch()`].
+
+use std::convert::Infallible;
+use std::future::{self, Future};
+use std::io;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::time::{self, MissedTickBehavior};
+
+/// Callback receiving the outcome of a reload.
+pub(crate) type Callback<Error> = Arc<dyn Fn(Result<(), Error>) + Send + Sync>;
+
+/// Reads files every `interval` and loads them if their contents changed since
+/// the last read. Whenever `force` is ready they are loaded regardless. The
+/// outcome of every load, including failing to read, is reported to
+/// `callback`.
+#[allow(clippy::infinite_loop)]
+pub(crate) async fn watch<Files, Error, Read, ReadFuture, Load, Force>(
+	interval: Duration,
+	mut read: Read,
+	load: Load,
+	mut force: Force,
+	callback: Option<&Callback<Error>>,
+) -> Infallible
+where
+	Files: Clone + PartialEq,
+	Error: From<io::Error>,
+	Read: FnMut() -> ReadFuture,
+	ReadFuture: Future<Output = io::Result<Files>>,
+	Load: Fn(&Files) -> Result<(), Error>,
+	Force: FnMut(&mut Context<'_>) -> Poll<()>,
+{
+	// The first tick completes immediately, so the files are loaded right away.
+	let mut interval = time::interval(interval);
+	interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+	// Nothing was read yet.
+	let mut current = None;
+
+	loop {
+		let forced = future::poll_fn(|cx| {
+			if force(cx).is_ready() {
+				Poll::Ready(true)
+			} else {
+				interval.poll_tick(cx).map(|_| false)
+			}
+		})
+		.await;
+
+		let files = read().await;
+		let new = files.as_ref().ok();
+
+		if !forced && current.as_ref().map(Option::as_ref) == Some(new) {
+			continue;
+		}
+
+		current = Some(new.cloned());
+		let result = files.map_err(Error::from).and_then(|files| load(&files));
+
+		if let Some(callback) = callback {
+			callback(result);
+		}
+	}
+}
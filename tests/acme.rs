@@ -0,0 +1,572 @@
+#![cfg(test)]
+#![cfg(feature = "acme")]
+#![allow(clippy::missing_assert_message, clippy::too_many_lines)]
+
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{env, fs, process};
+
+use anyhow::{Error, Result};
+use axum::body::Bytes;
+use axum::response::IntoResponse;
+use axum::{routing, Router};
+use axum_server::Handle;
+use axum_server_dual_protocol::{Acme, AcmeChallenge, AcmeConfig, ServerExt};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use futures_util::{future, TryFutureExt};
+use http::header::LOCATION;
+use rcgen::{BasicConstraints, CertificateParams, CertificateSigningRequestParams, IsCa, KeyPair};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, StatusCode};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::aws_lc_rs;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+
+/// Returns an empty cache directory unique to this test.
+fn cache(test: &str) -> PathBuf {
+	let path = env::temp_dir().join(format!(
+		"axum-server-dual-protocol-{test}-{}",
+		process::id()
+	));
+	let _ = fs::remove_dir_all(&path);
+	path
+}
+
+/// Returns the files in `cache` ending with `suffix`.
+fn cached(cache: &Path, suffix: &str) -> Vec<PathBuf> {
+	fs::read_dir(cache)
+		.unwrap()
+		.map(|entry| entry.unwrap().path())
+		.filter(|path| path.to_string_lossy().ends_with(suffix))
+		.collect()
+}
+
+#[tokio::test]
+async fn challenge() -> Result<()> {
+	// `reqwest` requires a default `CryptoProvider`.
+	let _ = aws_lc_rs::default_provider().install_default();
+
+	let handle = Handle::new();
+
+	let acme = Acme::new(AcmeConfig::new(["localhost"]).cache(cache("challenge")))?;
+	// Nothing is cached, so this is a self-signed placeholder.
+	let config = acme.rustls_config().await?;
+
+	let server = axum_server_dual_protocol::bind_dual_protocol(
+		SocketAddr::from(([127, 0, 0, 1], 0)),
+		config,
+	)
+	.set_upgrade(true)
+	.set_acme(acme)
+	.handle(handle.clone());
+
+	let app = Router::new().fallback(routing::get(|| async { "test" }));
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		let address = handle.listening().await.unwrap();
+
+		let client = Client::builder()
+			.danger_accept_invalid_certs(true)
+			.redirect(Policy::none())
+			.build()?;
+
+		// Challenges are answered before upgrading to HTTPS.
+		let response = client
+			.get(format!(
+				"http://{address}/.well-known/acme-challenge/unknown"
+			))
+			.send()
+			.await?;
+		assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+		let response = client.get(format!("http://{address}/path")).send().await?;
+		assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+		assert_eq!(
+			*response.headers().get(LOCATION).unwrap(),
+			format!("https://{address}/path")
+		);
+
+		// Only the plain path answers challenges.
+		let response = client
+			.get(format!(
+				"https://{address}/.well-known/acme-challenge/unknown"
+			))
+			.send()
+			.await?;
+		assert_eq!(response.text().await?, "test");
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
+
+/// Progress of the order in [`mock()`].
+#[derive(Default)]
+struct Order {
+	/// If the HTTP-01 challenge was validated.
+	authorized: bool,
+	/// The issued PEM encoded certificate chain.
+	certificate: Option<String>,
+}
+
+/// Accepts any server certificate, like ACME servers validating TLS-ALPN-01
+/// challenges do.
+#[derive(Debug)]
+struct AcceptAll;
+
+impl ServerCertVerifier for AcceptAll {
+	fn verify_server_cert(
+		&self,
+		_: &CertificateDer<'_>,
+		_: &[CertificateDer<'_>],
+		_: &ServerName<'_>,
+		_: &[u8],
+		_: UnixTime,
+	) -> Result<ServerCertVerified, rustls::Error> {
+		Ok(ServerCertVerified::assertion())
+	}
+
+	fn verify_tls12_signature(
+		&self,
+		_: &[u8],
+		_: &CertificateDer<'_>,
+		_: &DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		Ok(HandshakeSignatureValid::assertion())
+	}
+
+	fn verify_tls13_signature(
+		&self,
+		_: &[u8],
+		_: &CertificateDer<'_>,
+		_: &DigitallySignedStruct,
+	) -> Result<HandshakeSignatureValid, rustls::Error> {
+		Ok(HandshakeSignatureValid::assertion())
+	}
+
+	fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+		aws_lc_rs::default_provider()
+			.signature_verification_algorithms
+			.supported_schemes()
+	}
+}
+
+/// Validates a HTTP-01 challenge like an ACME server.
+async fn validate_http(server: SocketAddr) {
+	let key_authorization =
+		reqwest::get(format!("http://{server}/.well-known/acme-challenge/token"))
+			.await
+			.unwrap()
+			.text()
+			.await
+			.unwrap();
+	assert!(key_authorization.starts_with("token."));
+}
+
+/// Validates a TLS-ALPN-01 challenge like an ACME server.
+async fn validate_tls_alpn(server: SocketAddr) {
+	let mut config = ClientConfig::builder()
+		.dangerous()
+		.with_custom_certificate_verifier(Arc::new(AcceptAll))
+		.with_no_client_auth();
+	config.alpn_protocols = vec![b"acme-tls/1".to_vec()];
+
+	let stream = TcpStream::connect(server).await.unwrap();
+	let mut stream = TlsConnector::from(Arc::new(config))
+		.connect(ServerName::try_from("localhost").unwrap(), stream)
+		.await
+		.unwrap();
+
+	let (_, connection) = stream.get_ref();
+	assert_eq!(connection.alpn_protocol(), Some(b"acme-tls/1".as_slice()));
+	let Some([certificate]) = connection.peer_certificates() else {
+		panic!("expected a single certificate")
+	};
+	// DER encoded `id-pe-acmeIdentifier` OID.
+	let oid = [6, 8, 43, 6, 1, 5, 5, 7, 1, 31];
+	assert!(certificate.windows(oid.len()).any(|window| window == oid));
+
+	// The connection is closed without reaching the app.
+	let _ = stream
+		.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+		.await;
+	let mut response = Vec::new();
+	let _ = stream.read_to_end(&mut response).await;
+	assert!(response.is_empty());
+}
+
+/// Starts a minimal ACME server, validating challenges against `server` and
+/// signing certificates with the returned CA.
+fn mock(server: SocketAddr, challenge: AcmeChallenge) -> Result<(String, Vec<u8>)> {
+	let ca_key = KeyPair::generate()?;
+	let mut params = CertificateParams::new([])?;
+	params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+	let ca = Arc::new((params.self_signed(&ca_key)?, ca_key));
+	let ca_der = ca.0.der().to_vec();
+
+	let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+	let base = format!("http://{}", listener.local_addr()?);
+	let state = Arc::new(Mutex::new(Order::default()));
+
+	let respond = |status: StatusCode, location: Option<String>, body: String| {
+		let mut response = (status, body).into_response();
+		let headers = response.headers_mut();
+		let _ = headers.insert("replay-nonce", "nonce".parse().unwrap());
+
+		if let Some(location) = location {
+			let _ = headers.insert(LOCATION, location.parse().unwrap());
+		}
+
+		response
+	};
+	let order = {
+		let base = base.clone();
+		let state = Arc::clone(&state);
+
+		move || {
+			let status = {
+				let state = state.lock().unwrap();
+
+				match (state.authorized, &state.certificate) {
+					(_, Some(_)) => "valid",
+					(true, None) => "ready",
+					(false, None) => "pending",
+				}
+			};
+
+			json!({
+				"status": status,
+				"authorizations": [format!("{base}/authz")],
+				"finalize": format!("{base}/finalize"),
+				"certificate": format!("{base}/certificate"),
+			})
+			.to_string()
+		}
+	};
+
+	let app = Router::new()
+		.route(
+			"/dir",
+			routing::get({
+				let base = base.clone();
+				move || async move {
+					json!({
+						"newNonce": format!("{base}/nonce"),
+						"newAccount": format!("{base}/account"),
+						"newOrder": format!("{base}/order"),
+					})
+					.to_string()
+				}
+			}),
+		)
+		.route(
+			"/nonce",
+			routing::get(move || async move { respond(StatusCode::OK, None, String::new()) }),
+		)
+		.route(
+			"/account",
+			routing::post({
+				let base = base.clone();
+				move || async move {
+					respond(
+						StatusCode::CREATED,
+						Some(format!("{base}/account/1")),
+						String::from("{}"),
+					)
+				}
+			}),
+		)
+		.route(
+			"/order",
+			routing::post({
+				let base = base.clone();
+				let order = order.clone();
+				move || async move {
+					respond(
+						StatusCode::CREATED,
+						Some(format!("{base}/order/1")),
+						order(),
+					)
+				}
+			}),
+		)
+		.route(
+			"/order/1",
+			routing::post({
+				let order = order.clone();
+				move || async move { respond(StatusCode::OK, None, order()) }
+			}),
+		)
+		.route(
+			"/authz",
+			routing::post({
+				let base = base.clone();
+				let state = Arc::clone(&state);
+				move || async move {
+					let status = if state.lock().unwrap().authorized {
+						"valid"
+					} else {
+						"pending"
+					};
+					let body = json!({
+						"status": status,
+						"identifier": { "type": "dns", "value": "localhost" },
+						"challenges": [{
+							"type": match challenge {
+								AcmeChallenge::Http01 => "http-01",
+								AcmeChallenge::TlsAlpn01 => "tls-alpn-01",
+							},
+							"url": format!("{base}/challenge"),
+							"token": "token",
+						}],
+					});
+
+					respond(StatusCode::OK, None, body.to_string())
+				}
+			}),
+		)
+		.route(
+			"/challenge",
+			routing::post({
+				let state = Arc::clone(&state);
+				move || async move {
+					match challenge {
+						AcmeChallenge::Http01 => validate_http(server).await,
+						AcmeChallenge::TlsAlpn01 => validate_tls_alpn(server).await,
+					}
+
+					state.lock().unwrap().authorized = true;
+
+					respond(StatusCode::OK, None, String::from("{}"))
+				}
+			}),
+		)
+		.route(
+			"/finalize",
+			routing::post({
+				let state = Arc::clone(&state);
+				move |body: Bytes| async move {
+					let jws: Value = serde_json::from_slice(&body).unwrap();
+					let payload = URL_SAFE_NO_PAD
+						.decode(jws.get("payload").unwrap().as_str().unwrap())
+						.unwrap();
+					let payload: Value = serde_json::from_slice(&payload).unwrap();
+					let csr = URL_SAFE_NO_PAD
+						.decode(payload.get("csr").unwrap().as_str().unwrap())
+						.unwrap();
+
+					let certificate = CertificateSigningRequestParams::from_der(&csr.into())
+						.unwrap()
+						.signed_by(&ca.0, &ca.1)
+						.unwrap();
+					state.lock().unwrap().certificate =
+						Some(format!("{}{}", certificate.pem(), ca.0.pem()));
+
+					respond(StatusCode::OK, None, order())
+				}
+			}),
+		)
+		.route(
+			"/certificate",
+			routing::post(move || async move {
+				let certificate = state.lock().unwrap().certificate.clone().unwrap();
+				respond(StatusCode::OK, None, certificate)
+			}),
+		);
+
+	drop(tokio::spawn(
+		axum_server::from_tcp(listener).serve(app.into_make_service()),
+	));
+
+	Ok((format!("{base}/dir"), ca_der))
+}
+
+#[tokio::test]
+async fn issue_http() -> Result<()> {
+	issue(AcmeChallenge::Http01).await
+}
+
+#[tokio::test]
+async fn issue_tls_alpn() -> Result<()> {
+	issue(AcmeChallenge::TlsAlpn01).await
+}
+
+/// Obtains a certificate from [`mock()`] with the given challenge.
+async fn issue(challenge: AcmeChallenge) -> Result<()> {
+	// `reqwest` requires a default `CryptoProvider`.
+	let _ = aws_lc_rs::default_provider().install_default();
+
+	let handle = Handle::new();
+
+	let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+	let address = listener.local_addr()?;
+	let (directory, ca) = mock(address, challenge)?;
+	let cache = cache(match challenge {
+		AcmeChallenge::Http01 => "issue-http",
+		AcmeChallenge::TlsAlpn01 => "issue-tls-alpn",
+	});
+
+	let acme = Acme::new(
+		AcmeConfig::new(["localhost"])
+			.directory(directory)
+			.terms_of_service_agreed(true)
+			.cache(&cache)
+			.challenge(challenge),
+	)?;
+	let config = acme.rustls_config().await?;
+
+	let mut server = axum_server_dual_protocol::from_tcp_dual_protocol(listener, config.clone())
+		.set_upgrade(true)
+		.handle(handle.clone());
+
+	// TLS-ALPN-01 is answered by the `RustlsConfig` alone.
+	if challenge == AcmeChallenge::Http01 {
+		server = server.set_acme(acme.clone());
+	}
+
+	let app = Router::new().fallback(routing::get(|| async { "test" }));
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		let _ = handle.listening().await.unwrap();
+
+		assert!(acme.renew(&config).await?);
+		assert_eq!(cached(&cache, ".crt.pem").len(), 1);
+		let [private_key] = cached(&cache, ".key.pem").try_into().unwrap();
+		assert!(cache.join("account.der").exists());
+		assert!(cached(&cache, ".tmp").is_empty());
+
+		// Private keys are only readable by the current user.
+		#[cfg(unix)]
+		for path in [private_key, cache.join("account.der")] {
+			use std::os::unix::fs::PermissionsExt;
+
+			assert_eq!(fs::metadata(path)?.permissions().mode() & 0o777, 0o600);
+		}
+
+		// The certificate is cached and not due yet.
+		assert!(!acme.renew(&config).await?);
+
+		// The new certificate was swapped in place.
+		let client = Client::builder()
+			.add_root_certificate(Certificate::from_der(&ca)?)
+			.resolve("localhost", address)
+			.build()?;
+		let response = client
+			.get(format!("https://localhost:{}", address.port()))
+			.send()
+			.await?;
+		assert_eq!(response.text().await?, "test");
+
+		// The rest of the port keeps working.
+		let response = Client::builder()
+			.redirect(Policy::none())
+			.build()?
+			.get(format!("http://{address}/path"))
+			.send()
+			.await?;
+		assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
+
+/// Requires a running [Pebble](https://github.com/letsencrypt/pebble) server:
+///
+/// ```sh
+/// pebble -config test/config/pebble-config.json
+/// PEBBLE_ROOT=test/certs/pebble.minica.pem cargo test --all-features --test acme -- --ignored
+/// ```
+///
+/// `PEBBLE_DIRECTORY` defaults to `https://localhost:14000/dir` and
+/// `PEBBLE_HTTP_PORT` to `5002`, matching Pebble's default configuration.
+#[tokio::test]
+#[ignore = "requires a running Pebble server"]
+async fn pebble() -> Result<()> {
+	// `reqwest` requires a default `CryptoProvider`.
+	let _ = aws_lc_rs::default_provider().install_default();
+
+	let directory =
+		env::var("PEBBLE_DIRECTORY").unwrap_or_else(|_| "https://localhost:14000/dir".to_owned());
+	let root = CertificateDer::from_pem_file(env::var("PEBBLE_ROOT")?)?;
+	let port = env::var("PEBBLE_HTTP_PORT").map_or(Ok(5002), |port| port.parse())?;
+	let cache = cache("pebble");
+
+	let acme = Acme::new(
+		AcmeConfig::new(["localhost"])
+			.directory(directory)
+			.root_certificate(root)
+			.terms_of_service_agreed(true)
+			.cache(&cache),
+	)?;
+	let config = acme.rustls_config().await?;
+
+	let handle = Handle::new();
+	let server = axum_server_dual_protocol::bind_dual_protocol(
+		SocketAddr::from(([0, 0, 0, 0], port)),
+		config.clone(),
+	)
+	.set_upgrade(true)
+	.set_acme(acme.clone())
+	.handle(handle.clone());
+
+	let app = Router::new().fallback(routing::get(|| async { "test" }));
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		let _ = handle.listening().await.unwrap();
+
+		assert!(acme.renew(&config).await?);
+		assert_eq!(cached(&cache, ".crt.pem").len(), 1);
+		assert_eq!(cached(&cache, ".key.pem").len(), 1);
+		// The certificate was just obtained and is cached.
+		assert!(!acme.renew(&config).await?);
+		let _ = acme.rustls_config().await?;
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
@@ -0,0 +1,152 @@
+#![cfg(test)]
+#![allow(clippy::missing_assert_message, clippy::too_many_lines)]
+
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+
+use anyhow::{Error, Result};
+use axum::{routing, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use axum_server_dual_protocol::{PeerAddr, ServerExt};
+use futures_util::{future, TryFutureExt};
+use reqwest::{Certificate, Client};
+use rustls::crypto::aws_lc_rs;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn alpn() -> Result<()> {
+	// `RustlsConfig::from_config()` doesn't install the default
+	// `CryptoProvider`, which the client requires.
+	let _ = aws_lc_rs::default_provider().install_default();
+
+	let handle = Handle::new();
+	let shutdown = CancellationToken::new();
+
+	let key_pair = rcgen::generate_simple_self_signed([String::from("localhost")])?;
+	let certificate = CertificateDer::from(key_pair.cert.der().to_vec());
+	let mut config = ServerConfig::builder()
+		.with_no_client_auth()
+		.with_single_cert(
+			vec![certificate.clone()],
+			PrivateKeyDer::from(PrivatePkcs8KeyDer::from(key_pair.key_pair.serialize_der())),
+		)?;
+	config.alpn_protocols = vec![
+		b"h2".to_vec(),
+		b"http/1.1".to_vec(),
+		b"echo/1".to_vec(),
+		b"hold/1".to_vec(),
+	];
+	let config = RustlsConfig::from_config(Arc::new(config));
+
+	let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+	let address = listener.local_addr()?;
+	let server = axum_server_dual_protocol::from_tcp_dual_protocol(listener, config)
+		.handle(handle.clone())
+		.set_alpn_handler("echo/1", |mut stream, PeerAddr(peer)| async move {
+			let mut buffer = [0; 4];
+			stream.read_exact(&mut buffer).await.unwrap();
+			stream.write_all(&buffer).await.unwrap();
+			stream.write_all(peer.to_string().as_bytes()).await.unwrap();
+			stream.shutdown().await.unwrap();
+		})
+		// Keeps the connection open until `shutdown` is cancelled.
+		.set_alpn_handler("hold/1", {
+			let shutdown = shutdown.clone();
+
+			move |mut stream, _| {
+				let shutdown = shutdown.clone();
+
+				async move {
+					stream.write_all(b"held").await.unwrap();
+					shutdown.cancelled().await;
+					stream.shutdown().await.unwrap();
+				}
+			}
+		});
+
+	let app = Router::new().route("/", routing::get(|| async { "test" }));
+
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		let connect = |protocol: &'static [u8]| {
+			let mut roots = RootCertStore::empty();
+			let certificate = certificate.clone();
+
+			async move {
+				roots.add(certificate)?;
+				let mut config = ClientConfig::builder()
+					.with_root_certificates(roots)
+					.with_no_client_auth();
+				config.alpn_protocols = vec![protocol.to_vec()];
+
+				let stream = TcpStream::connect(address).await?;
+				let local = stream.local_addr()?;
+				let stream = TlsConnector::from(Arc::new(config))
+					.connect(ServerName::try_from("localhost")?, stream)
+					.await?;
+
+				Result::<_, Error>::Ok((stream, local))
+			}
+		};
+
+		// Registered protocol.
+		let (mut stream, local) = connect(b"echo/1").await?;
+		assert_eq!(
+			stream.get_ref().1.alpn_protocol(),
+			Some(b"echo/1".as_slice())
+		);
+		stream.write_all(b"ping").await?;
+		let mut response = String::new();
+		let _ = stream.read_to_string(&mut response).await?;
+		assert_eq!(response, format!("ping{local}"));
+
+		// HTTP.
+		let client = Client::builder()
+			.add_root_certificate(Certificate::from_der(&certificate)?)
+			.build()?;
+		let response = client
+			.get(format!("https://localhost:{}", address.port()))
+			.send()
+			.await?;
+		assert_eq!(response.text().await?, "test");
+
+		let response = client
+			.get(format!("http://localhost:{}", address.port()))
+			.send()
+			.await?;
+		assert_eq!(response.text().await?, "test");
+
+		// Handlers don't hold up graceful shutdown.
+		let (mut held, _) = connect(b"hold/1").await?;
+		let mut buffer = [0; 4];
+		held.read_exact(&mut buffer).await?;
+		assert_eq!(&buffer, b"held");
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(held)
+	});
+
+	let ((), mut held) = future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	// The handler is still running after the server shut down.
+	shutdown.cancel();
+	let mut response = Vec::new();
+	let _ = held.read_to_end(&mut response).await?;
+	assert!(response.is_empty());
+
+	Ok(())
+}
@@ -0,0 +1,251 @@
+#![cfg(test)]
+#![cfg(feature = "certificate-store")]
+#![allow(clippy::missing_assert_message)]
+
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fs, process};
+
+use anyhow::{Error, Result};
+use axum::{routing, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use axum_server_dual_protocol::{CertificateStore, CertificateStoreError};
+use futures_util::{future, TryFutureExt};
+use rcgen::CertifiedKey;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, RootCertStore};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsConnector;
+
+/// Returns an empty temporary directory for the given test.
+fn directory(test: &str) -> Result<PathBuf> {
+	let path = env::temp_dir().join(format!(
+		"axum-server-dual-protocol-certificate-store-{test}-{}",
+		process::id()
+	));
+	let _ = fs::remove_dir_all(&path);
+	fs::create_dir_all(&path)?;
+	Ok(path)
+}
+
+/// Generates a self-signed certificate for `names`.
+fn generate(names: &[&str]) -> Result<CertifiedKey> {
+	Ok(rcgen::generate_simple_self_signed(
+		names.iter().copied().map(String::from).collect::<Vec<_>>(),
+	)?)
+}
+
+/// Atomically replaces the file at `path` with `contents`, so a reload can't
+/// observe a partially written file.
+fn replace<Contents: AsRef<[u8]>>(path: &Path, contents: Contents) -> Result<()> {
+	let temporary = path.with_extension("tmp");
+	fs::write(&temporary, contents)?;
+	fs::rename(temporary, path)?;
+	Ok(())
+}
+
+/// Writes the private key of `certified_key` as `file` to `directory`.
+fn write_private_key(directory: &Path, file: &str, certified_key: &CertifiedKey) -> Result<()> {
+	replace(
+		&directory.join(format!("{file}.key.pem")),
+		certified_key.key_pair.serialize_pem(),
+	)
+}
+
+/// Writes the certificate of `certified_key` as `file` to `directory`.
+fn write_certificate(directory: &Path, file: &str, certified_key: &CertifiedKey) -> Result<()> {
+	replace(
+		&directory.join(format!("{file}.crt.pem")),
+		certified_key.cert.pem(),
+	)
+}
+
+/// Generates a self-signed certificate for `names` and writes it with its
+/// private key as `file` to `directory`.
+fn store(directory: &Path, file: &str, names: &[&str]) -> Result<CertifiedKey> {
+	let certified_key = generate(names)?;
+	write_private_key(directory, file, &certified_key)?;
+	write_certificate(directory, file, &certified_key)?;
+
+	Ok(certified_key)
+}
+
+/// Returns the certificate served at `address` for `name`.
+async fn served(
+	address: SocketAddr,
+	roots: &[&CertifiedKey],
+	name: &'static str,
+) -> Result<CertificateDer<'static>> {
+	let mut store = RootCertStore::empty();
+
+	for root in roots {
+		store.add(root.cert.der().clone())?;
+	}
+
+	let config = ClientConfig::builder()
+		.with_root_certificates(store)
+		.with_no_client_auth();
+	let stream = TcpStream::connect(address).await?;
+	let stream = TlsConnector::from(Arc::new(config))
+		.connect(ServerName::try_from(name)?, stream)
+		.await?;
+
+	let Some([certificate]) = stream.get_ref().1.peer_certificates() else {
+		panic!("expected a single certificate")
+	};
+
+	Ok(certificate.clone())
+}
+
+/// Serves `config` and runs `client` against it.
+async fn serve<Client, ClientFuture>(config: RustlsConfig, client: Client) -> Result<()>
+where
+	Client: 'static + FnOnce(SocketAddr) -> ClientFuture + Send,
+	ClientFuture: Future<Output = Result<()>> + Send,
+{
+	let handle = Handle::new();
+
+	let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+	let address = listener.local_addr()?;
+	let server =
+		axum_server_dual_protocol::from_tcp_dual_protocol(listener, config).handle(handle.clone());
+
+	let app = Router::new().route("/", routing::get(|| async { "test" }));
+
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		client(address).await?;
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn sni() -> Result<()> {
+	let directory = directory("sni")?;
+	let exact = store(&directory, "exact", &["example.com", "www.example.com"])?;
+	let wildcard = store(&directory, "wildcard", &["*.example.com"])?;
+	let default = store(&directory, "default", &["localhost", "127.0.0.1"])?;
+
+	let config = CertificateStore::new(&directory)
+		.default("localhost")
+		.rustls_config()
+		.await?;
+
+	serve(config, move |address| async move {
+		let roots = [&exact, &wildcard, &default];
+
+		assert_eq!(
+			served(address, &roots, "example.com").await?,
+			*exact.cert.der()
+		);
+		// Exact names take precedence over wildcards.
+		assert_eq!(
+			served(address, &roots, "www.example.com").await?,
+			*exact.cert.der()
+		);
+		assert_eq!(
+			served(address, &roots, "test.example.com").await?,
+			*wildcard.cert.der()
+		);
+		// Wildcards only match a single label.
+		served(address, &roots, "a.b.example.com")
+			.await
+			.unwrap_err();
+		// No SNI is sent for IP addresses.
+		assert_eq!(
+			served(address, &roots, "127.0.0.1").await?,
+			*default.cert.der()
+		);
+
+		Ok(())
+	})
+	.await
+}
+
+#[tokio::test]
+async fn reload() -> Result<()> {
+	let directory = directory("reload")?;
+	let old = store(&directory, "old", &["old.localhost"])?;
+
+	let (sender, mut outcomes) = mpsc::unbounded_channel();
+	let store = CertificateStore::new(&directory)
+		.interval(Duration::from_millis(10))
+		.on_reload(move |result| {
+			let _ = sender.send(result);
+		});
+	let config = store.rustls_config().await?;
+
+	drop(tokio::spawn(async move { store.run().await }));
+	// Initial load.
+	outcomes.recv().await.unwrap()?;
+
+	serve(config, move |address| async move {
+		// No default certificate.
+		served(address, &[&old], "new.localhost").await.unwrap_err();
+
+		let new = generate(&["new.localhost"])?;
+		// A private key without a certificate is ignored.
+		write_private_key(&directory, "new", &new)?;
+		outcomes.recv().await.unwrap()?;
+		served(address, &[&old, &new], "new.localhost").await.unwrap_err();
+
+		write_certificate(&directory, "new", &new)?;
+		outcomes.recv().await.unwrap()?;
+		assert_eq!(
+			served(address, &[&old, &new], "new.localhost").await?,
+			*new.cert.der()
+		);
+
+		// Missing private key.
+		write_certificate(&directory, "invalid", &new)?;
+		let outcome = outcomes.recv().await.unwrap();
+		assert!(
+			matches!(outcome, Err(CertificateStoreError::Invalid { path, .. }) if path == directory.join("invalid.crt.pem"))
+		);
+		assert_eq!(
+			served(address, &[&old, &new], "old.localhost").await?,
+			*old.cert.der()
+		);
+		assert_eq!(
+			served(address, &[&old, &new], "new.localhost").await?,
+			*new.cert.der()
+		);
+
+		Ok(())
+	})
+	.await
+}
+
+#[tokio::test]
+async fn missing_default() -> Result<()> {
+	let directory = directory("missing-default")?;
+	let _ = store(&directory, "localhost", &["localhost"])?;
+
+	let error = CertificateStore::new(&directory)
+		.default("example.com")
+		.rustls_config()
+		.await
+		.unwrap_err();
+	assert!(matches!(error, CertificateStoreError::MissingDefault(name) if name == "example.com"));
+
+	Ok(())
+}
@@ -0,0 +1,115 @@
+#![cfg(test)]
+#![cfg(feature = "dev-certs")]
+#![allow(clippy::missing_assert_message)]
+
+use std::net::{SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+use anyhow::{Error, Result};
+use axum::{routing, Router};
+use axum_server::Handle;
+use axum_server_dual_protocol::{DevCerts, DevCertsError};
+use futures_util::{future, TryFutureExt};
+use reqwest::{Certificate, Client};
+
+/// Returns an empty temporary directory for the given test.
+fn directory(test: &str) -> PathBuf {
+	let path = env::temp_dir().join(format!(
+		"axum-server-dual-protocol-dev-certs-{test}-{}",
+		process::id()
+	));
+	let _ = fs::remove_dir_all(&path);
+	path
+}
+
+#[tokio::test]
+async fn dev_certs() -> Result<()> {
+	let handle = Handle::new();
+	let directory = directory("serve");
+	let dev_certs = DevCerts::new(["localhost", "127.0.0.1"]).directory(&directory);
+
+	let config = dev_certs.rustls_config().await?;
+	let ca = fs::read(dev_certs.ca_path())?;
+	let certificate = fs::read(directory.join("localhost.crt.pem"))?;
+
+	#[cfg(unix)]
+	{
+		use std::os::unix::fs::PermissionsExt;
+
+		for key in ["ca.key.pem", "localhost.key.pem"] {
+			let mode = fs::metadata(directory.join(key))?.permissions().mode();
+			assert_eq!(mode & 0o777, 0o600);
+		}
+	}
+
+	// Both are re-used.
+	let _ = dev_certs.rustls_config().await?;
+	assert_eq!(fs::read(dev_certs.ca_path())?, ca);
+	assert_eq!(fs::read(directory.join("localhost.crt.pem"))?, certificate);
+
+	let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+	let address = listener.local_addr()?;
+	let server =
+		axum_server_dual_protocol::from_tcp_dual_protocol(listener, config).handle(handle.clone());
+
+	let app = Router::new().route("/", routing::get(|| async { "test" }));
+
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		// Only trusts the certificate authority.
+		let client = Client::builder()
+			.add_root_certificate(Certificate::from_pem(&ca)?)
+			.build()?;
+
+		for host in ["localhost", "127.0.0.1"] {
+			let response = client
+				.get(format!("https://{host}:{}", address.port()))
+				.send()
+				.await?;
+			assert_eq!(response.text().await?, "test");
+		}
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn hostnames() -> Result<()> {
+	let directory = directory("hostnames");
+
+	let dev_certs = DevCerts::new(["localhost"]).directory(&directory);
+	let _ = dev_certs.rustls_config().await?;
+	let ca = fs::read(dev_certs.ca_path())?;
+	let certificate = fs::read(directory.join("localhost.crt.pem"))?;
+
+	// Changing the hostnames only replaces the certificate.
+	let dev_certs = DevCerts::new(["localhost", "example.localhost"]).directory(&directory);
+	let _ = dev_certs.rustls_config().await?;
+	assert_eq!(fs::read(dev_certs.ca_path())?, ca);
+	assert_ne!(fs::read(directory.join("localhost.crt.pem"))?, certificate);
+
+	let error = DevCerts::new(["not a hostname"])
+		.directory(&directory)
+		.rustls_config()
+		.await
+		.unwrap_err();
+	assert!(
+		matches!(error, DevCertsError::InvalidHostname(hostname) if hostname == "not a hostname")
+	);
+
+	Ok(())
+}
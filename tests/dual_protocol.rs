@@ -5,9 +5,10 @@ use std::net::TcpListener;
 
 use anyhow::Result;
 use axum::{routing, Router};
-use axum_server_dual_protocol::Protocol;
+use axum_server_dual_protocol::{Protocol, ServerExt, SplitMakeService, UpgradeHttpLayer};
 use http::Extensions;
-use reqwest::Client;
+use reqwest::redirect::Policy;
+use reqwest::{Client, StatusCode};
 
 #[tokio::test]
 async fn bind() -> Result<()> {
@@ -98,3 +99,84 @@ async fn protocol() -> Result<()> {
 	)
 	.await
 }
+
+#[tokio::test]
+async fn split() -> Result<()> {
+	util::test_serve(
+		util::server,
+		convert::identity,
+		|server| {
+			let plain = Router::new().route("/", routing::get(|| async { "plain" }));
+			let tls = Router::new().route("/", routing::get(|| async { "tls" }));
+
+			server.serve(SplitMakeService::new(
+				plain.into_make_service(),
+				tls.into_make_service(),
+			))
+		},
+		|certificate, address| async move {
+			let client = Client::builder()
+				.add_root_certificate(certificate)
+				.danger_accept_invalid_certs(true)
+				.build()?;
+
+			// HTTP.
+			let response = client.get(format!("http://{address}")).send().await?;
+			assert_eq!(response.text().await?, "plain");
+
+			// HTTPS.
+			let response = client.get(format!("https://{address}")).send().await?;
+			assert_eq!(response.text().await?, "tls");
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[tokio::test]
+async fn split_upgrade() -> Result<()> {
+	util::test_serve(
+		util::server,
+		|server| server.set_upgrade_layer(UpgradeHttpLayer::new().exempt_path_prefix("/health")),
+		|server| {
+			let plain = Router::new().route("/health", routing::get(|| async { "healthy" }));
+			let tls = Router::new().route("/", routing::get(|| async { "tls" }));
+
+			server.serve(SplitMakeService::new(
+				plain.into_make_service(),
+				tls.into_make_service(),
+			))
+		},
+		|certificate, address| async move {
+			let client = Client::builder()
+				.add_root_certificate(certificate)
+				.danger_accept_invalid_certs(true)
+				.redirect(Policy::none())
+				.build()?;
+
+			// HTTP.
+			let response = client.get(format!("http://{address}")).send().await?;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+			let response = client
+				.get(format!("http://{address}/health"))
+				.send()
+				.await?;
+			assert_eq!(response.text().await?, "healthy");
+
+			// HTTPS.
+			let response = client.get(format!("https://{address}")).send().await?;
+			assert_eq!(response.text().await?, "tls");
+
+			let response = client
+				.get(format!("https://{address}/health"))
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+			Ok(())
+		},
+	)
+	.await
+}
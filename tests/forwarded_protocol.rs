@@ -0,0 +1,176 @@
+#![cfg(test)]
+#![allow(clippy::missing_assert_message)]
+
+mod util;
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use anyhow::Result;
+use axum::{routing, Router};
+use axum_server_dual_protocol::{ForwardedProtocolLayer, Protocol, ServerExt, TransportProtocol};
+use http::header::FORWARDED;
+use http::Extensions;
+use reqwest::redirect::Policy;
+use reqwest::{Client, StatusCode};
+
+fn app() -> Router {
+	Router::new().route(
+		"/",
+		routing::get(|extensions: Extensions| async move {
+			let protocol = match extensions.get::<Protocol>().unwrap() {
+				Protocol::Tls => "secure",
+				Protocol::Plain => "insecure",
+			};
+			let transport = match extensions.get::<TransportProtocol>().unwrap() {
+				TransportProtocol(Protocol::Tls) => "secure",
+				TransportProtocol(Protocol::Plain) => "insecure",
+			};
+
+			format!("{protocol} {transport}")
+		}),
+	)
+}
+
+#[tokio::test]
+async fn trusted() -> Result<()> {
+	util::test(
+		util::server,
+		|server| {
+			server
+				.set_upgrade(true)
+				.set_forwarded_protocol(ForwardedProtocolLayer::new([IpAddr::V4(
+					Ipv4Addr::LOCALHOST,
+				)]))
+		},
+		app(),
+		|certificate, address| async move {
+			let client = Client::builder()
+				.add_root_certificate(certificate)
+				.danger_accept_invalid_certs(true)
+				.redirect(Policy::none())
+				.build()?;
+
+			// `X-Forwarded-Proto`.
+			let response = client
+				.get(format!("http://{address}"))
+				.header("x-forwarded-proto", "https")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(response.text().await?, "secure insecure");
+
+			// `Forwarded`, preferring the closest proxy.
+			let response = client
+				.get(format!("http://{address}"))
+				.header(FORWARDED, "proto=http, for=192.0.2.1;proto=\"HTTPS\"")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(response.text().await?, "secure insecure");
+
+			// Delimiters in quoted strings.
+			let response = client
+				.get(format!("http://{address}"))
+				.header(FORWARDED, "proto=https;for=\"192.0.2.1, proto=http\"")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(response.text().await?, "secure insecure");
+
+			// Empty elements are ignored.
+			let response = client
+				.get(format!("http://{address}"))
+				.header(FORWARDED, "proto=https, ")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(response.text().await?, "secure insecure");
+
+			// `Forwarded` is authoritative, even without `proto`.
+			let response = client
+				.get(format!("http://{address}"))
+				.header(FORWARDED, "proto=https, for=192.0.2.1")
+				.header("x-forwarded-proto", "https")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+			// Downgrade.
+			let response = client
+				.get(format!("https://{address}"))
+				.header("x-forwarded-proto", "http")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+			// No header.
+			let response = client.get(format!("http://{address}")).send().await?;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+			let response = client.get(format!("https://{address}")).send().await?;
+			assert_eq!(response.text().await?, "secure secure");
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[tokio::test]
+async fn untrusted() -> Result<()> {
+	util::test(
+		util::server,
+		|server| server.set_forwarded_protocol(ForwardedProtocolLayer::with_predicate(|_| false)),
+		app(),
+		|certificate, address| async move {
+			let client = Client::builder()
+				.add_root_certificate(certificate)
+				.danger_accept_invalid_certs(true)
+				.build()?;
+
+			let response = client
+				.get(format!("http://{address}"))
+				.header("x-forwarded-proto", "https")
+				.send()
+				.await?;
+			assert_eq!(response.text().await?, "insecure insecure");
+
+			let response = client
+				.get(format!("https://{address}"))
+				.header(FORWARDED, "proto=http")
+				.send()
+				.await?;
+			assert_eq!(response.text().await?, "secure secure");
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[tokio::test]
+async fn layer() -> Result<()> {
+	util::test(
+		util::server,
+		|server| server,
+		app().layer(ForwardedProtocolLayer::new([IpAddr::V4(
+			Ipv4Addr::LOCALHOST,
+		)])),
+		|certificate, address| async move {
+			let client = Client::builder()
+				.add_root_certificate(certificate)
+				.danger_accept_invalid_certs(true)
+				.build()?;
+
+			let response = client
+				.get(format!("http://{address}"))
+				.header("x-forwarded-proto", "https")
+				.send()
+				.await?;
+			assert_eq!(response.text().await?, "secure insecure");
+
+			Ok(())
+		},
+	)
+	.await
+}
@@ -0,0 +1,343 @@
+#![cfg(test)]
+#![allow(clippy::missing_assert_message)]
+
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use anyhow::{Error, Result};
+use axum::{routing, Router};
+use axum_server::{Handle, Server};
+use axum_server_dual_protocol::{ClientHelloInfo, DualProtocolAcceptor, LazyRustlsAcceptor};
+use futures_util::{future, TryFutureExt};
+use rcgen::CertifiedKey;
+use reqwest::Client;
+use rustls::crypto::aws_lc_rs;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_rustls::TlsConnector;
+
+/// Creates a [`ServerConfig`] serving `certified_key`.
+fn server_config(certified_key: &CertifiedKey) -> io::Result<Arc<ServerConfig>> {
+	let mut config = ServerConfig::builder()
+		.with_no_client_auth()
+		.with_single_cert(
+			vec![certified_key.cert.der().clone()],
+			PrivateKeyDer::from(PrivatePkcs8KeyDer::from(
+				certified_key.key_pair.serialize_der(),
+			)),
+		)
+		.map_err(|error| io::Error::new(ErrorKind::Other, error))?;
+	config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+	Ok(Arc::new(config))
+}
+
+/// Returns the certificate served at `address` for `name`.
+async fn served(
+	address: SocketAddr,
+	roots: &[&CertifiedKey],
+	name: &'static str,
+) -> Result<CertificateDer<'static>> {
+	let mut store = RootCertStore::empty();
+
+	for root in roots {
+		store.add(root.cert.der().clone())?;
+	}
+
+	let mut config = ClientConfig::builder()
+		.with_root_certificates(store)
+		.with_no_client_auth();
+	config.alpn_protocols = vec![b"h2".to_vec()];
+	let stream = TcpStream::connect(address).await?;
+	let stream = TlsConnector::from(Arc::new(config))
+		.connect(ServerName::try_from(name)?, stream)
+		.await?;
+
+	let Some([certificate]) = stream.get_ref().1.peer_certificates() else {
+		panic!("expected a single certificate")
+	};
+
+	Ok(certificate.clone())
+}
+
+#[tokio::test]
+async fn lazy_rustls() -> Result<()> {
+	// `ServerConfig::builder()` requires a default `CryptoProvider`.
+	let _ = aws_lc_rs::default_provider().install_default();
+
+	let handle = Handle::new();
+
+	let first = rcgen::generate_simple_self_signed([String::from("a.localhost")])?;
+	let second = rcgen::generate_simple_self_signed([String::from("b.localhost")])?;
+	let configs = [
+		("a.localhost", server_config(&first)?),
+		("b.localhost", server_config(&second)?),
+	];
+	let resolved = Arc::new(Mutex::new(Vec::new()));
+
+	let acceptor = LazyRustlsAcceptor::new({
+		let resolved = Arc::clone(&resolved);
+
+		move |client_hello: ClientHelloInfo| {
+			let config = configs
+				.iter()
+				.find(|(name, _)| client_hello.server_name() == Some(name))
+				.map(|(_, config)| Arc::clone(config))
+				.ok_or_else(|| io::Error::new(ErrorKind::NotFound, "unknown server name"));
+			resolved
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner)
+				.push(client_hello);
+
+			async move { config }
+		}
+	});
+
+	let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+	let address = listener.local_addr()?;
+	let server = Server::from_tcp(listener)
+		.acceptor(DualProtocolAcceptor::with_tls(acceptor.clone()))
+		.handle(handle.clone());
+
+	let app = Router::new().route("/", routing::get(|| async { "test" }));
+
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		let resolved = move || {
+			resolved
+				.lock()
+				.unwrap_or_else(PoisonError::into_inner)
+				.clone()
+		};
+
+		assert_eq!(
+			served(address, &[&first, &second], "a.localhost").await?,
+			*first.cert.der()
+		);
+		let [client_hello] = resolved().try_into().unwrap();
+		assert_eq!(client_hello.server_name(), Some("a.localhost"));
+		assert_eq!(client_hello.alpn(), [b"h2".to_vec()]);
+		assert!(!client_hello.signature_schemes().is_empty());
+
+		// Cached.
+		assert_eq!(
+			served(address, &[&first, &second], "a.localhost").await?,
+			*first.cert.der()
+		);
+		assert_eq!(resolved().len(), 1);
+
+		assert_eq!(
+			served(address, &[&first, &second], "b.localhost").await?,
+			*second.cert.der()
+		);
+		assert_eq!(resolved().len(), 2);
+
+		// Errors abort the handshake and aren't cached.
+		let _ = served(address, &[&first, &second], "c.localhost")
+			.await
+			.unwrap_err();
+		let _ = served(address, &[&first, &second], "c.localhost")
+			.await
+			.unwrap_err();
+		assert_eq!(resolved().len(), 4);
+
+		acceptor.clear_cache();
+		assert_eq!(
+			served(address, &[&first, &second], "a.localhost").await?,
+			*first.cert.der()
+		);
+		assert_eq!(resolved().len(), 5);
+
+		// HTTP.
+		let response = Client::new()
+			.get(format!("http://localhost:{}", address.port()))
+			.send()
+			.await?;
+		assert_eq!(response.text().await?, "test");
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn cache() -> Result<()> {
+	// `ServerConfig::builder()` requires a default `CryptoProvider`.
+	let _ = aws_lc_rs::default_provider().install_default();
+
+	let handle = Handle::new();
+
+	let first = rcgen::generate_simple_self_signed([String::from("a.localhost")])?;
+	let second = rcgen::generate_simple_self_signed([String::from("b.localhost")])?;
+	let configs = [
+		("a.localhost", server_config(&first)?),
+		("b.localhost", server_config(&second)?),
+	];
+	let calls = Arc::new(AtomicUsize::new(0));
+
+	let acceptor = LazyRustlsAcceptor::new({
+		let calls = Arc::clone(&calls);
+
+		move |client_hello: ClientHelloInfo| {
+			let config = configs
+				.iter()
+				.find(|(name, _)| client_hello.server_name() == Some(name))
+				.map(|(_, config)| Arc::clone(config))
+				.ok_or_else(|| io::Error::new(ErrorKind::NotFound, "unknown server name"));
+			let _ = calls.fetch_add(1, Ordering::Relaxed);
+
+			async move {
+				// Give concurrent handshakes time to queue up.
+				time::sleep(Duration::from_millis(100)).await;
+				config
+			}
+		}
+	})
+	.cache_capacity(1);
+
+	let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+	let address = listener.local_addr()?;
+	let server = Server::from_tcp(listener)
+		.acceptor(DualProtocolAcceptor::with_tls(acceptor))
+		.handle(handle.clone());
+
+	let app = Router::new().route("/", routing::get(|| async { "test" }));
+
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		let calls = move || calls.load(Ordering::Relaxed);
+		let roots = [&first, &second];
+
+		// Concurrent handshakes share a single call to the resolver.
+		let certificates =
+			future::try_join_all((0..5).map(|_| served(address, &roots, "a.localhost"))).await?;
+		assert!(certificates
+			.iter()
+			.all(|certificate| certificate == first.cert.der()));
+		assert_eq!(calls(), 1);
+
+		// The cache is full, so this isn't cached.
+		for expected in 2..4 {
+			assert_eq!(
+				served(address, &roots, "b.localhost").await?,
+				*second.cert.der()
+			);
+			assert_eq!(calls(), expected);
+		}
+
+		assert_eq!(
+			served(address, &roots, "a.localhost").await?,
+			*first.cert.der()
+		);
+		assert_eq!(calls(), 3);
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn timeout() -> Result<()> {
+	// `ServerConfig::builder()` requires a default `CryptoProvider`.
+	let _ = aws_lc_rs::default_provider().install_default();
+
+	let handle = Handle::new();
+
+	let certificate = rcgen::generate_simple_self_signed([
+		String::from("a.localhost"),
+		String::from("slow.localhost"),
+	])?;
+	let config = server_config(&certificate)?;
+	let calls = Arc::new(AtomicUsize::new(0));
+
+	let acceptor = LazyRustlsAcceptor::new({
+		let calls = Arc::clone(&calls);
+
+		move |client_hello: ClientHelloInfo| {
+			let config = Arc::clone(&config);
+			let slow = client_hello.server_name() == Some("slow.localhost");
+			let _ = calls.fetch_add(1, Ordering::Relaxed);
+
+			async move {
+				if slow {
+					time::sleep(Duration::from_secs(10)).await;
+				}
+
+				Ok(config)
+			}
+		}
+	})
+	.cache_capacity(1)
+	.handshake_timeout(Duration::from_millis(100));
+
+	let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+	let address = listener.local_addr()?;
+	let server = Server::from_tcp(listener)
+		.acceptor(DualProtocolAcceptor::with_tls(acceptor))
+		.handle(handle.clone());
+
+	let app = Router::new().route("/", routing::get(|| async { "test" }));
+
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		let calls = move || calls.load(Ordering::Relaxed);
+		let roots = [&certificate];
+
+		// Timed out resolving.
+		let _ = served(address, &roots, "slow.localhost").await.unwrap_err();
+		assert_eq!(calls(), 1);
+
+		// The cancelled resolve doesn't occupy the cache.
+		for _ in 0..2 {
+			assert_eq!(
+				served(address, &roots, "a.localhost").await?,
+				*certificate.cert.der()
+			);
+			assert_eq!(calls(), 2);
+		}
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
@@ -0,0 +1,95 @@
+#![cfg(test)]
+#![allow(clippy::missing_assert_message)]
+
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+
+use anyhow::{Error, Result};
+use axum::{routing, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server_dual_protocol::{MultiAddressHandle, ServerExt, UpgradeHttpLayer};
+use futures_util::{future, TryFutureExt};
+use http::header::LOCATION;
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, StatusCode};
+
+#[tokio::test]
+async fn multi_address() -> Result<()> {
+	let handle = MultiAddressHandle::new();
+
+	let key_pair = rcgen::generate_simple_self_signed([String::from("localhost")])?;
+	let certificate = key_pair.cert.der().to_vec();
+	let config =
+		RustlsConfig::from_der(vec![certificate.clone()], key_pair.key_pair.serialize_der())
+			.await?;
+
+	let address = SocketAddr::from(([127, 0, 0, 1], 0));
+	let server = axum_server_dual_protocol::bind_dual_protocol_all([address, address], config)?
+		.handle(handle.clone())
+		.set_upgrade_layer(UpgradeHttpLayer::new().exempt_path_prefix("/exempt"));
+	let addresses = server.addresses().to_vec();
+	let [first, second] = addresses.as_slice() else {
+		panic!("expected two addresses")
+	};
+	assert_ne!(first, second);
+
+	let app = Router::new().fallback(routing::get(|| async { "test" }));
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		assert_eq!(handle.listening().await.unwrap(), addresses);
+
+		let client = Client::builder()
+			.add_root_certificate(Certificate::from_der(&certificate)?)
+			.danger_accept_invalid_certs(true)
+			.redirect(Policy::none())
+			.build()?;
+
+		for address in addresses {
+			let response = client.get(format!("http://{address}/path")).send().await?;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+			assert_eq!(
+				*response.headers().get(LOCATION).unwrap(),
+				format!("https://{address}/path")
+			);
+
+			let response = client
+				.get(format!("http://{address}/exempt"))
+				.send()
+				.await?;
+			assert_eq!(response.text().await?, "test");
+
+			let response = client.get(format!("https://{address}/path")).send().await?;
+			assert_eq!(response.text().await?, "test");
+		}
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn empty() -> Result<()> {
+	let key_pair = rcgen::generate_simple_self_signed([String::from("localhost")])?;
+	let config = RustlsConfig::from_der(
+		vec![key_pair.cert.der().to_vec()],
+		key_pair.key_pair.serialize_der(),
+	)
+	.await?;
+
+	let error = axum_server_dual_protocol::bind_dual_protocol_all([], config).unwrap_err();
+	assert_eq!(error.kind(), ErrorKind::InvalidInput);
+
+	Ok(())
+}
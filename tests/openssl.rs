@@ -0,0 +1,80 @@
+#![cfg(test)]
+#![cfg(feature = "openssl")]
+#![allow(clippy::missing_assert_message)]
+
+use std::net::{SocketAddr, TcpListener};
+
+use anyhow::{Error, Result};
+use axum::{routing, Router};
+use axum_server::tls_openssl::OpenSSLConfig;
+use axum_server::Handle;
+use axum_server_dual_protocol::{Protocol, ServerExt};
+use futures_util::{future, TryFutureExt};
+use http::header::LOCATION;
+use http::Extensions;
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, StatusCode};
+use rustls::crypto::aws_lc_rs;
+
+#[tokio::test]
+async fn openssl() -> Result<()> {
+	// Unlike `RustlsConfig`, `OpenSSLConfig` doesn't install the default
+	// `CryptoProvider`, which the client requires.
+	let _ = aws_lc_rs::default_provider().install_default();
+
+	let handle = Handle::new();
+
+	let key_pair = rcgen::generate_simple_self_signed([String::from("localhost")])?;
+	let certificate = key_pair.cert.der().to_vec();
+	let config = OpenSSLConfig::from_der(&certificate, &key_pair.key_pair.serialize_der())?;
+
+	let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+	let address = listener.local_addr()?;
+	let server = axum_server_dual_protocol::from_tcp_dual_protocol_openssl(listener, config)
+		.handle(handle.clone())
+		.set_upgrade(true);
+
+	let app = Router::new().route(
+		"/",
+		routing::get(|extensions: Extensions| async move {
+			match extensions.get::<Protocol>().unwrap() {
+				Protocol::Tls => "tls",
+				Protocol::Plain => "plain",
+			}
+		}),
+	);
+
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		let client = Client::builder()
+			.add_root_certificate(Certificate::from_der(&certificate)?)
+			.danger_accept_invalid_certs(true)
+			.redirect(Policy::none())
+			.build()?;
+
+		let response = client.get(format!("http://{address}")).send().await?;
+		assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+		assert_eq!(
+			*response.headers().get(LOCATION).unwrap(),
+			format!("https://{address}/")
+		);
+
+		let response = client.get(format!("https://{address}")).send().await?;
+		assert_eq!(response.text().await?, "tls");
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
@@ -0,0 +1,224 @@
+#![cfg(test)]
+#![cfg(feature = "reload")]
+#![allow(clippy::missing_assert_message)]
+
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{env, fs, process};
+
+use anyhow::{Error, Result};
+use axum::{routing, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use axum_server_dual_protocol::{CertificateReloader, ReloadError};
+use futures_util::{future, TryFutureExt};
+use rcgen::CertifiedKey;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio_rustls::TlsConnector;
+
+/// Returns an empty temporary directory for the given test.
+fn directory(test: &str) -> Result<PathBuf> {
+	let path = env::temp_dir().join(format!(
+		"axum-server-dual-protocol-reload-{test}-{}",
+		process::id()
+	));
+	let _ = fs::remove_dir_all(&path);
+	fs::create_dir_all(&path)?;
+	Ok(path)
+}
+
+/// Generates a self-signed certificate for `localhost`.
+fn generate() -> Result<CertifiedKey> {
+	Ok(rcgen::generate_simple_self_signed([String::from(
+		"localhost",
+	)])?)
+}
+
+/// Atomically replaces the file at `path` with `contents`, so the reloader
+/// can't observe a partially written file.
+fn replace<Contents: AsRef<[u8]>>(path: &Path, contents: Contents) -> Result<()> {
+	let temporary = path.with_extension("tmp");
+	fs::write(&temporary, contents)?;
+	fs::rename(temporary, path)?;
+	Ok(())
+}
+
+/// Writes the certificate and private key to `directory`.
+fn write(directory: &Path, certificate: &CertifiedKey, private_key: &CertifiedKey) -> Result<()> {
+	replace(&directory.join("certificate.pem"), certificate.cert.pem())?;
+	replace(
+		&directory.join("private-key.pem"),
+		private_key.key_pair.serialize_pem(),
+	)
+}
+
+/// Returns the certificate served at `address`.
+async fn served(address: SocketAddr, roots: &[&CertifiedKey]) -> Result<CertificateDer<'static>> {
+	let mut store = RootCertStore::empty();
+
+	for root in roots {
+		store.add(root.cert.der().clone())?;
+	}
+
+	let config = ClientConfig::builder()
+		.with_root_certificates(store)
+		.with_no_client_auth();
+	let stream = TcpStream::connect(address).await?;
+	let stream = TlsConnector::from(Arc::new(config))
+		.connect(ServerName::try_from("localhost")?, stream)
+		.await?;
+
+	let Some([certificate]) = stream.get_ref().1.peer_certificates() else {
+		panic!("expected a single certificate")
+	};
+
+	Ok(certificate.clone())
+}
+
+/// Serves `config` and runs `client` against it.
+async fn serve<Client, ClientFuture>(config: RustlsConfig, client: Client) -> Result<()>
+where
+	Client: 'static + FnOnce(SocketAddr) -> ClientFuture + Send,
+	ClientFuture: Future<Output = Result<()>> + Send,
+{
+	let handle = Handle::new();
+
+	let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))?;
+	let address = listener.local_addr()?;
+	let server =
+		axum_server_dual_protocol::from_tcp_dual_protocol(listener, config).handle(handle.clone());
+
+	let app = Router::new().route("/", routing::get(|| async { "test" }));
+
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		client(address).await?;
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
+
+/// Returns `reloader` reporting its outcomes to the returned receiver.
+fn outcomes(
+	reloader: CertificateReloader,
+) -> (
+	CertificateReloader,
+	UnboundedReceiver<Result<(), ReloadError>>,
+) {
+	let (sender, receiver) = mpsc::unbounded_channel();
+	let reloader = reloader.on_reload(move |result| {
+		let _ = sender.send(result);
+	});
+
+	(reloader, receiver)
+}
+
+#[tokio::test]
+async fn reload() -> Result<()> {
+	let directory = directory("reload")?;
+	let old = generate()?;
+	let new = generate()?;
+	write(&directory, &old, &old)?;
+
+	let (reloader, mut outcomes) = outcomes(
+		CertificateReloader::new(
+			directory.join("certificate.pem"),
+			directory.join("private-key.pem"),
+		)
+		.interval(Duration::from_millis(10)),
+	);
+	let config = reloader.rustls_config().await?;
+	drop(tokio::spawn(async move { reloader.run().await }));
+	// Initial load.
+	outcomes.recv().await.unwrap()?;
+
+	serve(config, move |address| async move {
+		assert_eq!(served(address, &[&old, &new]).await?, *old.cert.der());
+
+		// Only the certificate was replaced so far.
+		replace(&directory.join("certificate.pem"), new.cert.pem())?;
+		let outcome = outcomes.recv().await.unwrap();
+		assert!(matches!(outcome, Err(ReloadError::Invalid(_))));
+		assert_eq!(served(address, &[&old, &new]).await?, *old.cert.der());
+
+		replace(
+			&directory.join("private-key.pem"),
+			new.key_pair.serialize_pem(),
+		)?;
+		outcomes.recv().await.unwrap()?;
+		assert_eq!(served(address, &[&old, &new]).await?, *new.cert.der());
+
+		// Not a certificate.
+		replace(&directory.join("certificate.pem"), "invalid")?;
+		let outcome = outcomes.recv().await.unwrap();
+		assert!(matches!(outcome, Err(ReloadError::Invalid(_))));
+		assert_eq!(served(address, &[&old, &new]).await?, *new.cert.der());
+
+		Ok(())
+	})
+	.await
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn sighup() -> Result<()> {
+	use std::process::Command;
+
+	let directory = directory("sighup")?;
+	let old = generate()?;
+	let new = generate()?;
+	write(&directory, &old, &old)?;
+
+	// Only reload on `SIGHUP`.
+	let (reloader, mut outcomes) = outcomes(
+		CertificateReloader::new(
+			directory.join("certificate.pem"),
+			directory.join("private-key.pem"),
+		)
+		.interval(Duration::from_secs(60 * 60))
+		.sighup(true),
+	);
+	// Custom `ServerConfig` resolving certificates with the reloader.
+	let mut config = ServerConfig::builder()
+		.with_no_client_auth()
+		.with_cert_resolver(Arc::new(reloader.clone()));
+	config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+	let config = RustlsConfig::from_config(Arc::new(config));
+	drop(tokio::spawn(async move { reloader.run().await }));
+	// Initial load, the reloader is listening for `SIGHUP` afterwards.
+	outcomes.recv().await.unwrap()?;
+
+	serve(config, move |address| async move {
+		write(&directory, &new, &new)?;
+		assert_eq!(served(address, &[&old, &new]).await?, *old.cert.der());
+
+		let status = Command::new("kill")
+			.args(["-HUP", &process::id().to_string()])
+			.status()?;
+		assert!(status.success());
+		outcomes.recv().await.unwrap()?;
+		assert_eq!(served(address, &[&old, &new]).await?, *new.cert.der());
+
+		Ok(())
+	})
+	.await
+}
@@ -0,0 +1,182 @@
+#![cfg(test)]
+#![allow(clippy::missing_assert_message)]
+
+mod util;
+
+use std::convert;
+
+use anyhow::Result;
+use axum::body::Body;
+#[cfg(feature = "axum")]
+use axum::Extension;
+use axum::{middleware, routing, Router};
+use axum_server_dual_protocol::{Protocol, ProtocolRejection, RequirePlainLayer, RequireTlsLayer};
+#[cfg(feature = "axum")]
+use axum_server_dual_protocol::{RequirePlain, RequireTls};
+use http::header::{CONNECTION, UPGRADE};
+use http::Request;
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, StatusCode};
+
+#[tokio::test]
+async fn layer() -> Result<()> {
+	util::test(
+		util::server,
+		convert::identity,
+		Router::new()
+			.route(
+				"/tls",
+				routing::get(|| async { "tls" })
+					.layer(RequireTlsLayer::new().rejection(ProtocolRejection::UpgradeRequired)),
+			)
+			.route(
+				"/plain",
+				routing::get(|| async { "plain" }).layer(RequirePlainLayer::new()),
+			)
+			.route("/", routing::get(|| async { "any" })),
+		|certificate, address| async move {
+			let client = client(certificate)?;
+
+			let response = client.get(format!("http://{address}/tls")).send().await?;
+			assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+			assert!(response.headers().get(UPGRADE).is_none());
+			assert!(response.headers().get(CONNECTION).is_none());
+			assert_eq!(response.text().await?, "This resource requires HTTPS.");
+			let response = client.get(format!("https://{address}/tls")).send().await?;
+			assert_eq!(response.text().await?, "tls");
+
+			let response = client.get(format!("http://{address}/plain")).send().await?;
+			assert_eq!(response.text().await?, "plain");
+			let response = client
+				.get(format!("https://{address}/plain"))
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+			for scheme in ["http", "https"] {
+				let response = client.get(format!("{scheme}://{address}")).send().await?;
+				assert_eq!(response.text().await?, "any");
+			}
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[cfg(feature = "axum")]
+#[tokio::test]
+async fn extractor() -> Result<()> {
+	util::test(
+		util::server,
+		convert::identity,
+		Router::new()
+			.route(
+				"/",
+				routing::get(|protocol: Protocol| async move {
+					match protocol {
+						Protocol::Tls => "tls",
+						Protocol::Plain => "plain",
+					}
+				}),
+			)
+			.route(
+				"/tls",
+				routing::get(|_: RequireTls| async { "tls" })
+					.layer(Extension(ProtocolRejection::MisdirectedRequest)),
+			)
+			.route("/plain", routing::get(|_: RequirePlain| async { "plain" })),
+		|certificate, address| async move {
+			let client = client(certificate)?;
+
+			for scheme in ["http", "https"] {
+				let response = client.get(format!("{scheme}://{address}")).send().await?;
+				assert_eq!(
+					response.text().await?,
+					if scheme == "https" { "tls" } else { "plain" }
+				);
+			}
+
+			let response = client.get(format!("http://{address}/tls")).send().await?;
+			assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+			assert_eq!(response.text().await?, "This resource requires HTTPS.");
+			let response = client.get(format!("https://{address}/tls")).send().await?;
+			assert_eq!(response.text().await?, "tls");
+
+			let response = client.get(format!("http://{address}/plain")).send().await?;
+			assert_eq!(response.text().await?, "plain");
+			let response = client
+				.get(format!("https://{address}/plain"))
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[tokio::test]
+async fn missing_protocol() -> Result<()> {
+	let app = Router::new()
+		.route(
+			"/tls",
+			routing::get(|| async { "tls" }).layer(RequireTlsLayer::new()),
+		)
+		.route(
+			"/plain",
+			routing::get(|| async { "plain" }).layer(RequirePlainLayer::new()),
+		);
+	#[cfg(feature = "axum")]
+	let app = app
+		.route(
+			"/extractor/tls",
+			routing::get(|_: RequireTls| async { "tls" }),
+		)
+		.route(
+			"/extractor/plain",
+			routing::get(|_: RequirePlain| async { "plain" }),
+		);
+
+	util::test(
+		util::server,
+		convert::identity,
+		app.layer(middleware::map_request(
+			|mut request: Request<Body>| async {
+				let _ = request.extensions_mut().remove::<Protocol>();
+				request
+			},
+		)),
+		|certificate, address| async move {
+			let client = client(certificate)?;
+			let mut paths = vec!["tls", "plain"];
+
+			if cfg!(feature = "axum") {
+				paths.extend(["extractor/tls", "extractor/plain"]);
+			}
+
+			for path in paths {
+				for scheme in ["http", "https"] {
+					let response = client
+						.get(format!("{scheme}://{address}/{path}"))
+						.send()
+						.await?;
+					assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+					assert!(response.text().await?.contains("ProtocolLayer"));
+				}
+			}
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+fn client(certificate: Certificate) -> Result<Client> {
+	Ok(Client::builder()
+		.add_root_certificate(certificate)
+		.danger_accept_invalid_certs(true)
+		.redirect(Policy::none())
+		.build()?)
+}
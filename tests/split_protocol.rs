@@ -0,0 +1,143 @@
+#![cfg(test)]
+#![allow(clippy::missing_assert_message)]
+
+use std::net::{SocketAddr, TcpListener};
+
+use anyhow::{Error, Result};
+use axum::{routing, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use axum_server_dual_protocol::{Protocol, ServerExt, UpgradeHttpLayer};
+use futures_util::{future, TryFutureExt};
+use http::header::LOCATION;
+use http::Extensions;
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, StatusCode};
+
+#[tokio::test]
+async fn split_protocol() -> Result<()> {
+	let handle = Handle::new();
+
+	let key_pair = rcgen::generate_simple_self_signed([String::from("localhost")])?;
+	let certificate = key_pair.cert.der().to_vec();
+	let config =
+		RustlsConfig::from_der(vec![certificate.clone()], key_pair.key_pair.serialize_der())
+			.await?;
+
+	let address = SocketAddr::from(([127, 0, 0, 1], 0));
+	let server = axum_server_dual_protocol::from_tcp_split_protocol(
+		TcpListener::bind(address)?,
+		TcpListener::bind(address)?,
+		config,
+	)?
+	.handle(handle.clone())
+	.set_upgrade_layer(UpgradeHttpLayer::new().exempt_path_prefix("/exempt"));
+	let plain = server.plain_addr();
+	let tls = server.tls_addr();
+
+	let app = Router::new().fallback(routing::get(|extensions: Extensions| async move {
+		match extensions.get::<Protocol>().unwrap() {
+			Protocol::Tls => "tls",
+			Protocol::Plain => "plain",
+		}
+	}));
+
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		let client = Client::builder()
+			.add_root_certificate(Certificate::from_der(&certificate)?)
+			.danger_accept_invalid_certs(true)
+			.redirect(Policy::none())
+			.build()?;
+
+		let response = client.get(format!("http://{plain}/path")).send().await?;
+		assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+		assert_eq!(
+			*response.headers().get(LOCATION).unwrap(),
+			format!("https://127.0.0.1:{}/path", tls.port())
+		);
+
+		let response = client.get(format!("http://{plain}/exempt")).send().await?;
+		assert_eq!(response.text().await?, "plain");
+
+		let response = client.get(format!("https://{tls}/path")).send().await?;
+		assert_eq!(response.text().await?, "tls");
+
+		// The HTTP listener doesn't accept HTTPS.
+		let _ = client
+			.get(format!("https://{plain}/path"))
+			.send()
+			.await
+			.unwrap_err();
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn redirect_port() -> Result<()> {
+	let handle = Handle::new();
+
+	let key_pair = rcgen::generate_simple_self_signed([String::from("localhost")])?;
+	let certificate = key_pair.cert.der().to_vec();
+	let config =
+		RustlsConfig::from_der(vec![certificate.clone()], key_pair.key_pair.serialize_der())
+			.await?;
+
+	let address = SocketAddr::from(([127, 0, 0, 1], 0));
+	let server = axum_server_dual_protocol::from_tcp_split_protocol(
+		TcpListener::bind(address)?,
+		TcpListener::bind(address)?,
+		config,
+	)?
+	.handle(handle.clone())
+	.set_upgrade_layer(UpgradeHttpLayer::new().redirect_port(8443))
+	// Doesn't overwrite the configured port.
+	.set_upgrade(true);
+	let plain = server.plain_addr();
+
+	let app = Router::new().fallback(routing::get(|| async { "test" }));
+	let server = tokio::spawn(server.serve(app.into_make_service()));
+
+	let client = tokio::spawn(async move {
+		let client = Client::builder()
+			.add_root_certificate(Certificate::from_der(&certificate)?)
+			.danger_accept_invalid_certs(true)
+			.redirect(Policy::none())
+			.build()?;
+
+		let response = client.get(format!("http://{plain}/path")).send().await?;
+		assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+		assert_eq!(
+			*response.headers().get(LOCATION).unwrap(),
+			"https://127.0.0.1:8443/path"
+		);
+
+		handle.graceful_shutdown(None);
+
+		Result::<_, Error>::Ok(())
+	});
+
+	future::try_join(
+		server
+			.map_err(Error::from)
+			.and_then(|result| future::ready(result.map_err(Error::from))),
+		client.map_err(Error::from).and_then(future::ready),
+	)
+	.await?;
+
+	Ok(())
+}
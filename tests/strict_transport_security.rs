@@ -0,0 +1,106 @@
+#![cfg(test)]
+#![allow(clippy::missing_assert_message)]
+
+mod util;
+
+use std::convert;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::{routing, Router};
+use axum_server_dual_protocol::{ServerExt, StrictTransportSecurityLayer};
+use http::header::{CONTENT_SECURITY_POLICY, STRICT_TRANSPORT_SECURITY};
+use reqwest::redirect::Policy;
+use reqwest::{Certificate, Client, StatusCode};
+
+fn layer() -> StrictTransportSecurityLayer {
+	StrictTransportSecurityLayer::new(Duration::from_secs(31_536_000))
+		.include_subdomains(true)
+		.preload(true)
+		.upgrade_insecure_requests(true)
+}
+
+#[tokio::test]
+async fn router() -> Result<()> {
+	util::test(
+		util::server,
+		convert::identity,
+		Router::new()
+			.route(
+				"/",
+				routing::get(|| async { ([(STRICT_TRANSPORT_SECURITY, "max-age=1")], "test") }),
+			)
+			.layer(layer()),
+		|certificate, address| async move {
+			let client = client(certificate)?;
+
+			// HTTP must never receive the header, even if set by the app.
+			let response = client.get(format!("http://{address}")).send().await?;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert!(response.headers().get(STRICT_TRANSPORT_SECURITY).is_none());
+			assert!(response.headers().get(CONTENT_SECURITY_POLICY).is_none());
+
+			// HTTPS keeps the header set by the app.
+			let response = client.get(format!("https://{address}")).send().await?;
+			assert_eq!(
+				*response.headers().get(STRICT_TRANSPORT_SECURITY).unwrap(),
+				"max-age=1"
+			);
+			assert_eq!(
+				*response.headers().get(CONTENT_SECURITY_POLICY).unwrap(),
+				"upgrade-insecure-requests"
+			);
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[tokio::test]
+async fn server() -> Result<()> {
+	util::test(
+		util::server,
+		|server| {
+			server
+				.set_upgrade(true)
+				.set_strict_transport_security(layer())
+		},
+		Router::new().route("/", routing::get(|| async { "test" })),
+		test,
+	)
+	.await
+}
+
+async fn test(certificate: Certificate, address: SocketAddr) -> Result<()> {
+	let client = client(certificate)?;
+
+	// HTTP.
+	let response = client.get(format!("http://{address}")).send().await?;
+	assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+	assert!(response.headers().get(STRICT_TRANSPORT_SECURITY).is_none());
+	assert!(response.headers().get(CONTENT_SECURITY_POLICY).is_none());
+
+	// HTTPS.
+	let response = client.get(format!("https://{address}")).send().await?;
+	assert_eq!(
+		*response.headers().get(STRICT_TRANSPORT_SECURITY).unwrap(),
+		"max-age=31536000; includeSubDomains; preload"
+	);
+	assert_eq!(
+		*response.headers().get(CONTENT_SECURITY_POLICY).unwrap(),
+		"upgrade-insecure-requests"
+	);
+	assert_eq!(response.text().await?, "test");
+
+	Ok(())
+}
+
+fn client(certificate: Certificate) -> Result<Client> {
+	Ok(Client::builder()
+		.add_root_certificate(certificate)
+		.danger_accept_invalid_certs(true)
+		.redirect(Policy::none())
+		.build()?)
+}
@@ -0,0 +1,209 @@
+#![cfg(test)]
+#![cfg(unix)]
+#![allow(clippy::missing_assert_message)]
+
+use std::net::{TcpListener, TcpStream, UdpSocket};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::{env, io};
+
+use axum_server_dual_protocol::ActivationError;
+
+/// Environment variable selecting the case [`child()`] runs.
+const CASE: &str = "SYSTEMD_TEST_CASE";
+
+/// Environment variable holding the expected error message.
+const EXPECTED: &str = "SYSTEMD_TEST_EXPECTED";
+
+/// Environment variable holding the expected address of the listener.
+const ADDRESS: &str = "SYSTEMD_TEST_ADDRESS";
+
+/// Runs `case` of [`child()`] in a new process, started like systemd does:
+/// `sockets` are passed as file descriptors starting at 3 and a `LISTEN_PID` of
+/// `self` is replaced by the ID of the new process.
+fn run(case: &str, variables: &[(&str, &str)], sockets: &[RawFd]) {
+	// Move the sockets out of the way of their target file descriptors first.
+	let sockets: Vec<OwnedFd> = sockets
+		.iter()
+		.map(|&socket| {
+			// SAFETY: Duplicates an open file descriptor, the new one is owned.
+			#[allow(unsafe_code)]
+			unsafe {
+				let fd = libc::fcntl(socket, libc::F_DUPFD_CLOEXEC, 10);
+				assert_ne!(fd, -1);
+				OwnedFd::from_raw_fd(fd)
+			}
+		})
+		.collect();
+	let fds: Vec<RawFd> = sockets.iter().map(AsRawFd::as_raw_fd).collect();
+
+	let mut command = Command::new("sh");
+	let _ = command
+		.arg("-c")
+		.arg("if [ \"$LISTEN_PID\" = self ]; then export LISTEN_PID=$$; fi; exec \"$0\" \"$@\"")
+		.arg(env::current_exe().unwrap())
+		.args(["--exact", "child", "--ignored", "--nocapture"])
+		.env_remove("LISTEN_PID")
+		.env_remove("LISTEN_FDS")
+		.env_remove("LISTEN_FDNAMES")
+		.env(CASE, case)
+		.envs(variables.iter().copied());
+
+	// SAFETY: Only calls `dup2()`, which is async-signal-safe, and doesn't
+	// allocate.
+	#[allow(unsafe_code)]
+	unsafe {
+		let _ = command.pre_exec(move || {
+			for (target, &fd) in (3..).zip(&fds) {
+				// Unlike the source, the duplicate is inherited.
+				if libc::dup2(fd, target) == -1 {
+					return Err(io::Error::last_os_error());
+				}
+			}
+
+			Ok(())
+		});
+	}
+
+	let output = command.output().unwrap();
+	drop(sockets);
+
+	assert!(
+		output.status.success(),
+		"case `{case}` failed:\n{}{}",
+		String::from_utf8_lossy(&output.stdout),
+		String::from_utf8_lossy(&output.stderr),
+	);
+}
+
+// Sockets are passed through the environment and file descriptors, which are
+// process-wide, so every case runs in its own process.
+#[test]
+fn activation() {
+	run("not-activated", &[], &[]);
+
+	for (listen_pid, listen_fds, listen_fdnames, expected) in [
+		("0", "1", None, "sockets were passed to process 0"),
+		("self", "0", None, "no sockets were passed"),
+		(
+			"self",
+			"test",
+			None,
+			"invalid value for `LISTEN_FDS`: \"test\"",
+		),
+		("self", "-1", None, "invalid value for `LISTEN_FDS`: \"-1\""),
+		(
+			"test",
+			"1",
+			None,
+			"invalid value for `LISTEN_PID`: \"test\"",
+		),
+		(
+			"self",
+			"2",
+			Some("http"),
+			"invalid value for `LISTEN_FDNAMES`: \"http\"",
+		),
+	] {
+		let mut variables = vec![
+			("LISTEN_PID", listen_pid),
+			("LISTEN_FDS", listen_fds),
+			(EXPECTED, expected),
+		];
+
+		if let Some(listen_fdnames) = listen_fdnames {
+			variables.push(("LISTEN_FDNAMES", listen_fdnames));
+		}
+
+		run("invalid", &variables, &[]);
+	}
+
+	let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+	let address = tcp.local_addr().unwrap().to_string();
+	let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+	run(
+		"not-tcp",
+		&[
+			("LISTEN_PID", "self"),
+			("LISTEN_FDS", "2"),
+			("LISTEN_FDNAMES", "http:udp"),
+		],
+		&[tcp.as_raw_fd(), udp.as_raw_fd()],
+	);
+
+	run(
+		"listeners",
+		&[
+			("LISTEN_PID", "self"),
+			("LISTEN_FDS", "1"),
+			("LISTEN_FDNAMES", "http"),
+			(ADDRESS, &address),
+		],
+		&[tcp.as_raw_fd()],
+	);
+}
+
+/// Runs a single case of [`activation()`] in the process started by [`run()`].
+#[test]
+#[ignore = "run in its own process by `activation()`"]
+fn child() {
+	let Ok(case) = env::var(CASE) else {
+		return;
+	};
+
+	match case.as_str() {
+		"not-activated" => {
+			let error = axum_server_dual_protocol::systemd_listeners().unwrap_err();
+			assert!(matches!(error, ActivationError::NotActivated));
+		}
+		"invalid" => {
+			let variables: Vec<_> = env::vars_os().collect();
+
+			let error = axum_server_dual_protocol::systemd_listeners().unwrap_err();
+			assert!(
+				error.to_string().starts_with(&env::var(EXPECTED).unwrap()),
+				"{error}"
+			);
+
+			// The environment is never modified.
+			assert_eq!(env::vars_os().collect::<Vec<_>>(), variables);
+		}
+		"not-tcp" => {
+			let error = axum_server_dual_protocol::systemd_listeners().unwrap_err();
+			assert!(
+				matches!(error, ActivationError::NotTcpListener { fd: 4, .. }),
+				"{error}"
+			);
+
+			// The valid socket was left open.
+			// SAFETY: Only queries the flags of a file descriptor.
+			#[allow(unsafe_code)]
+			let flags = unsafe { libc::fcntl(3, libc::F_GETFD) };
+			assert_ne!(flags, -1);
+		}
+		"listeners" => {
+			let mut listeners = axum_server_dual_protocol::systemd_listeners().unwrap();
+			assert_eq!(listeners.len(), 1);
+			let (name, listener) = listeners.pop().unwrap();
+			assert_eq!(name, "http");
+			assert_eq!(listener.as_raw_fd(), 3);
+			let address = listener.local_addr().unwrap();
+			assert_eq!(address.to_string(), env::var(ADDRESS).unwrap());
+
+			// SAFETY: Only queries the flags of an open file descriptor.
+			#[allow(unsafe_code)]
+			let flags = unsafe { libc::fcntl(3, libc::F_GETFD) };
+			assert_eq!(flags & libc::FD_CLOEXEC, libc::FD_CLOEXEC);
+
+			let _stream = TcpStream::connect(address).unwrap();
+			let _ = listener.accept().unwrap();
+
+			// Sockets are only taken once.
+			let error = axum_server_dual_protocol::systemd_listeners().unwrap_err();
+			assert!(matches!(error, ActivationError::NotActivated));
+		}
+		_ => panic!("unknown case `{case}`"),
+	}
+}
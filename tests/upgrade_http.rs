@@ -5,13 +5,25 @@ mod util;
 
 use std::convert;
 use std::net::SocketAddr;
+use std::time::Duration;
 
 use anyhow::Result;
-use axum::{routing, Router};
-use axum_server_dual_protocol::{ServerExt, UpgradeHttpLayer};
-use http::header::LOCATION;
+use axum::body::Body;
+use axum::{middleware, routing, Extension, Router};
+use axum_server_dual_protocol::{
+	CanonicalHost, HostFallback, PreflightPolicy, Protocol, RedirectFailure, RedirectLoopPolicy,
+	ServerExt, UpgradeHttpLayer, WebSocketPolicy,
+};
+use http::header::{
+	ACCESS_CONTROL_REQUEST_METHOD, CACHE_CONTROL, CONNECTION, CONTENT_TYPE, HOST, LOCATION, ORIGIN,
+	UPGRADE, UPGRADE_INSECURE_REQUESTS, VARY,
+};
+use http::uri::Authority;
+use http::{Extensions, HeaderValue, Method, Request};
 use reqwest::redirect::Policy;
-use reqwest::{Certificate, Client, StatusCode};
+use reqwest::{Certificate, Client, Response, StatusCode};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
 
 #[tokio::test]
 async fn router() -> Result<()> {
@@ -20,7 +32,7 @@ async fn router() -> Result<()> {
 		convert::identity,
 		Router::new()
 			.route("/", routing::get(|| async { "test" }))
-			.layer(UpgradeHttpLayer),
+			.layer(UpgradeHttpLayer::new()),
 		test,
 	)
 	.await
@@ -51,6 +63,780 @@ async fn server() -> Result<()> {
 	.await
 }
 
+#[tokio::test]
+async fn exemptions() -> Result<()> {
+	let protocol = || {
+		routing::any(|extensions: Extensions| async move {
+			match extensions.get::<Protocol>().unwrap() {
+				Protocol::Tls => "secure",
+				Protocol::Plain => "insecure",
+			}
+		})
+	};
+
+	util::test(
+		util::server,
+		|server| {
+			server.set_upgrade_layer(
+				UpgradeHttpLayer::new()
+					.exempt_path_prefix("/.well-known/acme-challenge/")
+					.exempt_method(Method::PUT)
+					.exempt_if(|parts| parts.headers.contains_key("x-health-check")),
+			)
+		},
+		Router::new()
+			.route("/", protocol())
+			.route("/.well-known/acme-challenge/:token", protocol()),
+		|certificate, address| async move {
+			let client = Client::builder()
+				.add_root_certificate(certificate)
+				.danger_accept_invalid_certs(true)
+				.redirect(Policy::none())
+				.build()?;
+
+			// Path prefix.
+			let response = client
+				.get(format!("http://{address}/.well-known/acme-challenge/test"))
+				.send()
+				.await?;
+			assert_eq!(response.text().await?, "insecure");
+
+			// Method.
+			let response = client.put(format!("http://{address}")).send().await?;
+			assert_eq!(response.text().await?, "insecure");
+
+			// Predicate.
+			let response = client
+				.get(format!("http://{address}"))
+				.header("x-health-check", "1")
+				.send()
+				.await?;
+			assert_eq!(response.text().await?, "insecure");
+
+			// Not exempted.
+			let response = client.get(format!("http://{address}")).send().await?;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+			let response = client
+				.get(format!("http://{address}/.well-known/test"))
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+			// HTTPS.
+			let response = client
+				.get(format!("https://{address}/.well-known/acme-challenge/test"))
+				.send()
+				.await?;
+			assert_eq!(response.text().await?, "secure");
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[tokio::test]
+async fn template() -> Result<()> {
+	util::test(
+		util::server,
+		|server| {
+			server.set_upgrade_layer(
+				UpgradeHttpLayer::new()
+					.html_body("<a href=\"{location}\">{location}</a>")
+					.cache_control(HeaderValue::from_static("max-age=3600"))
+					.header(
+						"x-test".try_into().unwrap(),
+						HeaderValue::from_static("test"),
+					),
+			)
+		},
+		Router::new().route("/", routing::get(|| async { "test" })),
+		|certificate, address| async move {
+			let client = Client::builder()
+				.add_root_certificate(certificate)
+				.danger_accept_invalid_certs(true)
+				.redirect(Policy::none())
+				.build()?;
+
+			let response = client
+				.get(format!("http://{address}/test?a=b&c=d"))
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+			assert_eq!(
+				*response.headers().get(LOCATION).unwrap(),
+				format!("https://{address}/test?a=b&c=d")
+			);
+			assert_eq!(
+				*response.headers().get(CONTENT_TYPE).unwrap(),
+				"text/html; charset=utf-8"
+			);
+			assert_eq!(
+				*response.headers().get(CACHE_CONTROL).unwrap(),
+				"max-age=3600"
+			);
+			assert_eq!(*response.headers().get("x-test").unwrap(), "test");
+			assert_eq!(
+				response.text().await?,
+				format!(
+					"<a href=\"https://{address}/test?a=b&amp;c=d\">https://{address}/test?a=b&amp;c=d</a>"
+				)
+			);
+
+			// HTTPS responses are not affected.
+			let response = client.get(format!("https://{address}")).send().await?;
+			assert!(response.headers().get(CACHE_CONTROL).is_none());
+			assert_eq!(response.text().await?, "test");
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[tokio::test]
+async fn upgrade_insecure_requests() -> Result<()> {
+	util::test(
+		util::server,
+		|server| {
+			server.set_upgrade_layer(
+				UpgradeHttpLayer::new()
+					.only_upgrade_insecure_requests(true)
+					.websocket(WebSocketPolicy::Reject)
+					.preflight(PreflightPolicy::reject()),
+			)
+		},
+		Router::new().route(
+			"/",
+			routing::get(|| async { "test" }).options(|| async { "preflight" }),
+		),
+		|certificate, address| async move {
+			let client = Client::builder()
+				.add_root_certificate(certificate)
+				.danger_accept_invalid_certs(true)
+				.redirect(Policy::none())
+				.build()?;
+
+			// Browser preferring HTTPS.
+			let response = client
+				.get(format!("http://{address}"))
+				.header(UPGRADE_INSECURE_REQUESTS, "1")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+			assert_eq!(
+				*response.headers().get(LOCATION).unwrap(),
+				format!("https://{address}/")
+			);
+			assert_eq!(
+				*response.headers().get(VARY).unwrap(),
+				"upgrade-insecure-requests"
+			);
+
+			// Other clients.
+			let response = client.get(format!("http://{address}")).send().await?;
+			assert_eq!(response.status(), StatusCode::OK);
+			assert_eq!(
+				*response.headers().get(VARY).unwrap(),
+				"upgrade-insecure-requests"
+			);
+			assert_eq!(response.text().await?, "test");
+
+			// WebSocket handshakes don't send the header, so they aren't rejected.
+			let response = client
+				.get(format!("http://{address}"))
+				.header(CONNECTION, "upgrade")
+				.header(UPGRADE, "websocket")
+				.send()
+				.await?;
+			assert_eq!(
+				*response.headers().get(VARY).unwrap(),
+				"upgrade-insecure-requests"
+			);
+			assert_eq!(response.text().await?, "test");
+
+			// Neither do CORS preflight requests.
+			let response = client
+				.request(Method::OPTIONS, format!("http://{address}"))
+				.header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+				.header(ORIGIN, "http://example.com")
+				.send()
+				.await?;
+			assert_eq!(
+				*response.headers().get(VARY).unwrap(),
+				"upgrade-insecure-requests"
+			);
+			assert_eq!(response.text().await?, "preflight");
+
+			// Other responses depending on the header.
+			let response = client
+				.request(Method::OPTIONS, format!("http://{address}"))
+				.header(UPGRADE_INSECURE_REQUESTS, "1")
+				.header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+				.header(ORIGIN, "http://example.com")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::FORBIDDEN);
+			assert_eq!(
+				*response.headers().get(VARY).unwrap(),
+				"upgrade-insecure-requests"
+			);
+
+			let response = client
+				.get(format!("http://{address}"))
+				.header(UPGRADE_INSECURE_REQUESTS, "1")
+				.header("x-forwarded-host", "invalid host")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+			assert_eq!(
+				*response.headers().get(VARY).unwrap(),
+				"upgrade-insecure-requests"
+			);
+
+			// HTTPS.
+			let response = client
+				.get(format!("https://{address}"))
+				.header(UPGRADE_INSECURE_REQUESTS, "1")
+				.send()
+				.await?;
+			assert!(response.headers().get(VARY).is_none());
+			assert_eq!(response.text().await?, "test");
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[tokio::test]
+async fn websocket() -> Result<()> {
+	for policy in [
+		WebSocketPolicy::Redirect,
+		WebSocketPolicy::PassThrough,
+		WebSocketPolicy::Reject,
+	] {
+		util::test(
+			util::server,
+			move |server| server.set_upgrade_layer(UpgradeHttpLayer::new().websocket(policy)),
+			Router::new().route("/", routing::get(|| async { "test" })),
+			move |certificate, address| async move {
+				let client = Client::builder()
+					.add_root_certificate(certificate)
+					.danger_accept_invalid_certs(true)
+					.redirect(Policy::none())
+					.build()?;
+
+				let response = client
+					.get(format!("http://{address}"))
+					.header(CONNECTION, "keep-alive, Upgrade")
+					.header(UPGRADE, "WebSocket")
+					.send()
+					.await?;
+
+				match policy {
+					WebSocketPolicy::Redirect => {
+						assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+						assert_eq!(
+							*response.headers().get(LOCATION).unwrap(),
+							format!("wss://{address}/")
+						);
+					}
+					WebSocketPolicy::PassThrough => {
+						assert_eq!(response.text().await?, "test");
+					}
+					WebSocketPolicy::Reject => {
+						assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+						assert_eq!(
+							response.text().await?,
+							format!(
+								"WebSocket connections require TLS, connect to \
+								 \"wss://{address}/\" instead."
+							)
+						);
+					}
+				}
+
+				// `Upgrade` without `Connection: upgrade` is not a WebSocket handshake.
+				let response = client
+					.get(format!("http://{address}"))
+					.header(UPGRADE, "websocket")
+					.send()
+					.await?;
+				assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+				assert_eq!(
+					*response.headers().get(LOCATION).unwrap(),
+					format!("https://{address}/")
+				);
+
+				Ok(())
+			},
+		)
+		.await?;
+	}
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn missing_protocol() -> Result<()> {
+	for default in [None, Some(Protocol::Plain), Some(Protocol::Tls)] {
+		let mut layer = UpgradeHttpLayer::new();
+
+		if let Some(default) = default {
+			layer = layer.default_protocol(default);
+		}
+
+		util::test(
+			util::server,
+			convert::identity,
+			Router::new()
+				.route("/", routing::get(|| async { "test" }))
+				.layer(layer)
+				.layer(middleware::map_request(
+					|mut request: Request<Body>| async {
+						let _ = request.extensions_mut().remove::<Protocol>();
+						request
+					},
+				)),
+			move |certificate, address| async move {
+				let client = Client::builder()
+					.add_root_certificate(certificate)
+					.danger_accept_invalid_certs(true)
+					.redirect(Policy::none())
+					.build()?;
+
+				for scheme in ["http", "https"] {
+					let response = client.get(format!("{scheme}://{address}")).send().await?;
+
+					match default {
+						None => {
+							assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+							assert!(response.text().await?.contains("default_protocol()"));
+						}
+						Some(Protocol::Plain) => {
+							assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+						}
+						Some(Protocol::Tls) => assert_eq!(response.text().await?, "test"),
+					}
+				}
+
+				Ok(())
+			},
+		)
+		.await?;
+	}
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn host_fallback() -> Result<()> {
+	for fallback in [
+		None,
+		Some(HostFallback::Authority(Authority::from_static(
+			"example.com",
+		))),
+		Some(HostFallback::LocalAddr),
+	] {
+		let mut layer = UpgradeHttpLayer::new();
+
+		if let Some(fallback) = fallback.clone() {
+			layer = layer.host_fallback(fallback);
+		}
+
+		util::test(
+			util::server,
+			|server| server.set_upgrade_layer(layer),
+			Router::new().route("/", routing::get(|| async { "test" })),
+			move |_, address| async move {
+				// HTTP/1.0 doesn't require a `Host` header.
+				let mut stream = TcpStream::connect(address).await?;
+				stream
+					.write_all(b"GET /test?query HTTP/1.0\r\n\r\n")
+					.await?;
+				let mut response = String::new();
+				let _ = stream.read_to_string(&mut response).await?;
+				let response = response.to_ascii_lowercase();
+
+				match fallback {
+					None => assert!(response.starts_with("http/1.0 400")),
+					Some(HostFallback::Authority(_)) => {
+						assert!(response.starts_with("http/1.0 301"));
+						assert!(response.contains("location: https://example.com/test?query\r\n"));
+					}
+					Some(HostFallback::LocalAddr) => {
+						assert!(response.starts_with("http/1.0 301"));
+						assert!(response
+							.contains(&format!("location: https://{address}/test?query\r\n")));
+					}
+				}
+
+				Ok(())
+			},
+		)
+		.await?;
+	}
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn fallback() -> Result<()> {
+	util::test(
+		util::server,
+		convert::identity,
+		Router::new()
+			.route("/", routing::get(|| async { "test" }))
+			.layer(UpgradeHttpLayer::new().fallback(Router::new().fallback(
+				|Extension(failure): Extension<RedirectFailure>| async move {
+					(StatusCode::MISDIRECTED_REQUEST, failure.to_string())
+				},
+			))),
+		|certificate, address| async move {
+			let client = Client::builder()
+				.add_root_certificate(certificate)
+				.danger_accept_invalid_certs(true)
+				.redirect(Policy::none())
+				.build()?;
+
+			// Invalid host.
+			let response = client
+				.get(format!("http://{address}"))
+				.header("x-forwarded-host", "invalid host")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+			assert_eq!(
+				response.text().await?,
+				RedirectFailure::InvalidHost.to_string()
+			);
+
+			// Missing host.
+			let mut stream = TcpStream::connect(address).await?;
+			stream.write_all(b"GET / HTTP/1.0\r\n\r\n").await?;
+			let mut response = String::new();
+			let _ = stream.read_to_string(&mut response).await?;
+			assert!(response.starts_with("HTTP/1.0 421"));
+			assert!(response.ends_with(&RedirectFailure::MissingHost.to_string()));
+
+			// HTTPS isn't affected.
+			let response = client
+				.get(format!("https://{address}"))
+				.header("x-forwarded-host", "invalid host")
+				.send()
+				.await?;
+			assert_eq!(response.text().await?, "test");
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[tokio::test]
+async fn server_fallback() -> Result<()> {
+	util::test_serve(
+		util::server,
+		convert::identity,
+		|server| {
+			server
+				.set_upgrade_layer(UpgradeHttpLayer::new().fallback(Router::new().fallback(
+					|Extension(failure): Extension<RedirectFailure>| async move {
+						(StatusCode::MISDIRECTED_REQUEST, failure.to_string())
+					},
+				)))
+				.serve(
+					Router::new()
+						.route("/", routing::get(|| async { "test" }))
+						.into_make_service(),
+				)
+		},
+		|certificate, address| async move {
+			let client = Client::builder()
+				.add_root_certificate(certificate)
+				.danger_accept_invalid_certs(true)
+				.redirect(Policy::none())
+				.build()?;
+
+			// Invalid host.
+			let response = client
+				.get(format!("http://{address}"))
+				.header("x-forwarded-host", "invalid host")
+				.send()
+				.await?;
+			assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
+			assert_eq!(
+				response.text().await?,
+				RedirectFailure::InvalidHost.to_string()
+			);
+
+			// Valid requests are still redirected.
+			let response = client.get(format!("http://{address}")).send().await?;
+			assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+			// HTTPS isn't affected.
+			let response = client.get(format!("https://{address}")).send().await?;
+			assert_eq!(response.text().await?, "test");
+
+			Ok(())
+		},
+	)
+	.await
+}
+
+#[tokio::test]
+async fn canonical_host() -> Result<()> {
+	for canonicalize_tls in [false, true] {
+		util::test(
+			util::server,
+			convert::identity,
+			Router::new()
+				.route("/", routing::get(|| async { "test" }))
+				.layer(
+					UpgradeHttpLayer::new()
+						.canonical_host(CanonicalHost::Primary {
+							primary: Authority::from_static("example.com"),
+							aliases: vec![
+								Authority::from_static("www.example.com"),
+								Authority::from_static("example.org"),
+							],
+						})
+						.canonicalize_tls(canonicalize_tls),
+				),
+			move |certificate, address| async move {
+				let client = Client::builder()
+					.add_root_certificate(certificate)
+					.danger_accept_invalid_certs(true)
+					.redirect(Policy::none())
+					.build()?;
+				let port = address.port();
+
+				for (host, canonical) in [
+					("example.com", "example.com"),
+					("WWW.example.com", "example.com"),
+					("example.org", "example.com"),
+					("example.net", "example.net"),
+				] {
+					let response = client
+						.get(format!("http://{address}/x"))
+						.header(HOST, format!("{host}:{port}"))
+						.send()
+						.await?;
+					assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+					assert_eq!(
+						*response.headers().get(LOCATION).unwrap(),
+						format!("https://{canonical}:{port}/x")
+					);
+
+					let response = client
+						.get(format!("https://{address}/x"))
+						.header(HOST, format!("{host}:{port}"))
+						.send()
+						.await?;
+
+					if canonicalize_tls && host != canonical {
+						assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+						assert_eq!(
+							*response.headers().get(LOCATION).unwrap(),
+							format!("https://{canonical}:{port}/x")
+						);
+					} else {
+						assert_eq!(response.status(), StatusCode::NOT_FOUND);
+					}
+				}
+
+				// Without a `Host` header, e.g. in HTTP/2, the port of the URI is kept.
+				let mut stream = TcpStream::connect(address).await?;
+				stream
+					.write_all(
+						format!("GET http://www.example.com:{port}/x HTTP/1.0\r\n\r\n").as_bytes(),
+					)
+					.await?;
+				let mut response = String::new();
+				let _ = stream.read_to_string(&mut response).await?;
+				let response = response.to_ascii_lowercase();
+				assert!(response.starts_with("http/1.0 301"), "{response}");
+				assert!(
+					response.contains(&format!("location: https://example.com:{port}/x\r\n")),
+					"{response}"
+				);
+
+				Ok(())
+			},
+		)
+		.await?;
+	}
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn preflight() -> Result<()> {
+	for policy in [
+		PreflightPolicy::Redirect,
+		PreflightPolicy::PassThrough,
+		PreflightPolicy::reject(),
+	] {
+		util::test(
+			util::server,
+			convert::identity,
+			Router::new()
+				.route(
+					"/",
+					routing::get(|| async { "test" }).options(|| async { "preflight" }),
+				)
+				.layer(UpgradeHttpLayer::new().preflight(policy.clone())),
+			move |certificate, address| async move {
+				let client = Client::builder()
+					.add_root_certificate(certificate)
+					.danger_accept_invalid_certs(true)
+					.redirect(Policy::none())
+					.build()?;
+
+				let response = client
+					.request(Method::OPTIONS, format!("http://{address}"))
+					.header(ACCESS_CONTROL_REQUEST_METHOD, "POST")
+					.header(ORIGIN, "http://example.com")
+					.send()
+					.await?;
+
+				match policy {
+					PreflightPolicy::Redirect => {
+						assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+					}
+					PreflightPolicy::PassThrough => {
+						assert_eq!(response.text().await?, "preflight");
+					}
+					PreflightPolicy::Respond { .. } => {
+						assert_eq!(response.status(), StatusCode::FORBIDDEN);
+						assert_eq!(
+							response.text().await?,
+							format!(
+								"CORS requests require HTTPS, send them to \"https://{address}/\" \
+								 instead."
+							)
+						);
+					}
+				}
+
+				// Plain `OPTIONS` requests are still redirected.
+				let response = client
+					.request(Method::OPTIONS, format!("http://{address}"))
+					.send()
+					.await?;
+				assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+				Ok(())
+			},
+		)
+		.await?;
+	}
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn redirect_loop() -> Result<()> {
+	for policy in [RedirectLoopPolicy::Serve, RedirectLoopPolicy::Reject] {
+		util::test(
+			util::server,
+			move |server| {
+				server.set_upgrade_layer(
+					UpgradeHttpLayer::new()
+						.redirect_loop(policy)
+						.redirect_loop_limit(2, Duration::from_secs(60)),
+				)
+			},
+			Router::new().route("/", routing::get(|| async { "test" })),
+			move |certificate, address| async move {
+				let client = Client::builder()
+					.add_root_certificate(certificate)
+					.danger_accept_invalid_certs(true)
+					.redirect(Policy::none())
+					.build()?;
+
+				let assert_loop = |response: Response| async move {
+					match policy {
+						RedirectLoopPolicy::Redirect => unreachable!(),
+						RedirectLoopPolicy::Serve => assert_eq!(response.text().await?, "test"),
+						RedirectLoopPolicy::Reject => {
+							assert_eq!(response.status(), StatusCode::LOOP_DETECTED);
+						}
+					}
+
+					anyhow::Ok(())
+				};
+
+				for (name, value) in [
+					("x-forwarded-proto", "https"),
+					("forwarded", "for=192.0.2.60;proto=https"),
+				] {
+					let response = client
+						.get(format!("http://{address}"))
+						.header(name, value)
+						.send()
+						.await?;
+					assert_loop(response).await?;
+				}
+
+				// Repeated redirects.
+				for _ in 0..2 {
+					let response = client.get(format!("http://{address}")).send().await?;
+					assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+				}
+
+				let response = client.get(format!("http://{address}")).send().await?;
+				assert_loop(response).await?;
+
+				// Other URIs are tracked separately.
+				let response = client.get(format!("http://{address}/other")).send().await?;
+				assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+
+				Ok(())
+			},
+		)
+		.await?;
+	}
+
+	Ok(())
+}
+
+#[tokio::test]
+async fn redirect_port() -> Result<()> {
+	for (port, location) in [
+		(443, "https://localhost/x"),
+		(8443, "https://localhost:8443/x"),
+	] {
+		util::test(
+			util::server,
+			move |server| server.set_upgrade_layer(UpgradeHttpLayer::new().redirect_port(port)),
+			Router::new().route("/x", routing::get(|| async { "test" })),
+			move |certificate, address| async move {
+				let client = Client::builder()
+					.add_root_certificate(certificate)
+					.danger_accept_invalid_certs(true)
+					.redirect(Policy::none())
+					.build()?;
+
+				let response = client
+					.get(format!("http://{address}/x"))
+					.header(HOST, format!("localhost:{}", address.port()))
+					.send()
+					.await?;
+				assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+				assert_eq!(*response.headers().get(LOCATION).unwrap(), location);
+
+				Ok(())
+			},
+		)
+		.await?;
+	}
+
+	Ok(())
+}
+
 async fn test(certificate: Certificate, address: SocketAddr) -> Result<()> {
 	let client = Client::builder()
 		.add_root_certificate(certificate)
@@ -1,5 +1,6 @@
 use std::error::Error as StdError;
 use std::future::Future;
+use std::io;
 use std::net::SocketAddr;
 
 use anyhow::{Error, Result};
@@ -43,6 +44,37 @@ where
 		'static + FnOnce(Server<DualProtocolAcceptor>) -> Server<DualProtocolAcceptor> + Send,
 	ClientFn: 'static + FnOnce(Certificate, SocketAddr) -> ClientFuture + Send,
 	ClientFuture: Future<Output = Result<()>> + Send,
+{
+	test_serve(
+		server,
+		server_logic,
+		|server| server.serve(app.into_make_service()),
+		client_logic,
+	)
+	.await
+}
+
+pub(crate) async fn test_serve<
+	ServerFn,
+	ServerLogicFn,
+	ServeFn,
+	ServeFuture,
+	ClientFn,
+	ClientFuture,
+>(
+	server: ServerFn,
+	server_logic: ServerLogicFn,
+	serve: ServeFn,
+	client_logic: ClientFn,
+) -> Result<()>
+where
+	ServerFn: 'static + FnOnce(SocketAddr, RustlsConfig) -> Server<DualProtocolAcceptor> + Send,
+	ServerLogicFn:
+		'static + FnOnce(Server<DualProtocolAcceptor>) -> Server<DualProtocolAcceptor> + Send,
+	ServeFn: 'static + FnOnce(Server<DualProtocolAcceptor>) -> ServeFuture + Send,
+	ServeFuture: Future<Output = io::Result<()>> + Send,
+	ClientFn: 'static + FnOnce(Certificate, SocketAddr) -> ClientFuture + Send,
+	ClientFuture: Future<Output = Result<()>> + Send,
 {
 	let handle = Handle::new();
 
@@ -63,7 +95,7 @@ where
 
 			server = server_logic(server);
 
-			server.serve(app.into_make_service()).await?;
+			serve(server).await?;
 
 			Result::<_, Error>::Ok(())
 		}